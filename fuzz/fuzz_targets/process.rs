@@ -0,0 +1,33 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use transactions::{
+    input,
+    output::{write_json, write_output},
+    Diagnostics, InputOptions, Options, OutputOptions
+};
+
+// Processing arbitrary bytes may fail, but must never panic, and neither
+// may writing whatever it produces.
+fuzz_target!(|data: &[u8]| {
+    let options = Options {
+        check_conservation: true,
+        ..Options::default()
+    };
+    let clients = input::process_csv(
+        data,
+        &options,
+        &InputOptions::default(),
+        &mut Diagnostics::silent()
+    );
+
+    if let Ok(clients) = clients {
+        let output = OutputOptions {
+            totals_row: true,
+            ..OutputOptions::default()
+        };
+
+        let _ = write_output(&mut Vec::new(), &clients, &output);
+        let _ = write_json(&mut Vec::new(), &clients, &output);
+    }
+});
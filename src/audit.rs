@@ -0,0 +1,421 @@
+use crate::{ClientData, Outcome, TransactionType};
+use anyhow::{bail, Result};
+use std::{collections::HashMap, fmt};
+
+/// The effect a transaction had on one client. A transaction touching
+/// several clients, like a transfer, has one entry per client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// The transaction id, or the id a dispute, resolve or chargeback
+    /// refers to.
+    pub tx: u32,
+
+    /// The transaction type.
+    pub kind: TransactionType,
+
+    /// The client.
+    pub client: u16,
+
+    /// Whether the transaction was applied or skipped.
+    pub outcome: Outcome,
+
+    /// The client data before the transaction. A client that didn't
+    /// exist yet has default data.
+    pub before: ClientData,
+
+    /// The client data after the transaction.
+    pub after: ClientData,
+
+    /// Whether the client exists after the transaction. Transactions
+    /// skipped before touching any state don't create clients.
+    pub exists: bool
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tx {} {} for client {}: ",
+            self.tx, self.kind, self.client
+        )?;
+
+        match self.outcome {
+            Outcome::Applied => f.write_str("applied")?,
+            Outcome::Skipped(skip) => return write!(f, "skipped, {skip}")
+        }
+
+        let (before, after) = (&self.before, &self.after);
+        let amounts = [
+            ("available", before.available, after.available),
+            ("held", before.held, after.held),
+            ("total", before.total, after.total)
+        ];
+
+        // Only mention what actually changed.
+        for (name, before, after) in amounts {
+            if before != after {
+                write!(f, ", {name} {before} -> {after}")?;
+            }
+        }
+
+        if before.locked != after.locked {
+            write!(f, ", locked {} -> {}", before.locked, after.locked)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A uniform random sample of transactions' audit entries, picked by
+/// reservoir sampling so only the sample is kept in memory. The same seed
+/// and transactions always give the same sample.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// How many transactions to sample.
+    size: usize,
+
+    /// The state of the random number generator, a splitmix64.
+    state: u64,
+
+    /// How many transactions were offered.
+    seen: u64,
+
+    /// The sampled transactions' entries, with the order they were
+    /// offered in.
+    picked: Vec<(u64, Vec<AuditEntry>)>
+}
+
+impl Sample {
+    /// Creates an empty sample of `size` transactions.
+    pub fn new(size: usize, seed: u64) -> Self {
+        Self {
+            size,
+            state: seed,
+            seen: 0,
+            picked: Vec::new()
+        }
+    }
+
+    /// Returns the next random number.
+    fn next(&mut self) -> u64 {
+        self.state = self
+            .state
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Offers a transaction's entries to the sample.
+    pub fn offer(&mut self, entries: Vec<AuditEntry>) {
+        let index = self.seen;
+
+        self.seen += 1;
+
+        if self.picked.len() < self.size {
+            self.picked.push((index, entries));
+            return;
+        }
+
+        // Keep the new transaction with probability size / seen.
+        let slot = self.next() % self.seen;
+
+        if let Some(picked) = self.picked.get_mut(slot as usize) {
+            *picked = (index, entries);
+        }
+    }
+
+    /// How many transactions were offered.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// The sampled transactions' entries, in the order they were offered.
+    pub fn entries(&self) -> Vec<&AuditEntry> {
+        let mut picked = self
+            .picked
+            .iter()
+            .collect::<Vec<_>>();
+
+        picked.sort_unstable_by_key(|(index, _)| *index);
+        picked
+            .into_iter()
+            .flat_map(|(_, entries)| entries)
+            .collect()
+    }
+
+    /// How many transactions were sampled.
+    pub fn len(&self) -> usize {
+        self.picked.len()
+    }
+
+    /// Whether no transactions were sampled.
+    pub fn is_empty(&self) -> bool {
+        self.picked.is_empty()
+    }
+}
+
+/// Reconstructs the client data from an audit log, making sure each entry
+/// picks up where the last one for its client left off, that skipped
+/// transactions changed nothing and that every client's `available` and
+/// `held` add up to its `total`.
+pub fn replay_audit(entries: &[AuditEntry]) -> Result<HashMap<u16, ClientData>> {
+    let mut clients = HashMap::new();
+
+    for entry in entries {
+        let current = clients
+            .get(&entry.client)
+            .cloned()
+            .unwrap_or_default();
+
+        if entry.before != current {
+            bail!(
+                "tx {} for client {} doesn't start from the client's replayed data",
+                entry.tx,
+                entry.client
+            );
+        }
+
+        if matches!(entry.outcome, Outcome::Skipped(_)) && entry.after != entry.before {
+            bail!(
+                "tx {} for client {} was skipped but changed the client's data",
+                entry.tx,
+                entry.client
+            );
+        }
+
+        let after = &entry.after;
+        let Some(sum) = after
+            .available
+            .checked_add(after.held)
+        else {
+            bail!(
+                "tx {} leaves client {} with available {} and held {}, which overflow",
+                entry.tx,
+                entry.client,
+                after.available,
+                after.held
+            );
+        };
+
+        if sum != after.total {
+            bail!(
+                "tx {} leaves client {} with available {} and held {} but total {}",
+                entry.tx,
+                entry.client,
+                after.available,
+                after.held,
+                after.total
+            );
+        }
+
+        if entry.exists {
+            clients.insert(entry.client, entry.after.clone());
+        }
+    }
+
+    Ok(clients)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, Options, Transaction};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    /// Processes transactions, returning the engine's client data and the
+    /// audit log.
+    fn audited(txs: Vec<Transaction>) -> (HashMap<u16, ClientData>, Vec<AuditEntry>) {
+        let mut engine = Engine::new(Options::default());
+        let mut entries = Vec::new();
+
+        for tx in txs {
+            engine
+                .apply_audited(tx, &mut |entry| entries.push(entry.clone()))
+                .unwrap();
+        }
+
+        (engine.into_clients(), entries)
+    }
+
+    /// Samples the audit entries of 100 deposits, returning the sampled
+    /// transaction ids.
+    fn sampled(size: usize, seed: u64) -> Vec<u32> {
+        let txs = (1..=100)
+            .map(|tx| Transaction {
+                kind: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(dec!(1.0)),
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            })
+            .collect();
+        let (_, entries) = audited(txs);
+        let mut sample = Sample::new(size, seed);
+
+        for entry in entries {
+            sample.offer(vec![entry]);
+        }
+
+        assert_eq!(sample.seen(), 100);
+        sample
+            .entries()
+            .into_iter()
+            .map(|entry| entry.tx)
+            .collect()
+    }
+
+    #[test]
+    fn fixed_seeds_sample_reproducibly() {
+        let sample = sampled(5, 42);
+
+        assert_eq!(sample, sampled(5, 42));
+        assert_eq!(sample.len(), 5);
+        assert!(sample.is_sorted());
+        assert_ne!(sample, sampled(5, 43));
+        assert_ne!(sample, vec![1, 2, 3, 4, 5]);
+        assert_eq!(sampled(200, 42), (1..=100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn replaying_the_audit_log_reproduces_balances() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       2,
+                tx:           2,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Transfer,
+                client:       1,
+                tx:           3,
+                amount:       Some(dec!(4.0)),
+                counterparty: Some(3),
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Transfer,
+                client:       1,
+                tx:           4,
+                amount:       Some(dec!(100.0)),
+                counterparty: Some(4),
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           5,
+                amount:       Some(dec!(20.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           5,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           5,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+        let (clients, entries) = audited(txs);
+
+        assert_eq!(replay_audit(&entries).unwrap(), clients);
+    }
+
+    #[test]
+    fn inconsistent_audit_logs_fail() {
+        let (_, mut entries) = audited(vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ]);
+
+        entries[0].after.available = dec!(9.0);
+
+        let err = replay_audit(&entries).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "tx 1 leaves client 1 with available 9.0 and held 0 but total 10.0"
+        );
+
+        entries[0].after.held = Decimal::MAX;
+
+        let err = replay_audit(&entries).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "tx 1 leaves client 1 with available 9.0 and held {}, which overflow",
+                Decimal::MAX
+            )
+        );
+
+        entries[0].after.held = dec!(0);
+        entries[0].after.total = dec!(9.0);
+
+        let err = replay_audit(&entries).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "tx 2 for client 1 doesn't start from the client's replayed data"
+        );
+    }
+}
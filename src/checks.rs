@@ -0,0 +1,454 @@
+use crate::{
+    clients_sorted, ClientData, DisputeMode, DuplicatePolicy, Options, Outcome, Transaction,
+    TransactionType
+};
+use anyhow::{anyhow, bail, Result};
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+
+/// Makes sure no client has a negative balance, which shouldn't happen
+/// unless an option allowing it is set, in which case this passes. Those
+/// are overdrafts, proportional disputes, negative held funds and
+/// saturating balances.
+pub fn check_nonnegative(clients: &HashMap<u16, ClientData>, options: &Options) -> Result<()> {
+    if options.allow_overdraft
+        || options.dispute_mode == DisputeMode::Proportional
+        || options.allow_negative_held
+        || options.saturating
+    {
+        return Ok(());
+    }
+
+    let negative = clients_sorted(clients)
+        .into_iter()
+        .filter(|(_, c)| {
+            c.available < Decimal::ZERO || c.held < Decimal::ZERO || c.total < Decimal::ZERO
+        })
+        .map(|(id, _)| id.to_string())
+        .collect::<Vec<_>>();
+
+    if !negative.is_empty() {
+        bail!("clients have negative balances: {}", negative.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Returns the clients whose `available` and `held` don't add up to
+/// `total`, sorted by client id. The engine keeps them in step, so this
+/// only finds drift in client data loaded from elsewhere.
+pub fn unbalanced(clients: &HashMap<u16, ClientData>) -> Vec<u16> {
+    clients_sorted(clients)
+        .into_iter()
+        .filter(|(_, c)| {
+            c.available
+                .checked_add(c.held)
+                .is_none_or(|sum| sum != c.total)
+        })
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Independently accumulates the funds that should exist across all
+/// clients, being deposits net of withdrawals and chargebacks. Transfers
+/// only move funds between clients, so they don't change the sum, while
+/// a disputed withdrawal's funds are held until it's resolved.
+#[derive(Default, Debug)]
+pub struct Conservation {
+    /// The processing options.
+    options: Options,
+
+    /// The deposit amounts chargebacks refer to.
+    deposits: HashMap<u32, Decimal>,
+
+    /// The withdrawal amounts disputes may refer to.
+    withdrawals: HashMap<u32, Decimal>,
+
+    /// The deposits being disputed, which a reused id doesn't replace.
+    disputed: HashSet<u32>,
+
+    /// The funds that should exist.
+    expected: Decimal
+}
+
+impl Conservation {
+    /// Creates a new accumulator.
+    pub fn new(options: &Options) -> Self {
+        Self {
+            options: options.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Starts from the funds the clients already hold, such as those
+    /// loaded from a snapshot.
+    pub fn starting_from(mut self, clients: &HashMap<u16, ClientData>) -> Result<Self> {
+        self.expected = sum(clients)?;
+
+        Ok(self)
+    }
+
+    /// Records a transaction that was applied or skipped, failing if the
+    /// funds across all clients grow too large to hold.
+    pub fn record(&mut self, tx: &Transaction, outcome: Outcome) -> Result<()> {
+        if outcome != Outcome::Applied {
+            return Ok(());
+        }
+
+        // Mirror any rounding the engine applied on ingestion.
+        let mut tx = tx.clone();
+
+        if let Some(mode) = self.options.round_mode {
+            tx.round(mode);
+        }
+
+        let amount = tx.amount.unwrap_or_default();
+
+        match tx.kind {
+            TransactionType::Deposit => {
+                self.expected = add(self.expected, amount)?;
+
+                if !self.deposits.contains_key(&tx.tx)
+                    || (self.options.duplicate_policy == DuplicatePolicy::Last
+                        && !self.disputed.contains(&tx.tx))
+                {
+                    self.deposits.insert(tx.tx, amount);
+                }
+            },
+
+            TransactionType::Withdrawal => {
+                self.expected = add(self.expected, -amount)?;
+                self.withdrawals
+                    .insert(tx.tx, amount);
+            },
+
+            TransactionType::Dispute => {
+                self.disputed.insert(tx.tx);
+
+                if let Some((TransactionType::Withdrawal, amount)) = self.reference(tx.tx) {
+                    self.expected = add(self.expected, amount)?;
+                }
+            },
+
+            TransactionType::Resolve => {
+                self.disputed.remove(&tx.tx);
+
+                if let Some((TransactionType::Withdrawal, amount)) = self.reference(tx.tx) {
+                    self.expected = add(self.expected, -amount)?;
+                }
+            },
+
+            TransactionType::Chargeback => {
+                self.disputed.remove(&tx.tx);
+
+                if let Some((TransactionType::Deposit, amount)) = self.reference(tx.tx) {
+                    self.expected = add(self.expected, -amount)?;
+                }
+            },
+
+            TransactionType::Transfer => {}
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the deposit, or failing that the withdrawal, that a
+    /// dispute refers to like the engine does, returning its type and
+    /// amount.
+    fn reference(&self, tx: u32) -> Option<(TransactionType, Decimal)> {
+        let disputable = self.options.disputable;
+        let deposit = self
+            .deposits
+            .get(&tx)
+            .filter(|_| disputable.deposits());
+        let withdrawal = self
+            .withdrawals
+            .get(&tx)
+            .filter(|_| disputable.withdrawals());
+
+        deposit
+            .map(|&amount| (TransactionType::Deposit, amount))
+            .or_else(|| withdrawal.map(|&amount| (TransactionType::Withdrawal, amount)))
+    }
+
+    /// Makes sure the clients' totals add up to the accumulated funds.
+    pub fn check(&self, clients: &HashMap<u16, ClientData>) -> Result<()> {
+        let actual = sum(clients)?;
+
+        if actual != self.expected {
+            bail!(
+                "clients hold {} in total but deposits net of withdrawals and chargebacks are {}",
+                actual,
+                self.expected
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Adds to the funds across all clients, failing rather than overflowing.
+fn add(funds: Decimal, amount: Decimal) -> Result<Decimal> {
+    funds
+        .checked_add(amount)
+        .ok_or_else(|| anyhow!("funds across all clients overflow, so they can't be checked"))
+}
+
+/// Sums the clients' totals, failing rather than overflowing.
+fn sum(clients: &HashMap<u16, ClientData>) -> Result<Decimal> {
+    clients
+        .values()
+        .try_fold(Decimal::ZERO, |funds, c| add(funds, c.total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_slice, Engine};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn default_flows_are_nonnegative() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(8.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let options = Options::default();
+        let clients = process_slice(&txs, &options).unwrap();
+
+        assert!(check_nonnegative(&clients, &options).is_ok());
+    }
+
+    #[test]
+    fn negative_balances_fail() {
+        let clients = HashMap::from([
+            (1, ClientData::default()),
+            (
+                2,
+                ClientData {
+                    available: dec!(-1.0),
+                    total: dec!(-1.0),
+                    ..ClientData::default()
+                }
+            ),
+            (
+                3,
+                ClientData {
+                    held: dec!(-2.0),
+                    ..ClientData::default()
+                }
+            )
+        ]);
+
+        let err = check_nonnegative(&clients, &Options::default()).unwrap_err();
+
+        assert_eq!(err.to_string(), "clients have negative balances: 2, 3");
+    }
+
+    #[test]
+    fn options_allowing_negative_balances_pass() {
+        let clients = HashMap::from([(
+            1,
+            ClientData {
+                available: dec!(-1.0),
+                total: dec!(-1.0),
+                ..ClientData::default()
+            }
+        )]);
+        let options = [
+            Options {
+                allow_overdraft: true,
+                ..Options::default()
+            },
+            Options {
+                dispute_mode: DisputeMode::Proportional,
+                ..Options::default()
+            },
+            Options {
+                allow_negative_held: true,
+                ..Options::default()
+            },
+            Options {
+                saturating: true,
+                ..Options::default()
+            }
+        ];
+
+        for options in options {
+            assert!(check_nonnegative(&clients, &options).is_ok());
+        }
+    }
+
+    #[test]
+    fn mixed_flows_conserve_funds() {
+        let txs = [
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       2,
+                tx:           2,
+                amount:       Some(dec!(7.5)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           3,
+                amount:       Some(dec!(2.25)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       2,
+                tx:           4,
+                amount:       Some(dec!(100.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Transfer,
+                client:       1,
+                tx:           5,
+                amount:       Some(dec!(1.0)),
+                counterparty: Some(3),
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       2,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       2,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       2,
+                tx:           6,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            }
+        ];
+        let options = Options::default();
+        let mut engine = Engine::new(options.clone());
+        let mut conservation = Conservation::new(&options);
+
+        for tx in txs {
+            let outcome = engine.apply(tx.clone()).unwrap();
+
+            conservation
+                .record(&tx, outcome)
+                .unwrap();
+        }
+
+        assert_eq!(conservation.expected, dec!(7.75));
+        assert!(conservation
+            .check(engine.clients())
+            .is_ok());
+    }
+
+    #[test]
+    fn mismatched_totals_fail() {
+        let mut conservation = Conservation::new(&Options::default());
+        let tx = Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(5.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        };
+
+        conservation
+            .record(&tx, Outcome::Applied)
+            .unwrap();
+
+        let clients = HashMap::from([(
+            1,
+            ClientData {
+                available: dec!(4.0),
+                total: dec!(4.0),
+                ..ClientData::default()
+            }
+        )]);
+        let err = conservation
+            .check(&clients)
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "clients hold 4.0 in total but deposits net of withdrawals and chargebacks are 5.0"
+        );
+    }
+}
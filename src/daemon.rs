@@ -0,0 +1,231 @@
+use crate::{
+    engine, input::StreamParser, output, Diagnostics, Engine, InputOptions, Options, OutputOptions
+};
+use anyhow::Result;
+use std::io::{BufRead, Write};
+
+/// A line of daemon input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// Write the current client data.
+    Report,
+
+    /// A csv row, the first being the header unless there isn't one.
+    Row(&'a [u8])
+}
+
+impl<'a> Line<'a> {
+    /// Parses a line, which is a control line if it's just a control
+    /// word, ignoring surrounding whitespace, and a csv row otherwise.
+    /// No transaction type is a control word, so the two can't be
+    /// confused.
+    pub fn parse(line: &'a [u8]) -> Self {
+        match line.trim_ascii() {
+            b"report" => Self::Report,
+            _ => Self::Row(line)
+        }
+    }
+}
+
+/// Applies csv transactions read a line at a time from `input`, writing
+/// the current client data to `out` whenever a `report` control line
+/// arrives, and returns at EOF. The rows are read as one csv stream, so
+/// the header, line numbers and input options apply to it as a whole.
+/// Rows that fail to parse or apply are reported as diagnostics and
+/// skipped, so one bad row doesn't bring the daemon down.
+pub fn daemon<R: BufRead, W: Write, D: Write>(
+    mut input: R,
+    out: &mut W,
+    options: &Options,
+    input_options: &InputOptions,
+    output_options: &OutputOptions,
+    diagnostics: &mut Diagnostics<D>
+) -> Result<()> {
+    let mut engine = Engine::new(options.clone());
+    let mut parser = StreamParser::new(input_options);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+
+        // Make sure the last line ends like the others.
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+
+        let row = match Line::parse(&line) {
+            Line::Report => {
+                parser.skip(&line)?;
+                output::write_output(out, &engine.normalized_clients(), output_options)?;
+                continue;
+            },
+            Line::Row(row) => row
+        };
+
+        // Rows that fail to parse already name their line.
+        for tx in parser.parse(row)? {
+            let (number, tx) = match tx {
+                Ok((number, tx)) => (Some(number), Ok(tx)),
+                Err(error) => (None, Err(error))
+            };
+            let errors = engine::run_lenient(&mut engine, std::iter::once(tx), diagnostics, None)?;
+
+            for error in errors {
+                match number {
+                    Some(number) => diagnostics.print(format_args!("line {number}: {error}")),
+                    None => diagnostics.print(format_args!("{error}"))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_lines_are_told_apart_from_rows() {
+        assert_eq!(Line::parse(b"report\n"), Line::Report);
+        assert_eq!(Line::parse(b"  report\r\n"), Line::Report);
+        assert_eq!(
+            Line::parse(b"deposit,1,1,1.0\n"),
+            Line::Row(b"deposit,1,1,1.0\n")
+        );
+        assert_eq!(Line::parse(b"reports\n"), Line::Row(b"reports\n"));
+    }
+
+    #[test]
+    fn reports_write_the_balances_mid_stream() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     report\n\
+                     withdrawal,1,2,4.0\n\
+                     deposit,2,3,\n\
+                     deposit,2,4,1.5\n\
+                     report\n";
+        let mut out = Vec::new();
+        let mut diagnostics = Diagnostics::new(Vec::new(), false, false);
+
+        daemon(
+            input.as_bytes(),
+            &mut out,
+            &Options::default(),
+            &InputOptions::default(),
+            &OutputOptions::default(),
+            &mut diagnostics
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n\
+             1,10.0000,0.0000,10.0000,false\n\
+             client,available,held,total,locked\n\
+             1,6.0000,0.0000,6.0000,false\n\
+             2,1.5000,0.0000,1.5000,false\n"
+        );
+        assert_eq!(
+            String::from_utf8(diagnostics.into_inner()).unwrap(),
+            "line 5: transaction 3 has no amount\n"
+        );
+    }
+
+    /// Runs the daemon, returning what it output and its diagnostics.
+    fn run(input: &str, input_options: &InputOptions) -> (Result<()>, String, String) {
+        let mut out = Vec::new();
+        let mut diagnostics = Diagnostics::new(Vec::new(), false, false);
+        let result = daemon(
+            input.as_bytes(),
+            &mut out,
+            &Options::default(),
+            input_options,
+            &OutputOptions::default(),
+            &mut diagnostics
+        );
+
+        (
+            result,
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(diagnostics.into_inner()).unwrap()
+        )
+    }
+
+    #[test]
+    fn headerless_rows_are_each_applied_once() {
+        let options = InputOptions {
+            no_header: true,
+            ..InputOptions::default()
+        };
+        let (result, out, _) = run("deposit,1,1,5.0\ndeposit,1,2,3.0\nreport\n", &options);
+
+        result.unwrap();
+        assert_eq!(
+            out,
+            "client,available,held,total,locked\n1,8.0000,0.0000,8.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn headers_are_detected_once() {
+        let options = InputOptions {
+            auto_header: true,
+            ..InputOptions::default()
+        };
+
+        for header in ["type,client,tx,amount\n", ""] {
+            let input = format!("{header}deposit,1,1,5.0\ndeposit,1,2,3.0\nreport\n");
+            let (result, out, _) = run(&input, &options);
+
+            result.unwrap();
+            assert_eq!(
+                out, "client,available,held,total,locked\n1,8.0000,0.0000,8.0000,false\n",
+                "{header:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn unique_pairs_are_required_across_lines() {
+        let options = InputOptions {
+            strict_unique_client_tx: true,
+            ..InputOptions::default()
+        };
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     report\n\
+                     deposit,1,1,3.0\n\
+                     report\n";
+        let (result, out, diagnostics) = run(input, &options);
+
+        result.unwrap();
+        assert!(out.ends_with("1,5.0000,0.0000,5.0000,false\n"));
+        assert_eq!(
+            diagnostics,
+            "line 4 repeats client 1 and tx 1 from line 2\n"
+        );
+    }
+
+    #[test]
+    fn the_size_limit_counts_every_line() {
+        let options = InputOptions {
+            max_size: Some(50),
+            ..InputOptions::default()
+        };
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     report\n\
+                     deposit,1,2,3.0\n";
+        let (result, out, _) = run(input, &options);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "the input is larger than the maximum of 50 bytes"
+        );
+        assert!(out.ends_with("1,5.0000,0.0000,5.0000,false\n"));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::Transaction;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// The deposits that may be disputed, optionally capped in size. When
+/// full, the least recently used deposit that isn't being disputed is
+/// evicted to make room.
+#[derive(Default, Debug)]
+pub struct Deposits {
+    /// The deposits and when they were last used.
+    entries: HashMap<u32, (u64, Transaction)>,
+
+    /// The deposit transaction ids, ordered by when they were last used.
+    order: BTreeMap<u64, u32>,
+
+    /// The next usage stamp.
+    stamp: u64,
+
+    /// The maximum number of deposits to keep.
+    capacity: Option<usize>
+}
+
+impl Deposits {
+    /// Creates an empty set of deposits holding at most `capacity`.
+    pub fn new(capacity: Option<usize>) -> Self {
+        Self {
+            capacity,
+            ..Self::default()
+        }
+    }
+
+    /// The number of deposits.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether there are no deposits.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the deposit is present.
+    pub fn contains(&self, tx: &u32) -> bool {
+        self.entries.contains_key(tx)
+    }
+
+    /// Looks up a deposit.
+    pub fn get(&self, tx: &u32) -> Option<&Transaction> {
+        self.entries
+            .get(tx)
+            .map(|(_, value)| value)
+    }
+
+    /// The deposits, from least to most recently used.
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.order
+            .values()
+            .filter_map(|tx| self.get(tx))
+    }
+
+    /// Marks a deposit as recently used.
+    pub fn touch(&mut self, tx: &u32) {
+        let stamp = self.next_stamp();
+
+        if let Some((old, _)) = self.entries.get_mut(tx) {
+            self.order.remove(old);
+            self.order.insert(stamp, *tx);
+            *old = stamp;
+        }
+    }
+
+    /// Stores a deposit, evicting the least recently used deposit that
+    /// isn't in `disputed` if we're over capacity.
+    pub fn insert(&mut self, tx: Transaction, disputed: &HashSet<u32>) {
+        let id = tx.tx;
+        let stamp = self.next_stamp();
+
+        if let Some((old, _)) = self.entries.insert(id, (stamp, tx)) {
+            self.order.remove(&old);
+        }
+
+        self.order.insert(stamp, id);
+
+        // Evict if we've grown too large. Disputed deposits are never
+        // evicted, so we may stay over capacity if they're all disputed.
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+
+        while self.entries.len() > capacity {
+            let Some((&stamp, &victim)) = self
+                .order
+                .iter()
+                .find(|(_, tx)| !disputed.contains(tx))
+            else {
+                break;
+            };
+
+            self.order.remove(&stamp);
+            self.entries.remove(&victim);
+        }
+    }
+
+    /// Returns the next usage stamp.
+    fn next_stamp(&mut self) -> u64 {
+        self.stamp += 1;
+        self.stamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionType;
+    use rust_decimal_macros::dec;
+
+    fn deposit(tx: u32) -> Transaction {
+        Transaction {
+            kind: TransactionType::Deposit,
+            client: 1,
+            tx,
+            amount: Some(dec!(1.0)),
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        }
+    }
+
+    #[test]
+    fn eviction_respects_capacity() {
+        let mut deposits = Deposits::new(Some(2));
+        let disputed = HashSet::new();
+
+        for tx in 1..=5 {
+            deposits.insert(deposit(tx), &disputed);
+            assert!(deposits.len() <= 2);
+        }
+
+        assert!(!deposits.contains(&3));
+        assert!(deposits.contains(&4));
+        assert!(deposits.contains(&5));
+    }
+
+    #[test]
+    fn eviction_prefers_least_recently_used() {
+        let mut deposits = Deposits::new(Some(2));
+        let disputed = HashSet::new();
+
+        deposits.insert(deposit(1), &disputed);
+        deposits.insert(deposit(2), &disputed);
+        deposits.touch(&1);
+        deposits.insert(deposit(3), &disputed);
+
+        assert!(deposits.contains(&1));
+        assert!(!deposits.contains(&2));
+        assert!(deposits.contains(&3));
+    }
+
+    #[test]
+    fn eviction_never_removes_disputed_deposits() {
+        let mut deposits = Deposits::new(Some(2));
+        let disputed = HashSet::from([1, 2]);
+
+        deposits.insert(deposit(1), &disputed);
+        deposits.insert(deposit(2), &disputed);
+        deposits.insert(deposit(3), &disputed);
+
+        assert!(deposits.contains(&1));
+        assert!(deposits.contains(&2));
+        assert!(!deposits.contains(&3));
+
+        // With everything disputed there's nothing to evict.
+        let disputed = HashSet::from([1, 2, 4]);
+
+        deposits.insert(deposit(4), &disputed);
+
+        assert_eq!(deposits.len(), 3);
+    }
+
+    #[test]
+    fn iteration_follows_usage() {
+        let mut deposits = Deposits::new(None);
+        let disputed = HashSet::new();
+
+        deposits.insert(deposit(1), &disputed);
+        deposits.insert(deposit(2), &disputed);
+        deposits.insert(deposit(3), &disputed);
+        deposits.touch(&1);
+
+        let order = deposits
+            .iter()
+            .map(|tx| tx.tx)
+            .collect::<Vec<_>>();
+
+        assert_eq!(order, vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn unbounded_by_default() {
+        let mut deposits = Deposits::new(None);
+        let disputed = HashSet::new();
+
+        for tx in 1..=100 {
+            deposits.insert(deposit(tx), &disputed);
+        }
+
+        assert_eq!(deposits.len(), 100);
+    }
+}
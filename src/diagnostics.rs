@@ -0,0 +1,150 @@
+use crate::{audit::Sample, AuditEntry};
+use std::{
+    fmt::Display,
+    io::{self, Sink, Stderr, Write}
+};
+
+/// Writes diagnostics, which go to stderr when run from the command
+/// line. Nothing is ever written when quiet, regardless of verbosity.
+#[derive(Debug)]
+pub struct Diagnostics<W: Write = Stderr> {
+    /// Where diagnostics are written.
+    out: W,
+
+    /// Whether verbose diagnostics are written.
+    verbose: bool,
+
+    /// Whether all diagnostics are suppressed.
+    quiet: bool,
+
+    /// The transaction whose effects are explained, if any.
+    explain: Option<u32>,
+
+    /// The random sample of transactions whose effects are reported once
+    /// done, if any.
+    sample: Option<Sample>
+}
+
+impl Diagnostics<Stderr> {
+    /// Creates diagnostics that are written to stderr.
+    pub fn stderr(verbose: bool, quiet: bool) -> Self {
+        Self::new(io::stderr(), verbose, quiet)
+    }
+}
+
+impl Diagnostics<Sink> {
+    /// Creates diagnostics that are discarded.
+    pub fn silent() -> Self {
+        Self::new(io::sink(), false, true)
+    }
+}
+
+impl<W: Write> Diagnostics<W> {
+    /// Creates diagnostics that are written to `out`.
+    pub fn new(out: W, verbose: bool, quiet: bool) -> Self {
+        Self {
+            out,
+            verbose,
+            quiet,
+            explain: None,
+            sample: None
+        }
+    }
+
+    /// Explains the effects of one transaction, and of any dispute,
+    /// resolve or chargeback referring to it.
+    pub fn with_explain(mut self, tx: Option<u32>) -> Self {
+        self.explain = tx;
+        self
+    }
+
+    /// Samples transactions to report the effects of once done.
+    pub fn with_sample(mut self, sample: Option<Sample>) -> Self {
+        self.sample = sample;
+        self
+    }
+
+    /// Whether transactions are being sampled.
+    pub fn is_sampling(&self) -> bool {
+        self.sample.is_some()
+    }
+
+    /// Offers a transaction's audit entries to the sample, if any.
+    pub fn offer(&mut self, entries: Vec<AuditEntry>) {
+        if let Some(sample) = &mut self.sample {
+            sample.offer(entries);
+        }
+    }
+
+    /// Writes the sampled transactions' effects unless quiet, which ends
+    /// the sampling.
+    pub fn write_sample(&mut self) {
+        let Some(sample) = self.sample.take() else {
+            return;
+        };
+        let header = format!(
+            "sampled {} of {} transactions:",
+            sample.len(),
+            sample.seen()
+        );
+
+        self.print(header);
+
+        for entry in sample.entries() {
+            self.print(entry);
+        }
+    }
+
+    /// Writes a message unless quiet.
+    pub fn print(&mut self, message: impl Display) {
+        if !self.quiet {
+            // There's nowhere left to report a failure to.
+            let _ = writeln!(self.out, "{message}");
+        }
+    }
+
+    /// Writes a message if verbose, unless quiet.
+    pub fn verbose(&mut self, message: impl Display) {
+        if self.verbose {
+            self.print(message);
+        }
+    }
+
+    /// Writes an audit entry if it's for the explained transaction,
+    /// unless quiet.
+    pub fn explain(&mut self, entry: &AuditEntry) {
+        if self.explain == Some(entry.tx) {
+            self.print(entry);
+        }
+    }
+
+    /// Consumes the diagnostics, returning the writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbose_messages_require_verbose() {
+        let mut diagnostics = Diagnostics::new(Vec::new(), false, false);
+
+        diagnostics.print("shown");
+        diagnostics.verbose("hidden");
+
+        assert_eq!(diagnostics.into_inner(), b"shown\n");
+    }
+
+    #[test]
+    fn quiet_suppresses_everything() {
+        let mut diagnostics = Diagnostics::new(Vec::new(), true, true);
+
+        diagnostics.print("hidden");
+        diagnostics.verbose("hidden");
+
+        assert!(diagnostics.into_inner().is_empty());
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{
+    expect,
+    output::{format_amount, Column, OutputOptions},
+    ClientData, PRECISION
+};
+use anyhow::{anyhow, Result};
+use csv::WriterBuilder;
+use rust_decimal::Decimal;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    io::{Read, Write}
+};
+
+/// How a client's data changed since a prior snapshot.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub available:      Decimal,
+    pub held:           Decimal,
+    pub total:          Decimal,
+    pub locked_changed: bool
+}
+
+/// Returns how each client changed since `prior`, sorted by client id.
+/// Unchanged clients are omitted, and clients missing from either side
+/// count as having default data. Fails if a change overflows.
+pub fn deltas(
+    prior: &HashMap<u16, ClientData>,
+    clients: &HashMap<u16, ClientData>
+) -> Result<Vec<(u16, Delta)>> {
+    let ids = prior
+        .keys()
+        .chain(clients.keys())
+        .copied()
+        .collect::<BTreeSet<_>>();
+    let missing = ClientData::default();
+    let mut deltas = Vec::new();
+
+    for id in ids {
+        let before = prior.get(&id).unwrap_or(&missing);
+        let after = clients.get(&id).unwrap_or(&missing);
+
+        if before == after {
+            continue;
+        }
+
+        let change = |a: Decimal, b: Decimal| {
+            a.checked_sub(b)
+                .ok_or_else(|| anyhow!("the change in client {id} overflows"))
+        };
+
+        deltas.push((
+            id,
+            Delta {
+                available:      change(after.available, before.available)?,
+                held:           change(after.held, before.held)?,
+                total:          change(after.total, before.total)?,
+                locked_changed: after.locked != before.locked
+            }
+        ));
+    }
+
+    Ok(deltas)
+}
+
+/// How a client differs between two sets of client data, say two runs'
+/// output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The client is only in the first.
+    Removed(u16),
+
+    /// The client is only in the second.
+    Added(u16),
+
+    /// The client's data differs.
+    Changed {
+        client: u16,
+        before: ClientData,
+        after:  ClientData
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (client, before, after) = match self {
+            Self::Removed(client) => return write!(f, "client {client} was removed"),
+            Self::Added(client) => return write!(f, "client {client} was added"),
+            Self::Changed {
+                client,
+                before,
+                after
+            } => (client, before, after)
+        };
+        let amounts = [
+            ("available", before.available, after.available),
+            ("held", before.held, after.held),
+            ("total", before.total, after.total)
+        ];
+
+        write!(f, "client {client}")?;
+
+        // Only mention what actually changed.
+        let mut separator = ":";
+
+        for (name, before, after) in amounts {
+            if before != after {
+                write!(
+                    f,
+                    "{separator} {name} {} -> {}",
+                    format_amount(before, PRECISION),
+                    format_amount(after, PRECISION)
+                )?;
+                separator = ",";
+            }
+        }
+
+        if before.locked != after.locked {
+            write!(
+                f,
+                "{separator} locked {} -> {}",
+                before.locked, after.locked
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns how each client differs between `a` and `b`, sorted by client
+/// id. Amounts are compared numerically, so `2.5` and `2.5000` are the
+/// same.
+pub fn differences(a: &HashMap<u16, ClientData>, b: &HashMap<u16, ClientData>) -> Vec<Difference> {
+    let ids = a
+        .keys()
+        .chain(b.keys())
+        .copied()
+        .collect::<BTreeSet<_>>();
+
+    ids.into_iter()
+        .filter_map(|client| match (a.get(&client), b.get(&client)) {
+            (Some(_), None) => Some(Difference::Removed(client)),
+            (None, Some(_)) => Some(Difference::Added(client)),
+            (Some(before), Some(after)) if before != after => Some(Difference::Changed {
+                client,
+                before: before.clone(),
+                after: after.clone()
+            }),
+            _ => None
+        })
+        .collect()
+}
+
+/// Reads two csv outputs, like two runs' or two implementations', and
+/// returns how each client differs between them. Amounts may have any
+/// scale, and `TOTAL` rows and extra columns are ignored.
+pub fn diff_outputs<A: Read, B: Read>(a: A, b: B) -> Result<Vec<Difference>> {
+    Ok(differences(
+        &expect::read_expected(a)?,
+        &expect::read_expected(b)?
+    ))
+}
+
+/// Writes the deltas as csv, flushing once done.
+pub fn write_deltas<W: Write>(
+    out: &mut W,
+    deltas: &[(u16, Delta)],
+    options: &OutputOptions
+) -> Result<()> {
+    let mut writer = WriterBuilder::new()
+        .terminator(options.terminator.into())
+        .quote_style(options.quoting.into())
+        .from_writer(&mut *out);
+
+    writer.write_record(["client", "available", "held", "total", "locked_changed"])?;
+
+    for (id, delta) in deltas {
+        writer.write_record([
+            id.to_string(),
+            options.format(delta.available, Column::Available),
+            options.format(delta.held, Column::Held),
+            options.format(delta.total, Column::Total),
+            delta.locked_changed.to_string()
+        ])?;
+    }
+
+    // Releasing the writer flushes it.
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn identical_outputs_have_no_differences() {
+        let a = "client,available,held,total,locked\n\
+                 1,6.0000,0.0000,6.0000,false\n\
+                 2,2.5000,0.0000,2.5000,false\n";
+        let b = "client,available,held,total,locked\n\
+                 2,2.5,0,2.5,false\n\
+                 1,6,0,6,false\n";
+
+        assert_eq!(diff_outputs(a.as_bytes(), b.as_bytes()).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn differing_balances_and_locks_are_reported() {
+        let a = "client,available,held,total,locked\n\
+                 1,6.0000,0.0000,6.0000,false\n\
+                 2,2.5000,0.0000,2.5000,false\n";
+        let b = "client,available,held,total,locked\n\
+                 1,5.0000,0.0000,5.0000,true\n\
+                 2,2.5000,0.0000,2.5000,false\n";
+        let differences = diff_outputs(a.as_bytes(), b.as_bytes()).unwrap();
+
+        assert_eq!(differences.len(), 1);
+        assert_eq!(
+            differences[0].to_string(),
+            "client 1: available 6.0000 -> 5.0000, total 6.0000 -> 5.0000, locked false -> true"
+        );
+    }
+
+    #[test]
+    fn clients_in_only_one_output_are_reported() {
+        let a = "client,available,held,total,locked\n\
+                 1,6.0000,0.0000,6.0000,false\n\
+                 2,2.5000,0.0000,2.5000,false\n";
+        let b = "client,available,held,total,locked\n\
+                 2,2.5000,0.0000,2.5000,false\n\
+                 3,1.0000,0.0000,1.0000,false\n";
+        let differences = diff_outputs(a.as_bytes(), b.as_bytes()).unwrap();
+
+        assert_eq!(
+            differences,
+            vec![Difference::Removed(1), Difference::Added(3)]
+        );
+        assert_eq!(differences[0].to_string(), "client 1 was removed");
+        assert_eq!(differences[1].to_string(), "client 3 was added");
+    }
+
+    #[test]
+    fn deltas_omit_unchanged_clients() {
+        let prior = HashMap::from([
+            (
+                1,
+                ClientData {
+                    available: dec!(10.0),
+                    total: dec!(10.0),
+                    ..ClientData::default()
+                }
+            ),
+            (
+                2,
+                ClientData {
+                    available: dec!(5.0),
+                    total: dec!(5.0),
+                    ..ClientData::default()
+                }
+            ),
+            (
+                3,
+                ClientData {
+                    available: dec!(1.0),
+                    held:      dec!(2.0),
+                    total:     dec!(3.0),
+                    locked:    false
+                }
+            )
+        ]);
+        let clients = HashMap::from([
+            (
+                1,
+                ClientData {
+                    available: dec!(7.5),
+                    total: dec!(7.5),
+                    ..ClientData::default()
+                }
+            ),
+            (2, prior[&2].clone()),
+            (
+                3,
+                ClientData {
+                    available: dec!(1.0),
+                    held:      dec!(0.0),
+                    total:     dec!(1.0),
+                    locked:    true
+                }
+            ),
+            (
+                4,
+                ClientData {
+                    available: dec!(2.0),
+                    total: dec!(2.0),
+                    ..ClientData::default()
+                }
+            )
+        ]);
+        let deltas = deltas(&prior, &clients).unwrap();
+        let mut out = Vec::new();
+
+        write_deltas(&mut out, &deltas, &OutputOptions::default()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked_changed\n\
+             1,-2.5000,0.0000,-2.5000,false\n\
+             3,0.0000,-2.0000,-2.0000,true\n\
+             4,2.0000,0.0000,2.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn overflowing_deltas_fail() {
+        let client = |amount| ClientData {
+            available: amount,
+            total: amount,
+            ..ClientData::default()
+        };
+        let prior = HashMap::from([(1, client(Decimal::MIN))]);
+        let clients = HashMap::from([(1, client(Decimal::MAX))]);
+
+        assert_eq!(
+            deltas(&prior, &clients)
+                .unwrap_err()
+                .to_string(),
+            "the change in client 1 overflows"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use crate::ClientData;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// The number of clients whose total falls in each balance bucket.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct Distribution {
+    pub negative:   usize,
+    pub zero:       usize,
+    pub under_100:  usize,
+    pub under_1000: usize,
+    pub rest:       usize
+}
+
+/// Buckets client totals into a distribution.
+pub fn distribution(clients: &HashMap<u16, ClientData>) -> Distribution {
+    let mut d = Distribution::default();
+
+    for client in clients.values() {
+        let total = client.total;
+
+        if total < Decimal::ZERO {
+            d.negative += 1;
+        } else if total.is_zero() {
+            d.zero += 1;
+        } else if total < Decimal::ONE_HUNDRED {
+            d.under_100 += 1;
+        } else if total < Decimal::ONE_THOUSAND {
+            d.under_1000 += 1;
+        } else {
+            d.rest += 1;
+        }
+    }
+
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn distribution_buckets_totals() {
+        let clients = [
+            dec!(-1.0),
+            dec!(0.0),
+            dec!(0.0001),
+            dec!(99.9999),
+            dec!(100.0),
+            dec!(999.9999),
+            dec!(1000.0),
+            dec!(25000.0)
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(id, total)| {
+            (
+                id as u16,
+                ClientData {
+                    total,
+                    ..ClientData::default()
+                }
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+        assert_eq!(
+            distribution(&clients),
+            Distribution {
+                negative:   1,
+                zero:       1,
+                under_100:  2,
+                under_1000: 2,
+                rest:       2
+            }
+        );
+    }
+}
@@ -0,0 +1,4502 @@
+use crate::{
+    checks::Conservation,
+    deposits::Deposits,
+    diagnostics::Diagnostics,
+    error::{ErrorKind, ProcessError},
+    metrics::EngineMetrics,
+    snapshot::{Checkpoints, EngineSnapshot},
+    transaction::RoundMode,
+    AuditEntry, Transaction, TransactionType
+};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::Write
+};
+
+/// Options that control how transactions are processed.
+#[derive(Default, Debug, Clone)]
+pub struct Options {
+    /// Whether withdrawals may take `available` (and `total`) negative.
+    pub allow_overdraft: bool,
+
+    /// Whether zero-amount deposits and withdrawals are skipped rather
+    /// than accepted as no-ops. Skipped deposits are never stored, so
+    /// they can't be disputed later.
+    pub skip_zero_amounts: bool,
+
+    /// How amounts with more than four decimal places are rounded on
+    /// ingestion. When unset, amounts are used exactly as given.
+    pub round_mode: Option<RoundMode>,
+
+    /// The transaction types to process. When set, transactions of any
+    /// other type are skipped before they touch any state.
+    pub tx_types: Option<Vec<TransactionType>>,
+
+    /// The clients to process. When set, transactions for any other
+    /// client are skipped before they touch any state, so those clients
+    /// never appear in the output. Transfers are only filtered by the
+    /// sending client.
+    pub clients: Option<HashSet<u16>>,
+
+    /// The maximum number of deposits remembered for disputes. When
+    /// exceeded, the least recently used undisputed deposit is forgotten
+    /// and can no longer be disputed.
+    pub max_deposits: Option<usize>,
+
+    /// Which transactions a locked account blocks.
+    pub locked_blocks: LockedBlocks,
+
+    /// Whether transactions a locked account blocks are reported as
+    /// rejected rather than silently skipped, so operators know funds
+    /// were refused. They're printed as diagnostics, or collected as
+    /// `LockedClient` errors by a lenient run.
+    pub reject_after_lock: bool,
+
+    /// What happens when a disputed deposit is more than the available
+    /// funds.
+    pub dispute_mode: DisputeMode,
+
+    /// Which transactions may be disputed.
+    pub disputable: Disputable,
+
+    /// How many times a transaction may be disputed, after which further
+    /// disputes of it are skipped. This stops a file from disputing and
+    /// resolving the same transaction over and over. When unset, there's
+    /// no limit.
+    pub max_dispute_cycles: Option<u32>,
+
+    /// Whether a resolve or chargeback may take `held` negative, which
+    /// only happens if `held` was already short of the disputed amount,
+    /// say in a hand-edited snapshot. Otherwise what's released is clamped
+    /// to what's held, or processing fails when strict.
+    pub allow_negative_held: bool,
+
+    /// How many disputes may be open at once across all clients, bounding
+    /// memory and limiting abuse. Further disputes are skipped until a
+    /// resolve or chargeback closes one. When unset, there's no limit.
+    pub max_open_disputes: Option<usize>,
+
+    /// Which deposit is kept when a deposit reuses a transaction id.
+    pub duplicate_policy: DuplicatePolicy,
+
+    /// The largest amount a single deposit, withdrawal or transfer may
+    /// have. Larger ones are skipped, or fail processing when strict.
+    pub max_amount: Option<Decimal>,
+
+    /// Whether transactions that indicate bad data fail processing
+    /// rather than being skipped.
+    pub strict: bool,
+
+    /// Whether deposits, withdrawals and transfers with a negative or zero
+    /// amount fail processing. Their type gives the direction funds move
+    /// in, so such amounts are likely sign convention mistakes.
+    pub strict_amount_sign: bool,
+
+    /// Whether deposits and withdrawals missing an amount are treated as
+    /// having a zero amount instead of failing processing. Combined with
+    /// `skip_zero_amounts` they're skipped entirely, otherwise they're
+    /// accepted as no-ops.
+    pub coerce_missing_amount: bool,
+
+    /// Whether a dispute, resolve or chargeback referencing a transaction
+    /// that doesn't exist at all fails processing, suggesting a malformed
+    /// file. Deposits forgotten because of `max_deposits` count as not
+    /// existing, as do withdrawals unless they're `disputable`. Referencing
+    /// a transaction that exists but can't be acted on is always skipped.
+    pub strict_references: bool,
+
+    /// Whether processing fails if the clients' totals don't add up to
+    /// the deposits net of withdrawals and chargebacks, tallied
+    /// independently of the engine.
+    pub check_conservation: bool,
+
+    /// Whether processing fails when adding to or subtracting from a
+    /// balance would round the result, which happens once it needs more
+    /// than the 28 significant digits a `Decimal` holds. Otherwise the
+    /// least significant digits are silently rounded away.
+    pub strict_precision: bool,
+
+    /// Whether balances saturate at the largest and smallest amounts
+    /// rather than processing failing when they'd overflow. Saturated
+    /// balances no longer add up, so this is only for analytics.
+    pub saturating: bool,
+
+    /// The scale client balances are rescaled to once processing is done
+    /// and in snapshots, so `10.00000000` comes out as `10.0000`. More
+    /// precise balances are rounded half away from zero. This is
+    /// independent of how amounts are formatted for output. When unset,
+    /// balances keep whatever scale they accumulated.
+    pub normalize_scale: Option<u32>
+}
+
+/// What a run does when a transaction fails to parse or apply.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnError {
+    /// Fail processing.
+    #[default]
+    Abort,
+
+    /// Skip the transaction and carry on.
+    Continue,
+
+    /// Skip the transaction and every later one its client sends,
+    /// abandoning the client. Rows that fail to parse have no client, so
+    /// only they are skipped.
+    SkipClient
+}
+
+/// Which deposit is kept when a deposit reuses a transaction id. Either
+/// way both deposits are credited, this decides which one later disputes
+/// refer to. A disputed deposit is never replaced while its dispute is
+/// open, so resolving or charging it back moves what was actually held.
+/// Once resolved, `Last` makes the reused id a new disputable deposit.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep the original deposit.
+    First,
+
+    /// Keep the latest deposit.
+    #[default]
+    Last,
+
+    /// Fail processing on the duplicate, even of a deposit evicted to
+    /// honor `--max-deposits`.
+    Reject,
+
+    /// Ignore an exact repeat of a deposit, with the same client and
+    /// amount, as redelivered. Fail processing on any other duplicate.
+    Idempotent
+}
+
+/// What happens when a disputed deposit is more than the available
+/// funds, say because some of it was already withdrawn.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeMode {
+    /// The dispute is skipped.
+    #[default]
+    Full,
+
+    /// The available funds are held and the rest is recorded as a
+    /// shortfall. A resolve releases only what was held. A chargeback
+    /// still reverses the whole deposit, taking the held funds from
+    /// `held` and the shortfall from `available`, which may go negative.
+    Proportional
+}
+
+/// Which transactions may be disputed.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Disputable {
+    /// Only deposits.
+    #[default]
+    Deposits,
+
+    /// Only withdrawals. Disputing one holds the withdrawn amount until
+    /// it's resolved, or returned to the client by a chargeback.
+    Withdrawals,
+
+    /// Both deposits and withdrawals.
+    Both
+}
+
+impl Disputable {
+    /// Whether deposits may be disputed.
+    pub fn deposits(self) -> bool {
+        self != Self::Withdrawals
+    }
+
+    /// Whether withdrawals may be disputed.
+    pub fn withdrawals(self) -> bool {
+        self != Self::Deposits
+    }
+}
+
+/// Which transactions a locked account blocks.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockedBlocks {
+    /// Every transaction on a locked account is skipped.
+    #[default]
+    All,
+
+    /// Only transactions taking funds out of a locked account, being
+    /// withdrawals and outgoing transfers, are skipped. Deposits,
+    /// incoming transfers and disputes are still processed.
+    WithdrawalsOnly
+}
+
+impl LockedBlocks {
+    /// Whether a locked account blocks this kind of transaction.
+    pub fn blocks(&self, kind: TransactionType) -> bool {
+        match self {
+            Self::All => true,
+            Self::WithdrawalsOnly => matches!(
+                kind,
+                TransactionType::Withdrawal | TransactionType::Transfer
+            )
+        }
+    }
+}
+
+/// Aggregated client data. All amounts are exact decimals, no amount is
+/// ever converted to or from floating point.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct ClientData {
+    pub available: Decimal,
+    pub held:      Decimal,
+    pub total:     Decimal,
+    pub locked:    bool
+}
+
+impl ClientData {
+    /// Rescales the amounts to `scale` decimal places, rounding half away
+    /// from zero if they're more precise.
+    pub fn normalize(&mut self, scale: u32) {
+        self.available.rescale(scale);
+        self.held.rescale(scale);
+        self.total.rescale(scale);
+    }
+}
+
+/// The result of applying a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The transaction was applied.
+    Applied,
+
+    /// The transaction was skipped.
+    Skipped(Skip)
+}
+
+/// Why a transaction was skipped.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Skip {
+    /// Its type was filtered out.
+    Filtered,
+
+    /// It had a zero amount.
+    ZeroAmount,
+
+    /// The client is locked.
+    Locked,
+
+    /// The client doesn't have enough available funds.
+    InsufficientFunds,
+
+    /// It referenced an unknown transaction.
+    UnknownTransaction,
+
+    /// It disputed a transaction that's already disputed.
+    AlreadyDisputed,
+
+    /// It referenced a transaction that isn't disputed.
+    NotDisputed,
+
+    /// Its amount is over the maximum.
+    OverMaxAmount,
+
+    /// It repeated a deposit that was already applied.
+    Duplicate,
+
+    /// It disputed a transaction id shared by a deposit and a withdrawal,
+    /// when both may be disputed.
+    Ambiguous,
+
+    /// It disputed a transaction that was already disputed the maximum
+    /// number of times.
+    TooManyDisputes,
+
+    /// It disputed a transaction while the maximum number of disputes were
+    /// open.
+    TooManyOpenDisputes,
+
+    /// An earlier transaction for the client failed, so the client was
+    /// abandoned.
+    Errored
+}
+
+impl fmt::Display for Skip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::Filtered => "filtered out",
+            Self::ZeroAmount => "zero amount",
+            Self::Locked => "client is locked",
+            Self::InsufficientFunds => "insufficient funds",
+            Self::UnknownTransaction => "unknown transaction",
+            Self::AlreadyDisputed => "already disputed",
+            Self::NotDisputed => "not disputed",
+            Self::OverMaxAmount => "amount over maximum",
+            Self::Duplicate => "duplicate deposit",
+            Self::Ambiguous => "ambiguous transaction",
+            Self::TooManyDisputes => "too many disputes",
+            Self::TooManyOpenDisputes => "too many open disputes",
+            Self::Errored => "client errored"
+        };
+
+        f.write_str(reason)
+    }
+}
+
+/// The transaction engine.
+#[derive(Default, Debug)]
+pub struct Engine {
+    /// The processing options.
+    options: Options,
+
+    /// The client data.
+    clients: HashMap<u16, ClientData>,
+
+    /// The deposits that may be disputed.
+    deposits: Deposits,
+
+    /// The withdrawals that may be disputed, only kept if asked to.
+    withdrawals: Deposits,
+
+    /// The transactions currently being disputed.
+    disputed: HashSet<u32>,
+
+    /// How much of each partially held dispute couldn't be held.
+    shortfalls: HashMap<u32, Decimal>,
+
+    /// How many times each transaction has been disputed, only kept if
+    /// the number of disputes is capped.
+    dispute_counts: HashMap<u32, u32>,
+
+    /// The counts and amounts of the transactions processed.
+    metrics: EngineMetrics,
+
+    /// The clients with at least one transaction applied, including
+    /// transfer counterparties.
+    touched: HashSet<u16>,
+
+    /// How many transactions each client sent were applied and skipped.
+    tx_counts: HashMap<u16, (u64, u64)>,
+
+    /// The client and amount of every applied deposit, only kept for the
+    /// reject and idempotent duplicate policies, so they still spot
+    /// duplicates of deposits evicted to honor `max_deposits`.
+    applied: HashMap<u32, (u16, Decimal)>,
+
+    /// How far the last transaction would have taken `held` negative,
+    /// had it not been clamped.
+    clamped: Option<Decimal>,
+
+    /// Whether the engine started from existing client data, so
+    /// transactions before it may be unknown.
+    resumed: bool,
+
+    /// The clients abandoned after an error, whose transactions are
+    /// skipped.
+    errored: HashSet<u16>
+}
+
+impl Engine {
+    /// Creates a new engine.
+    pub fn new(options: Options) -> Self {
+        Self {
+            deposits: Deposits::new(options.max_deposits),
+            withdrawals: Deposits::new(options.max_deposits),
+            options,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a new engine starting from existing client data, such as
+    /// a loaded snapshot. Earlier deposits aren't known, so they can't be
+    /// disputed.
+    pub fn with_clients(options: Options, clients: HashMap<u16, ClientData>) -> Self {
+        Self {
+            clients,
+            resumed: true,
+            ..Self::new(options)
+        }
+    }
+
+    /// Creates a new engine from a snapshot of another, so disputes opened
+    /// before it was taken can still be resolved. Fails if a stored deposit
+    /// or withdrawal is malformed, say lacking an amount.
+    pub fn from_snapshot(options: Options, state: EngineSnapshot) -> Result<Self> {
+        let mut engine = Self::with_clients(options, state.clients());
+
+        engine.disputed = state.disputed.into_iter().collect();
+        engine.shortfalls = state
+            .shortfalls
+            .into_iter()
+            .collect();
+        engine.dispute_counts = state
+            .dispute_counts
+            .into_iter()
+            .collect();
+        engine.applied = state
+            .applied
+            .into_iter()
+            .map(|(tx, client, amount)| (tx, (client, amount)))
+            .collect();
+
+        // Insert in usage order so eviction picks up where it left off.
+        for tx in state.deposits {
+            tx.verify()?;
+            engine
+                .deposits
+                .insert(tx, &engine.disputed);
+        }
+
+        for tx in state.withdrawals {
+            tx.verify()?;
+            engine
+                .withdrawals
+                .insert(tx, &engine.disputed);
+        }
+
+        Ok(engine)
+    }
+
+    /// A point-in-time snapshot of the engine's client data, deposits,
+    /// withdrawals and disputes, which serializes as an extended snapshot.
+    /// The metrics and which clients were touched aren't kept, and the
+    /// balances are exact rather than normalized, so a restored engine
+    /// carries on exactly where this one is.
+    pub fn snapshot(&self) -> EngineSnapshot {
+        let mut disputed = self
+            .disputed
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        let mut shortfalls = self
+            .shortfalls
+            .iter()
+            .map(|(&tx, &amount)| (tx, amount))
+            .collect::<Vec<_>>();
+
+        let mut dispute_counts = self
+            .dispute_counts
+            .iter()
+            .map(|(&tx, &count)| (tx, count))
+            .collect::<Vec<_>>();
+        let mut applied = self
+            .applied
+            .iter()
+            .map(|(&tx, &(client, amount))| (tx, client, amount))
+            .collect::<Vec<_>>();
+
+        disputed.sort_unstable();
+        shortfalls.sort_unstable_by_key(|&(tx, _)| tx);
+        dispute_counts.sort_unstable();
+        applied.sort_unstable_by_key(|&(tx, ..)| tx);
+
+        let mut state = EngineSnapshot::new(&self.clients);
+
+        state.deposits = self
+            .deposits
+            .iter()
+            .cloned()
+            .collect();
+        state.withdrawals = self
+            .withdrawals
+            .iter()
+            .cloned()
+            .collect();
+        state.disputed = disputed;
+        state.shortfalls = shortfalls;
+        state.dispute_counts = dispute_counts;
+        state.applied = applied;
+        state
+    }
+
+    /// How much of a disputed deposit couldn't be held, if any.
+    pub fn shortfall(&self, tx: u32) -> Option<Decimal> {
+        self.shortfalls.get(&tx).copied()
+    }
+
+    /// The counts and amounts of the transactions processed.
+    pub fn metrics(&self) -> &EngineMetrics {
+        &self.metrics
+    }
+
+    /// The client data.
+    pub fn clients(&self) -> &HashMap<u16, ClientData> {
+        &self.clients
+    }
+
+    /// A copy of a client's current data, if the client exists.
+    pub fn balance(&self, client: u16) -> Option<ClientData> {
+        self.clients.get(&client).cloned()
+    }
+
+    /// A copy of a client's current data like `balance`, normalized if
+    /// asked to like `normalized_clients`.
+    pub fn normalized_balance(&self, client: u16) -> Option<ClientData> {
+        let mut data = self.balance(client)?;
+
+        if let Some(scale) = self.options.normalize_scale {
+            data.normalize(scale);
+        }
+
+        Some(data)
+    }
+
+    /// Abandons a client, skipping every later transaction it sends.
+    pub fn abandon(&mut self, client: u16) {
+        self.errored.insert(client);
+    }
+
+    /// The clients abandoned after an error.
+    pub fn errored(&self) -> &HashSet<u16> {
+        &self.errored
+    }
+
+    /// Whether a client is locked. Unknown clients aren't.
+    pub fn is_locked(&self, client: u16) -> bool {
+        self.clients
+            .get(&client)
+            .is_some_and(|c| c.locked)
+    }
+
+    /// Consumes the engine, returning the client data, normalized if
+    /// asked to.
+    pub fn into_clients(mut self) -> HashMap<u16, ClientData> {
+        self.normalize();
+        self.clients
+    }
+
+    /// The clients with at least one transaction applied by this engine,
+    /// as opposed to loaded from a snapshot.
+    pub fn touched(&self) -> &HashSet<u16> {
+        &self.touched
+    }
+
+    /// Consumes the engine, returning the data of only the touched
+    /// clients, normalized if asked to.
+    pub fn into_touched_clients(mut self) -> HashMap<u16, ClientData> {
+        self.clients
+            .retain(|id, _| self.touched.contains(id));
+        self.normalize();
+        self.clients
+    }
+
+    /// A copy of the client data, normalized if asked to, for output or
+    /// a snapshot while the engine carries on with the exact balances.
+    pub fn normalized_clients(&self) -> HashMap<u16, ClientData> {
+        let mut clients = self.clients.clone();
+
+        if let Some(scale) = self.options.normalize_scale {
+            clients
+                .values_mut()
+                .for_each(|c| c.normalize(scale));
+        }
+
+        clients
+    }
+
+    /// Rescales every client's balances if asked to.
+    fn normalize(&mut self) {
+        if let Some(scale) = self.options.normalize_scale {
+            self.clients
+                .values_mut()
+                .for_each(|c| c.normalize(scale));
+        }
+    }
+
+    /// Applies a single transaction like `apply`, then calls `on_change`
+    /// with the new state of each client whose state actually changed. A
+    /// client that didn't exist before counts as having default state.
+    pub fn apply_with<F>(&mut self, tx: Transaction, on_change: &mut F) -> Result<Outcome>
+    where
+        F: FnMut(u16, &ClientData)
+    {
+        self.apply_audited(tx, &mut |entry| {
+            if entry.before != entry.after {
+                on_change(entry.client, &entry.after);
+            }
+        })
+    }
+
+    /// Applies a batch of transactions like `apply`, returning the new
+    /// state of only the clients whose state actually changed. If a
+    /// transaction fails, those before it stay applied.
+    pub fn apply_batch<T>(&mut self, txs: T) -> Result<HashMap<u16, ClientData>>
+    where
+        T: IntoIterator<Item = Transaction>
+    {
+        let mut changed = HashMap::new();
+
+        for tx in txs {
+            self.apply_with(tx, &mut |client, data| {
+                changed.insert(client, data.clone());
+            })?;
+        }
+
+        Ok(changed)
+    }
+
+    /// Applies a single transaction like `apply`, then calls `audit` with
+    /// its effect on each client it names, whether it was applied or
+    /// skipped.
+    pub fn apply_audited<F>(&mut self, tx: Transaction, audit: &mut F) -> Result<Outcome>
+    where
+        F: FnMut(&AuditEntry)
+    {
+        let (id, kind) = (tx.tx, tx.kind);
+        let affected = [Some(tx.client), tx.counterparty];
+        let before = affected.map(|id| {
+            id.map(|id| {
+                let client = self.clients.get(&id);
+
+                (id, client.cloned().unwrap_or_default())
+            })
+        });
+
+        let outcome = self.apply(tx)?;
+
+        for (client, before) in before.into_iter().flatten() {
+            let after = self.clients.get(&client);
+
+            audit(&AuditEntry {
+                tx: id,
+                kind,
+                client,
+                outcome,
+                before,
+                after: after.cloned().unwrap_or_default(),
+                exists: after.is_some()
+            });
+        }
+
+        Ok(outcome)
+    }
+
+    /// How many transactions were processed for each client, counting
+    /// skipped ones too if asked to. Transfers only count for the sending
+    /// client.
+    pub fn tx_counts(&self, skipped: bool) -> HashMap<u16, u64> {
+        self.tx_counts
+            .iter()
+            .map(|(&id, &(applied, skips))| (id, applied + if skipped { skips } else { 0 }))
+            .collect()
+    }
+
+    /// How far the last transaction applied would have taken `held`
+    /// negative, had it not been clamped at zero.
+    pub fn clamped(&self) -> Option<Decimal> {
+        self.clamped
+    }
+
+    /// Applies a single transaction, returning whether it was applied
+    /// or skipped.
+    pub fn apply(&mut self, tx: Transaction) -> Result<Outcome> {
+        let mut recorded = tx.clone();
+
+        self.clamped = None;
+
+        if let Some(mode) = self.options.round_mode {
+            recorded.round(mode);
+        }
+
+        let outcome = self.apply_unrecorded(tx)?;
+
+        self.metrics
+            .record(recorded.kind, recorded.amount, outcome);
+        self.metrics.open_disputes = self.disputed.len();
+
+        let (applied, skipped) = self
+            .tx_counts
+            .entry(recorded.client)
+            .or_default();
+
+        if outcome == Outcome::Applied {
+            *applied += 1;
+        } else {
+            *skipped += 1;
+        }
+
+        if outcome == Outcome::Applied {
+            self.touched.insert(recorded.client);
+            self.touched
+                .extend(recorded.counterparty);
+        }
+
+        Ok(outcome)
+    }
+
+    /// Applies a single transaction without recording it in the metrics
+    /// or the touched clients.
+    fn apply_unrecorded(&mut self, mut tx: Transaction) -> Result<Outcome> {
+        // Skip transaction types we've been asked to ignore.
+        if let Some(tx_types) = &self.options.tx_types
+            && !tx_types.contains(&tx.kind)
+        {
+            return Ok(Outcome::Skipped(Skip::Filtered));
+        }
+
+        // Skip clients we've been asked to ignore.
+        if let Some(clients) = &self.options.clients
+            && !clients.contains(&tx.client)
+        {
+            return Ok(Outcome::Skipped(Skip::Filtered));
+        }
+
+        // Skip clients abandoned after an error, before anything else
+        // about the transaction can fail.
+        if self.errored.contains(&tx.client) {
+            return Ok(Outcome::Skipped(Skip::Errored));
+        }
+
+        // Treat missing amounts as zero if asked to.
+        if self.options.coerce_missing_amount
+            && tx.amount.is_none()
+            && matches!(
+                tx.kind,
+                TransactionType::Deposit | TransactionType::Withdrawal
+            )
+        {
+            tx.amount = Some(Decimal::ZERO);
+        }
+
+        // Verify the transaction, and its sign if asked to.
+        tx.verify()?;
+
+        if self.options.strict_amount_sign {
+            tx.verify_sign()?;
+        }
+
+        // Normalize over-precise amounts if asked to.
+        if let Some(mode) = self.options.round_mode {
+            tx.round(mode);
+        }
+
+        // Skip zero-amount transactions if asked to.
+        if self.options.skip_zero_amounts
+            && tx
+                .amount
+                .is_some_and(|a| a.is_zero())
+        {
+            return Ok(Outcome::Skipped(Skip::ZeroAmount));
+        }
+
+        // Skip amounts over the maximum, likely data-entry errors.
+        if let (Some(max), Some(amount)) = (self.options.max_amount, tx.amount)
+            && amount > max
+            && matches!(
+                tx.kind,
+                TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+            )
+        {
+            if self.options.strict {
+                bail!(ErrorKind::OverMaxAmount.error(format!(
+                    "transaction {} has amount {} over the maximum of {}",
+                    tx.tx, amount, max
+                )));
+            }
+
+            return Ok(Outcome::Skipped(Skip::OverMaxAmount));
+        }
+
+        // Ensure this client exists.
+        let client = self
+            .clients
+            .entry(tx.client)
+            .or_default();
+
+        // If the client is locked, do nothing. A dispute arriving after
+        // that usually means the data is off, so it fails if strict.
+        if client.locked
+            && self
+                .options
+                .locked_blocks
+                .blocks(tx.kind)
+        {
+            if self.options.strict
+                && matches!(
+                    tx.kind,
+                    TransactionType::Dispute
+                        | TransactionType::Resolve
+                        | TransactionType::Chargeback
+                )
+            {
+                bail!(ErrorKind::LockedClient.error(format!(
+                    "{} of transaction {} for client {} arrived after the client was locked",
+                    tx.kind, tx.tx, tx.client
+                )));
+            }
+
+            return Ok(Outcome::Skipped(Skip::Locked));
+        }
+
+        // Now match on the transaction type.
+        match tx.kind {
+            TransactionType::Deposit => {
+                let amount = &tx.required_amount()?;
+                let duplicate = self.deposits.contains(&tx.tx);
+
+                // Reject duplicates before touching any state.
+                if self.options.duplicate_policy == DuplicatePolicy::Reject
+                    && (duplicate || self.applied.contains_key(&tx.tx))
+                {
+                    bail!(ErrorKind::DuplicateTx
+                        .error(format!("transaction {} is a duplicate deposit", tx.tx)));
+                }
+
+                // Ignore redelivered deposits, but not conflicting ones.
+                if self.options.duplicate_policy == DuplicatePolicy::Idempotent
+                    && let Some(&(id, applied)) = self.applied.get(&tx.tx)
+                {
+                    if id != tx.client || applied != *amount {
+                        bail!(ErrorKind::DuplicateTx.error(format!(
+                            "transaction {} conflicts with an earlier deposit of {} for client {}",
+                            tx.tx, applied, id
+                        )));
+                    }
+
+                    return Ok(Outcome::Skipped(Skip::Duplicate));
+                }
+
+                // Update the client data.
+                add(&mut client.available, *amount, &tx, &self.options)?;
+                add(&mut client.total, *amount, &tx, &self.options)?;
+
+                // Only remember the deposit once it's applied, so one that
+                // failed can still be redelivered.
+                if matches!(
+                    self.options.duplicate_policy,
+                    DuplicatePolicy::Reject | DuplicatePolicy::Idempotent
+                ) {
+                    self.applied
+                        .insert(tx.tx, (tx.client, *amount));
+                }
+
+                // Store the deposit, unless we're keeping the original or
+                // it's still being disputed.
+                if !duplicate
+                    || (self.options.duplicate_policy == DuplicatePolicy::Last
+                        && !self.disputed.contains(&tx.tx))
+                {
+                    self.deposits
+                        .insert(tx, &self.disputed);
+                }
+            },
+
+            TransactionType::Withdrawal => {
+                let amount = &tx.required_amount()?;
+
+                // Check if we have enough available funds. Overdrafts
+                // only ever draw against available, so held funds
+                // remain protected either way.
+                if !self.options.allow_overdraft && client.available < *amount {
+                    return Ok(Outcome::Skipped(Skip::InsufficientFunds));
+                }
+
+                // Update the client data.
+                sub(&mut client.available, *amount, &tx, &self.options)?;
+                sub(&mut client.total, *amount, &tx, &self.options)?;
+
+                // Only keep withdrawals that could be disputed.
+                if self
+                    .options
+                    .disputable
+                    .withdrawals()
+                {
+                    self.withdrawals
+                        .insert(tx, &self.disputed);
+                }
+            },
+
+            TransactionType::Dispute => {
+                // Try and lookup the disputed transaction.
+                let reference =
+                    match reference(&tx, &self.deposits, &self.withdrawals, &self.options)? {
+                        Ok(reference) => reference,
+                        Err(outcome) => return Ok(outcome)
+                    };
+
+                // Make sure it's not already being disputed.
+                if self.disputed.contains(&tx.tx) {
+                    return Ok(Outcome::Skipped(Skip::AlreadyDisputed));
+                }
+
+                // Make sure it hasn't been disputed too often.
+                if let Some(max) = self.options.max_dispute_cycles
+                    && self
+                        .dispute_counts
+                        .get(&tx.tx)
+                        .is_some_and(|&count| count >= max)
+                {
+                    return Ok(Outcome::Skipped(Skip::TooManyDisputes));
+                }
+
+                // Make sure there's room for another open dispute.
+                if let Some(max) = self.options.max_open_disputes
+                    && self.disputed.len() >= max
+                {
+                    return Ok(Outcome::Skipped(Skip::TooManyOpenDisputes));
+                }
+
+                match reference {
+                    Reference::Deposit(amount) => {
+                        // Only allow the dispute if we have available
+                        // funds. This was unclear in the spec, but it
+                        // aligns with what I'd expect from a bank in the
+                        // real world. In proportional mode we hold what we
+                        // can instead.
+                        let held = match self.options.dispute_mode {
+                            _ if client.available >= amount => amount,
+                            DisputeMode::Full => {
+                                return Ok(Outcome::Skipped(Skip::InsufficientFunds));
+                            },
+                            DisputeMode::Proportional => client.available.max(Decimal::ZERO)
+                        };
+
+                        // Update the client data.
+                        sub(&mut client.available, held, &tx, &self.options)?;
+                        add(&mut client.held, held, &tx, &self.options)?;
+
+                        // Remember what we couldn't hold.
+                        if held < amount {
+                            self.shortfalls
+                                .insert(tx.tx, amount - held);
+                        }
+
+                        self.deposits.touch(&tx.tx);
+                    },
+
+                    Reference::Withdrawal(amount) => {
+                        // Hold the withdrawn funds until we know whether
+                        // they go back to the client.
+                        add(&mut client.held, amount, &tx, &self.options)?;
+                        add(&mut client.total, amount, &tx, &self.options)?;
+
+                        self.withdrawals.touch(&tx.tx);
+                    }
+                }
+
+                // Mark the transaction as disputed.
+                self.disputed.insert(tx.tx);
+
+                if self
+                    .options
+                    .max_dispute_cycles
+                    .is_some()
+                {
+                    *self
+                        .dispute_counts
+                        .entry(tx.tx)
+                        .or_default() += 1;
+                }
+            },
+
+            TransactionType::Resolve => {
+                // Try and lookup the disputed transaction.
+                let reference =
+                    match reference(&tx, &self.deposits, &self.withdrawals, &self.options)? {
+                        Ok(reference) => reference,
+                        Err(outcome) => return Ok(outcome)
+                    };
+
+                // Make sure that it is being disputed.
+                if !self.disputed.contains(&tx.tx) {
+                    return Ok(Outcome::Skipped(Skip::NotDisputed));
+                }
+
+                match reference {
+                    Reference::Deposit(amount) => {
+                        // Release what was held, the shortfall was never
+                        // taken.
+                        let shortfall = self
+                            .shortfalls
+                            .remove(&tx.tx)
+                            .unwrap_or_default();
+                        let held = guard_held(
+                            client.held,
+                            amount - shortfall,
+                            &tx,
+                            &self.options,
+                            &mut self.clamped
+                        )?;
+
+                        // Update the client data.
+                        add(&mut client.available, held, &tx, &self.options)?;
+                        sub(&mut client.held, held, &tx, &self.options)?;
+                    },
+
+                    Reference::Withdrawal(amount) => {
+                        // The withdrawal stands, so drop the held funds.
+                        let held =
+                            guard_held(client.held, amount, &tx, &self.options, &mut self.clamped)?;
+
+                        sub(&mut client.held, held, &tx, &self.options)?;
+                        sub(&mut client.total, held, &tx, &self.options)?;
+                    }
+                }
+
+                // Mark the transaction as no longer disputed.
+                self.disputed.remove(&tx.tx);
+            },
+
+            TransactionType::Chargeback => {
+                // Try and lookup the disputed transaction.
+                let reference =
+                    match reference(&tx, &self.deposits, &self.withdrawals, &self.options)? {
+                        Ok(reference) => reference,
+                        Err(outcome) => return Ok(outcome)
+                    };
+
+                // Make sure that it is being disputed.
+                if !self.disputed.contains(&tx.tx) {
+                    return Ok(Outcome::Skipped(Skip::NotDisputed));
+                }
+
+                match reference {
+                    Reference::Deposit(amount) => {
+                        // Reverse the whole deposit, taking what wasn't
+                        // held out of available.
+                        let shortfall = self
+                            .shortfalls
+                            .remove(&tx.tx)
+                            .unwrap_or_default();
+
+                        let held = guard_held(
+                            client.held,
+                            amount - shortfall,
+                            &tx,
+                            &self.options,
+                            &mut self.clamped
+                        )?;
+
+                        // Update the client data.
+                        sub(&mut client.available, shortfall, &tx, &self.options)?;
+                        sub(&mut client.held, held, &tx, &self.options)?;
+                        sub(&mut client.total, shortfall + held, &tx, &self.options)?;
+                    },
+
+                    Reference::Withdrawal(amount) => {
+                        // Reverse the withdrawal, returning the held funds.
+                        let held =
+                            guard_held(client.held, amount, &tx, &self.options, &mut self.clamped)?;
+
+                        sub(&mut client.held, held, &tx, &self.options)?;
+                        add(&mut client.available, held, &tx, &self.options)?;
+                    }
+                }
+
+                client.locked = true;
+
+                // Mark the transaction as no longer disputed.
+                self.disputed.remove(&tx.tx);
+            },
+
+            TransactionType::Transfer => {
+                return self.transfer(&tx);
+            }
+        }
+
+        Ok(Outcome::Applied)
+    }
+
+    /// Moves funds from the client to the counterparty, or leaves both
+    /// untouched if either is locked or the client lacks the funds.
+    fn transfer(&mut self, tx: &Transaction) -> Result<Outcome> {
+        let amount = tx.required_amount()?;
+        let counterparty = tx.required_counterparty()?;
+
+        // The counterparty must be unlocked, unless locked accounts
+        // still accept incoming funds.
+        if self.options.locked_blocks == LockedBlocks::All
+            && self
+                .clients
+                .get(&counterparty)
+                .is_some_and(|c| c.locked)
+        {
+            return Ok(Outcome::Skipped(Skip::Locked));
+        }
+
+        // Take the funds from the client and give them to the
+        // counterparty, working out both balances before changing either
+        // so a transfer that overflows leaves both untouched.
+        let mut sender = self
+            .clients
+            .entry(tx.client)
+            .or_default()
+            .clone();
+
+        if !self.options.allow_overdraft && sender.available < amount {
+            return Ok(Outcome::Skipped(Skip::InsufficientFunds));
+        }
+
+        sub(&mut sender.available, amount, tx, &self.options)?;
+        sub(&mut sender.total, amount, tx, &self.options)?;
+
+        let mut receiver = self
+            .clients
+            .get(&counterparty)
+            .cloned()
+            .unwrap_or_default();
+
+        add(&mut receiver.available, amount, tx, &self.options)?;
+        add(&mut receiver.total, amount, tx, &self.options)?;
+
+        self.clients
+            .insert(tx.client, sender);
+        self.clients
+            .insert(counterparty, receiver);
+
+        Ok(Outcome::Applied)
+    }
+}
+
+/// Adds an amount to a balance, saturating if asked to and otherwise
+/// failing rather than overflowing.
+fn add(balance: &mut Decimal, amount: Decimal, tx: &Transaction, options: &Options) -> Result<()> {
+    *balance = match balance.checked_add(amount) {
+        Some(sum) => {
+            // The sum was rounded if taking the balance back off doesn't
+            // give the amount.
+            if options.strict_precision && sum.checked_sub(*balance) != Some(amount) {
+                bail!(precision_loss(*balance, amount, tx));
+            }
+
+            sum
+        },
+        None if options.saturating => balance.saturating_add(amount),
+        None => bail!(ErrorKind::Overflow.error(format!(
+            "transaction {} overflows a balance of {}",
+            tx.tx, balance
+        )))
+    };
+
+    Ok(())
+}
+
+/// Subtracts an amount from a balance, saturating if asked to and
+/// otherwise failing rather than overflowing.
+fn sub(balance: &mut Decimal, amount: Decimal, tx: &Transaction, options: &Options) -> Result<()> {
+    *balance = match balance.checked_sub(amount) {
+        Some(difference) => {
+            if options.strict_precision && balance.checked_sub(difference) != Some(amount) {
+                bail!(precision_loss(*balance, -amount, tx));
+            }
+
+            difference
+        },
+        None if options.saturating => balance.saturating_sub(amount),
+        None => bail!(ErrorKind::Overflow.error(format!(
+            "transaction {} overflows a balance of {}",
+            tx.tx, balance
+        )))
+    };
+
+    Ok(())
+}
+
+/// The error for a transaction whose change to a balance would be rounded.
+fn precision_loss(balance: Decimal, change: Decimal, tx: &Transaction) -> ProcessError {
+    ErrorKind::PrecisionLoss.error(format!(
+        "transaction {} for client {} would round a balance of {balance} changed by {change}, \
+         losing precision",
+        tx.tx, tx.client
+    ))
+}
+
+/// Returns how much of `amount` a resolve or chargeback may release from
+/// `held` without taking it negative, recording how far it would have
+/// in `clamped`. Fails instead when strict, and releases everything when
+/// negative held funds are allowed.
+fn guard_held(
+    held: Decimal,
+    amount: Decimal,
+    tx: &Transaction,
+    options: &Options,
+    clamped: &mut Option<Decimal>
+) -> Result<Decimal> {
+    if options.allow_negative_held || amount <= held {
+        return Ok(amount);
+    }
+
+    if options.strict {
+        bail!(ErrorKind::NegativeHeld.error(format!(
+            "transaction {} would release {} from held funds of {}",
+            tx.tx, amount, held
+        )));
+    }
+
+    let release = held.max(Decimal::ZERO);
+
+    *clamped = Some(amount - release);
+
+    Ok(release)
+}
+
+/// What a dispute, resolve or chargeback refers to.
+enum Reference {
+    /// A disputable deposit of this amount.
+    Deposit(Decimal),
+
+    /// A disputable withdrawal of this amount.
+    Withdrawal(Decimal)
+}
+
+/// Looks up the transaction a dispute, resolve or chargeback refers to,
+/// or how to skip it if there's nothing that can be disputed.
+fn reference(
+    tx: &Transaction,
+    deposits: &Deposits,
+    withdrawals: &Deposits,
+    options: &Options
+) -> Result<Result<Reference, Outcome>> {
+    // Refuse to guess which transaction is meant, if either could be.
+    if tx.kind == TransactionType::Dispute
+        && options.disputable == Disputable::Both
+        && deposits.contains(&tx.tx)
+        && withdrawals.contains(&tx.tx)
+    {
+        if options.strict {
+            bail!(ErrorKind::AmbiguousTx.error(format!(
+                "transaction {} refers to both a deposit and a withdrawal",
+                tx.tx
+            )));
+        }
+
+        return Ok(Err(Outcome::Skipped(Skip::Ambiguous)));
+    }
+
+    if options.disputable.deposits()
+        && let Some(deposit) = deposits.get(&tx.tx)
+    {
+        return Ok(Ok(Reference::Deposit(deposit.required_amount()?)));
+    }
+
+    if options.disputable.withdrawals()
+        && let Some(withdrawal) = withdrawals.get(&tx.tx)
+    {
+        return Ok(Ok(Reference::Withdrawal(withdrawal.required_amount()?)));
+    }
+
+    // The transaction exists, it just can't be disputed.
+    if deposits.contains(&tx.tx) || withdrawals.contains(&tx.tx) {
+        return Ok(Err(Outcome::Skipped(match tx.kind {
+            TransactionType::Dispute => Skip::UnknownTransaction,
+            _ => Skip::NotDisputed
+        })));
+    }
+
+    if options.strict_references {
+        bail!(ErrorKind::UnknownReference.error(format!(
+            "{} references transaction {}, which doesn't exist",
+            tx.kind, tx.tx
+        )));
+    }
+
+    Ok(Err(Outcome::Skipped(Skip::UnknownTransaction)))
+}
+
+/// Processes transactions.
+pub fn process<T>(txs: T, options: &Options) -> Result<HashMap<u16, ClientData>>
+where
+    T: IntoIterator<Item = Result<Transaction>>
+{
+    process_with(txs, options, &mut Diagnostics::silent())
+}
+
+/// Processes transactions that are already in memory.
+pub fn process_slice(txs: &[Transaction], options: &Options) -> Result<HashMap<u16, ClientData>> {
+    process(txs.iter().cloned().map(Ok), options)
+}
+
+/// Processes transactions, reporting skipped transactions as verbose
+/// diagnostics and explaining the traced transaction, if any.
+pub fn process_with<T, W>(
+    txs: T,
+    options: &Options,
+    diagnostics: &mut Diagnostics<W>
+) -> Result<HashMap<u16, ClientData>>
+where
+    T: IntoIterator<Item = Result<Transaction>>,
+    W: Write
+{
+    let mut engine = Engine::new(options.clone());
+
+    run(&mut engine, txs, diagnostics, None)?;
+
+    Ok(engine.into_clients())
+}
+
+/// Applies transactions to an engine, which may already hold client
+/// data, reporting diagnostics like `process_with` and writing
+/// checkpoints if given any.
+pub fn run<T, W>(
+    engine: &mut Engine,
+    txs: T,
+    diagnostics: &mut Diagnostics<W>,
+    checkpoints: Option<&mut Checkpoints>
+) -> Result<()>
+where
+    T: IntoIterator<Item = Result<Transaction>>,
+    W: Write
+{
+    run_collecting(engine, txs, diagnostics, checkpoints, None, OnError::Abort)
+}
+
+/// Applies transactions like `run`, but collects the errors of rows that
+/// fail to parse or apply and carries on, reporting each as a verbose
+/// diagnostic. A transaction that fails partway through, say overflowing
+/// `total` after crediting `available`, may leave a balance partially
+/// updated. Errors unrelated to a transaction, like failing to write a
+/// checkpoint, still fail the run.
+pub fn run_lenient<T, W>(
+    engine: &mut Engine,
+    txs: T,
+    diagnostics: &mut Diagnostics<W>,
+    checkpoints: Option<&mut Checkpoints>
+) -> Result<Vec<ProcessError>>
+where
+    T: IntoIterator<Item = Result<Transaction>>,
+    W: Write
+{
+    run_on_error(engine, txs, diagnostics, checkpoints, OnError::Continue)
+}
+
+/// Applies transactions like `run`, handling those that fail to parse or
+/// apply by the policy. Unless aborting, the errors are collected and
+/// reported like `run_lenient`, and clients abandoned by `SkipClient` are
+/// left in `Engine::errored`.
+pub fn run_on_error<T, W>(
+    engine: &mut Engine,
+    txs: T,
+    diagnostics: &mut Diagnostics<W>,
+    checkpoints: Option<&mut Checkpoints>,
+    on_error: OnError
+) -> Result<Vec<ProcessError>>
+where
+    T: IntoIterator<Item = Result<Transaction>>,
+    W: Write
+{
+    let mut errors = Vec::new();
+    let collected = (on_error != OnError::Abort).then_some(&mut errors);
+
+    run_collecting(engine, txs, diagnostics, checkpoints, collected, on_error)?;
+
+    Ok(errors)
+}
+
+/// Processes transactions leniently like `run_lenient`, returning the
+/// client data and the errors.
+pub fn process_lenient<T, W>(
+    txs: T,
+    options: &Options,
+    diagnostics: &mut Diagnostics<W>
+) -> Result<(HashMap<u16, ClientData>, Vec<ProcessError>)>
+where
+    T: IntoIterator<Item = Result<Transaction>>,
+    W: Write
+{
+    let mut engine = Engine::new(options.clone());
+    let errors = run_lenient(&mut engine, txs, diagnostics, None)?;
+
+    Ok((engine.into_clients(), errors))
+}
+
+/// Applies transactions, failing on the first error unless `errors` is
+/// given to collect them in, and abandoning the client of a transaction
+/// that fails to apply if asked to.
+fn run_collecting<T, W>(
+    engine: &mut Engine,
+    txs: T,
+    diagnostics: &mut Diagnostics<W>,
+    mut checkpoints: Option<&mut Checkpoints>,
+    mut errors: Option<&mut Vec<ProcessError>>,
+    on_error: OnError
+) -> Result<()>
+where
+    T: IntoIterator<Item = Result<Transaction>>,
+    W: Write
+{
+    let mut conservation = engine
+        .options
+        .check_conservation
+        .then(|| Conservation::new(&engine.options).starting_from(&engine.clients))
+        .transpose()?;
+
+    let lenient = errors.is_some();
+
+    // Collect an error if we're lenient, otherwise fail.
+    let mut recover =
+        |error: anyhow::Error, diagnostics: &mut Diagnostics<W>| match errors.as_deref_mut() {
+            Some(errors) => {
+                let error = ProcessError::from(error);
+
+                diagnostics.verbose(format_args!("error ({}): {error}", error.kind));
+                errors.push(error);
+
+                Ok(())
+            },
+            None => Err(error)
+        };
+
+    let sampling = diagnostics.is_sampling();
+
+    // Read line by line to minimize our memory footprint.
+    for tx in txs {
+        let tx = match tx {
+            Ok(tx) => tx,
+            Err(error) => {
+                recover(error, diagnostics)?;
+                continue;
+            }
+        };
+        let (client, id, kind) = (tx.client, tx.tx, tx.kind);
+        let recorded = conservation
+            .as_ref()
+            .map(|_| tx.clone());
+
+        // Explain what the transaction did if it's being traced, and keep
+        // its effects if it's being sampled.
+        let mut entries = Vec::new();
+        let outcome = match engine.apply_audited(tx, &mut |entry| {
+            diagnostics.explain(entry);
+
+            if sampling {
+                entries.push(entry.clone());
+            }
+        }) {
+            Ok(outcome) => outcome,
+            Err(error) => {
+                recover(error, diagnostics)?;
+
+                if on_error == OnError::SkipClient {
+                    engine.abandon(client);
+                    diagnostics.verbose(format_args!(
+                        "abandoned client {client} after transaction {id} failed"
+                    ));
+                }
+
+                continue;
+            }
+        };
+
+        if sampling {
+            diagnostics.offer(entries);
+        }
+
+        if let Some(excess) = engine.clamped() {
+            diagnostics.print(format_args!(
+                "transaction {id} for client {client} would have taken held funds {excess} \
+                 below zero, so they were clamped at zero"
+            ));
+        }
+
+        if let (Some(conservation), Some(tx)) = (&mut conservation, &recorded) {
+            conservation.record(tx, outcome)?;
+        }
+
+        if let Outcome::Skipped(skip) = outcome {
+            diagnostics.verbose(format_args!(
+                "skipped transaction {id} for client {client}: {skip}"
+            ));
+        }
+
+        // Record refused funds where operators will see them.
+        if engine.options.reject_after_lock && outcome == Outcome::Skipped(Skip::Locked) {
+            let error = ErrorKind::LockedClient.error(format!(
+                "{kind} {id} for client {client} was rejected because the client is locked"
+            ));
+
+            if lenient {
+                recover(error.into(), diagnostics)?;
+            } else {
+                diagnostics.print(error);
+            }
+        }
+
+        // Settling a dispute from before a snapshot leaves it open, with
+        // its funds held forever, so it's worth more than a skip.
+        if engine.resumed
+            && outcome == Outcome::Skipped(Skip::UnknownTransaction)
+            && matches!(kind, TransactionType::Resolve | TransactionType::Chargeback)
+        {
+            diagnostics.print(format_args!(
+                "{kind} of transaction {id} for client {client} refers to a transaction \
+                 unknown since the snapshot was loaded"
+            ));
+        }
+
+        if let Some(checkpoints) = checkpoints.as_deref_mut() {
+            checkpoints.record(engine)?;
+        }
+    }
+
+    // Make sure no funds appeared or vanished if asked to.
+    if let Some(conservation) = conservation {
+        conservation.check(&engine.clients)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn deposit_increases_available_and_total() {
+        let txs = vec![Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(10.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        }];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(10.0));
+        assert_eq!(client.total, dec!(10.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn withdrawal_reduces_available_and_total() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(2.0));
+        assert_eq!(client.total, dec!(2.0));
+    }
+
+    #[test]
+    fn withdrawal_fails_if_insufficient_funds() {
+        let txs = vec![Transaction {
+            kind:         TransactionType::Withdrawal,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(10.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        }];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.total, dec!(0.0));
+    }
+
+    #[test]
+    fn dispute_moves_funds_to_held() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.held, dec!(5.0));
+        assert_eq!(client.total, dec!(5.0));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn dispute_twice_does_nothing_the_second_time() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.held, dec!(5.0));
+        assert_eq!(client.total, dec!(5.0));
+    }
+
+    #[test]
+    fn dispute_is_ignored_if_funds_already_withdrawn() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(0.0));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn resolve_returns_held_to_available() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(7.5)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Resolve,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(7.5));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(7.5));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn resolve_ignored_if_tx_not_disputed() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Resolve,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(5.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(5.0));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn chargeback_removes_held_and_locks() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(0.0));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn chargeback_ignored_if_tx_not_disputed() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(5.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(5.0));
+        assert!(!client.locked);
+    }
+
+    fn two_chargebacks() -> Vec<Transaction> {
+        let mut txs = Vec::new();
+
+        for tx in [1, 2] {
+            txs.push(Transaction {
+                kind: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(dec!(5.0)),
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            });
+        }
+
+        for tx in [1, 2] {
+            for kind in [TransactionType::Dispute, TransactionType::Chargeback] {
+                txs.push(Transaction {
+                    kind,
+                    client: 1,
+                    tx,
+                    amount: None,
+                    counterparty: None,
+                    seq: None,
+                    timestamp: None
+                });
+            }
+        }
+
+        txs
+    }
+
+    #[test]
+    fn locked_accounts_skip_later_disputes() {
+        let mut engine = Engine::new(Options::default());
+        let outcomes = two_chargebacks()
+            .into_iter()
+            .map(|tx| engine.apply(tx).unwrap())
+            .collect::<Vec<_>>();
+        let client = &engine.clients()[&1];
+
+        // The first chargeback locks the client, so the second deposit
+        // is never disputed.
+        assert_eq!(outcomes[3], Outcome::Applied);
+        assert_eq!(outcomes[4], Outcome::Skipped(Skip::Locked));
+        assert_eq!(outcomes[5], Outcome::Skipped(Skip::Locked));
+        assert_eq!(client.available, dec!(5.0));
+        assert_eq!(client.total, dec!(5.0));
+    }
+
+    #[test]
+    fn locked_accounts_fail_later_disputes_if_strict() {
+        let options = Options {
+            strict: true,
+            ..Options::default()
+        };
+        let err = process_slice(&two_chargebacks(), &options).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "dispute of transaction 2 for client 1 arrived after the client was locked"
+        );
+    }
+
+    #[test]
+    fn locked_accounts_can_allow_later_disputes() {
+        let options = Options {
+            strict: true,
+            locked_blocks: LockedBlocks::WithdrawalsOnly,
+            ..Options::default()
+        };
+        let clients = process_slice(&two_chargebacks(), &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.total, dec!(0.0));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn locked_account_ignores_future_transactions() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.total, dec!(0.0));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn handles_multiple_clients_independently() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(4.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       2,
+                tx:           3,
+                amount:       Some(dec!(20.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       2,
+                tx:           4,
+                amount:       Some(dec!(15.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+
+        let c1 = clients.get(&1).unwrap();
+        assert_eq!(c1.available, dec!(6.0));
+        assert_eq!(c1.total, dec!(6.0));
+        assert_eq!(c1.held, dec!(0.0));
+        assert!(!c1.locked);
+
+        let c2 = clients.get(&2).unwrap();
+        assert_eq!(c2.available, dec!(5.0));
+        assert_eq!(c2.total, dec!(5.0));
+        assert_eq!(c2.held, dec!(0.0));
+        assert!(!c2.locked);
+    }
+
+    #[test]
+    fn overdraft_withdrawal_allows_negative_available() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(8.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let options = Options {
+            allow_overdraft: true,
+            ..Options::default()
+        };
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(-3.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(-3.0));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn overdraft_withdrawal_does_not_touch_held() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(2.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let options = Options {
+            allow_overdraft: true,
+            ..Options::default()
+        };
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(-2.0));
+        assert_eq!(client.held, dec!(5.0));
+        assert_eq!(client.total, dec!(3.0));
+    }
+
+    #[test]
+    fn zero_amount_deposit_is_accepted_by_default() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(0.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.total, dec!(0.0));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn zero_amount_deposit_is_skipped_and_not_disputable() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(0.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let options = Options {
+            skip_zero_amounts: true,
+            ..Options::default()
+        };
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.total, dec!(0.0));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn amounts_are_summed_without_float_error() {
+        let txs = (1..=3)
+            .map(|tx| Transaction {
+                kind: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(dec!(0.1)),
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            })
+            .collect::<Vec<_>>();
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.3));
+        assert_eq!(client.total, dec!(0.3));
+    }
+
+    #[test]
+    fn round_mode_applies_on_ingestion() {
+        let txs = vec![Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(1.23455)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        }];
+
+        let options = Options {
+            round_mode: Some(RoundMode::Truncate),
+            ..Options::default()
+        };
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.total, dec!(1.2345));
+    }
+
+    #[test]
+    fn tx_type_filter_ignores_disputes() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(2.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let options = Options {
+            tx_types: Some(vec![TransactionType::Deposit, TransactionType::Withdrawal]),
+            ..Options::default()
+        };
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(8.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(8.0));
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn evicted_deposit_cannot_be_disputed() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(1.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(2.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let options = Options {
+            max_deposits: Some(1),
+            ..Options::default()
+        };
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(1.0));
+        assert_eq!(client.held, dec!(2.0));
+    }
+
+    #[test]
+    fn apply_reports_skips() {
+        let mut engine = Engine::new(Options::default());
+
+        let outcome = engine
+            .apply(Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(1.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+        assert_eq!(outcome, Outcome::Skipped(Skip::InsufficientFunds));
+
+        let outcome = engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(1.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+        assert_eq!(outcome, Outcome::Applied);
+    }
+
+    #[test]
+    fn verbose_reports_skipped_transactions() {
+        let txs = vec![Transaction {
+            kind:         TransactionType::Withdrawal,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(1.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        }];
+
+        let mut diagnostics = Diagnostics::new(Vec::new(), true, false);
+
+        process_with(
+            txs.into_iter().map(Ok),
+            &Options::default(),
+            &mut diagnostics
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(diagnostics.into_inner()).unwrap(),
+            "skipped transaction 1 for client 1: insufficient funds\n"
+        );
+    }
+
+    #[test]
+    fn quiet_takes_precedence_over_verbose() {
+        let txs = vec![Transaction {
+            kind:         TransactionType::Withdrawal,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(1.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        }];
+
+        let mut diagnostics = Diagnostics::new(Vec::new(), true, true);
+
+        process_with(
+            txs.into_iter().map(Ok),
+            &Options::default(),
+            &mut diagnostics
+        )
+        .unwrap();
+
+        assert!(diagnostics.into_inner().is_empty());
+    }
+
+    #[test]
+    fn transfer_moves_funds_between_clients() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Transfer,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(4.0)),
+                counterparty: Some(2),
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+
+        let c1 = clients.get(&1).unwrap();
+        assert_eq!(c1.available, dec!(6.0));
+        assert_eq!(c1.total, dec!(6.0));
+
+        let c2 = clients.get(&2).unwrap();
+        assert_eq!(c2.available, dec!(4.0));
+        assert_eq!(c2.total, dec!(4.0));
+    }
+
+    #[test]
+    fn transfer_with_insufficient_funds_changes_nothing() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       2,
+                tx:           2,
+                amount:       Some(dec!(1.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Transfer,
+                client:       1,
+                tx:           3,
+                amount:       Some(dec!(4.0)),
+                counterparty: Some(2),
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+
+        let c1 = clients.get(&1).unwrap();
+        assert_eq!(c1.available, dec!(3.0));
+        assert_eq!(c1.total, dec!(3.0));
+
+        let c2 = clients.get(&2).unwrap();
+        assert_eq!(c2.available, dec!(1.0));
+        assert_eq!(c2.total, dec!(1.0));
+    }
+
+    #[test]
+    fn overflowing_transfer_changes_nothing() {
+        let full = ClientData {
+            available: Decimal::MAX,
+            total: Decimal::MAX,
+            ..ClientData::default()
+        };
+        let funded = ClientData {
+            available: dec!(5.0),
+            total: dec!(5.0),
+            ..ClientData::default()
+        };
+        let mut engine = Engine::with_clients(
+            Options::default(),
+            HashMap::from([(1, funded.clone()), (2, full.clone())])
+        );
+        let result = engine.apply(Transaction {
+            kind:         TransactionType::Transfer,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(1.0)),
+            counterparty: Some(2),
+            seq:          None,
+            timestamp:    None
+        });
+
+        assert!(result.is_err());
+        assert_eq!(engine.clients()[&1], funded);
+        assert_eq!(engine.clients()[&2], full);
+    }
+
+    #[test]
+    fn transfer_to_locked_counterparty_is_skipped() {
+        let mut engine = Engine::new(Options::default());
+
+        for tx in [
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       2,
+                tx:           2,
+                amount:       Some(dec!(1.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       2,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       2,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            }
+        ] {
+            engine.apply(tx).unwrap();
+        }
+
+        let outcome = engine
+            .apply(Transaction {
+                kind:         TransactionType::Transfer,
+                client:       1,
+                tx:           3,
+                amount:       Some(dec!(2.0)),
+                counterparty: Some(2),
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::Skipped(Skip::Locked));
+        assert_eq!(engine.clients()[&1].available, dec!(5.0));
+        assert_eq!(engine.clients()[&2].total, dec!(0.0));
+    }
+
+    #[test]
+    fn locked_account_accepts_deposits_when_only_withdrawals_blocked() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           3,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           4,
+                amount:       Some(dec!(1.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           3,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let options = Options {
+            locked_blocks: LockedBlocks::WithdrawalsOnly,
+            ..Options::default()
+        };
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(3.0));
+        assert_eq!(client.held, dec!(5.0));
+        assert_eq!(client.total, dec!(8.0));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn locked_blocks_all_by_default() {
+        assert!(LockedBlocks::default().blocks(TransactionType::Deposit));
+        assert!(LockedBlocks::default().blocks(TransactionType::Dispute));
+        assert!(!LockedBlocks::WithdrawalsOnly.blocks(TransactionType::Deposit));
+        assert!(!LockedBlocks::WithdrawalsOnly.blocks(TransactionType::Resolve));
+        assert!(LockedBlocks::WithdrawalsOnly.blocks(TransactionType::Withdrawal));
+        assert!(LockedBlocks::WithdrawalsOnly.blocks(TransactionType::Transfer));
+    }
+
+    fn duplicate_then_dispute() -> Vec<Transaction> {
+        vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ]
+    }
+
+    #[test]
+    fn duplicate_policy_first_keeps_original() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::First,
+            ..Options::default()
+        };
+        let clients = process_slice(&duplicate_then_dispute(), &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(3.0));
+        assert_eq!(client.held, dec!(5.0));
+        assert_eq!(client.total, dec!(8.0));
+    }
+
+    #[test]
+    fn duplicate_policy_last_keeps_latest() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::Last,
+            ..Options::default()
+        };
+        let clients = process_slice(&duplicate_then_dispute(), &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(5.0));
+        assert_eq!(client.held, dec!(3.0));
+        assert_eq!(client.total, dec!(8.0));
+    }
+
+    #[test]
+    fn duplicate_policy_reject_errors() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::Reject,
+            ..Options::default()
+        };
+        let err = process_slice(&duplicate_then_dispute(), &options).unwrap_err();
+
+        assert_eq!(err.to_string(), "transaction 1 is a duplicate deposit");
+    }
+
+    #[test]
+    fn duplicate_policy_reject_spots_evicted_deposits() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::Reject,
+            max_deposits: Some(1),
+            ..Options::default()
+        };
+        let txs = [1, 2, 1].map(|tx| Transaction {
+            kind: TransactionType::Deposit,
+            client: 1,
+            tx,
+            amount: Some(dec!(1.0)),
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        });
+        let err = process_slice(&txs, &options).unwrap_err();
+
+        assert_eq!(err.to_string(), "transaction 1 is a duplicate deposit");
+    }
+
+    fn reused_after_resolve() -> Vec<Transaction> {
+        let mut txs = vec![Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(10.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        }];
+
+        for kind in [TransactionType::Dispute, TransactionType::Resolve] {
+            txs.push(Transaction {
+                kind,
+                client: 1,
+                tx: 1,
+                amount: None,
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            });
+        }
+
+        txs.push(Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(4.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        });
+        txs.push(Transaction {
+            kind:         TransactionType::Dispute,
+            client:       1,
+            tx:           1,
+            amount:       None,
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        });
+        txs
+    }
+
+    #[test]
+    fn reused_ids_follow_the_duplicate_policy_after_resolving() {
+        let client = |policy| {
+            let options = Options {
+                duplicate_policy: policy,
+                ..Options::default()
+            };
+
+            process_slice(&reused_after_resolve(), &options).map(|clients| clients[&1].clone())
+        };
+
+        // The reuse is a new deposit, so the second dispute holds it.
+        let last = client(DuplicatePolicy::Last).unwrap();
+
+        assert_eq!(last.available, dec!(10.0));
+        assert_eq!(last.held, dec!(4.0));
+        assert_eq!(last.total, dec!(14.0));
+
+        // The second dispute still refers to the original deposit.
+        let first = client(DuplicatePolicy::First).unwrap();
+
+        assert_eq!(first.available, dec!(4.0));
+        assert_eq!(first.held, dec!(10.0));
+        assert_eq!(first.total, dec!(14.0));
+
+        assert_eq!(
+            client(DuplicatePolicy::Reject)
+                .unwrap_err()
+                .to_string(),
+            "transaction 1 is a duplicate deposit"
+        );
+        assert_eq!(
+            client(DuplicatePolicy::Idempotent)
+                .unwrap_err()
+                .to_string(),
+            "transaction 1 conflicts with an earlier deposit of 10.0 for client 1"
+        );
+    }
+
+    #[test]
+    fn reused_ids_never_replace_an_open_dispute() {
+        let mut txs = reused_after_resolve();
+
+        // Reuse the id while the first dispute is still open.
+        txs.swap(2, 3);
+        txs[4].kind = TransactionType::Resolve;
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(14.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(14.0));
+    }
+
+    #[test]
+    fn max_amount_skips_larger_deposits() {
+        let mut engine = Engine::new(Options {
+            max_amount: Some(dec!(100.0)),
+            ..Options::default()
+        });
+
+        let outcome = engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(1000.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+        assert_eq!(outcome, Outcome::Skipped(Skip::OverMaxAmount));
+
+        let outcome = engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(100.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+        assert_eq!(outcome, Outcome::Applied);
+
+        assert_eq!(engine.clients()[&1].total, dec!(100.0));
+    }
+
+    #[test]
+    fn max_amount_errors_when_strict() {
+        let txs = vec![Transaction {
+            kind:         TransactionType::Withdrawal,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(100.01)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        }];
+
+        let options = Options {
+            max_amount: Some(dec!(100.0)),
+            strict: true,
+            ..Options::default()
+        };
+        let err = process_slice(&txs, &options).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "transaction 1 has amount 100.01 over the maximum of 100.0"
+        );
+    }
+
+    #[test]
+    fn missing_amount_is_coerced_to_zero() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let options = Options {
+            coerce_missing_amount: true,
+            ..Options::default()
+        };
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.total, dec!(0.0));
+    }
+
+    #[test]
+    fn coerced_missing_amount_is_skipped_with_zero_amounts() {
+        let mut engine = Engine::new(Options {
+            coerce_missing_amount: true,
+            skip_zero_amounts: true,
+            ..Options::default()
+        });
+
+        let outcome = engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::Skipped(Skip::ZeroAmount));
+        assert!(engine.clients().is_empty());
+    }
+
+    #[test]
+    fn apply_with_reports_changed_clients() {
+        let mut engine = Engine::new(Options::default());
+        let mut changes = Vec::new();
+        let mut on_change = |id, client: &ClientData| changes.push((id, client.total));
+
+        for tx in [
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           3,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Transfer,
+                client:       1,
+                tx:           3,
+                amount:       Some(dec!(2.0)),
+                counterparty: Some(2),
+                seq:          None,
+                timestamp:    None
+            }
+        ] {
+            engine
+                .apply_with(tx, &mut on_change)
+                .unwrap();
+        }
+
+        assert_eq!(
+            changes,
+            vec![(1, dec!(5.0)), (1, dec!(3.0)), (2, dec!(2.0))]
+        );
+    }
+
+    fn deposits_near_the_limit() -> [Transaction; 2] {
+        [1, 2].map(|tx| Transaction {
+            kind: TransactionType::Deposit,
+            client: 1,
+            tx,
+            amount: Some(Decimal::MAX - dec!(1)),
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        })
+    }
+
+    #[test]
+    fn overflowing_balances_fail() {
+        let err = process_slice(&deposits_near_the_limit(), &Options::default()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "transaction 2 overflows a balance of {}",
+                Decimal::MAX - dec!(1)
+            )
+        );
+    }
+
+    #[test]
+    fn saturating_balances_clamp() {
+        let options = Options {
+            saturating: true,
+            ..Options::default()
+        };
+        let mut txs = deposits_near_the_limit().to_vec();
+
+        txs.push(Transaction {
+            kind:         TransactionType::Withdrawal,
+            client:       1,
+            tx:           3,
+            amount:       Some(dec!(1)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        });
+
+        let clients = process_slice(&txs, &options).unwrap();
+        let client = clients.get(&1).unwrap();
+
+        assert_eq!(client.available, Decimal::MAX - dec!(1));
+        assert_eq!(client.total, Decimal::MAX - dec!(1));
+    }
+
+    #[test]
+    fn strict_precision_flags_rounded_balances() {
+        let options = Options {
+            strict_precision: true,
+            ..Options::default()
+        };
+        let deposit = |tx, amount| Transaction {
+            kind: TransactionType::Deposit,
+            client: 1,
+            tx,
+            amount: Some(amount),
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        };
+
+        // 28 significant digits fit here, so nothing is lost.
+        let exact = [
+            deposit(1, dec!(98765432109876543210987654.32)),
+            deposit(2, dec!(0.01))
+        ];
+
+        assert_eq!(
+            process_slice(&exact, &options).unwrap()[&1].total,
+            dec!(98765432109876543210987654.33)
+        );
+
+        // A 29th doesn't, so it's rounded away.
+        let lossy = [
+            deposit(1, dec!(98765432109876543210987654.32)),
+            deposit(2, dec!(0.001))
+        ];
+        let clients = process_slice(&lossy, &Options::default()).unwrap();
+
+        assert_eq!(clients[&1].total, dec!(98765432109876543210987654.32));
+
+        let error = ProcessError::from(process_slice(&lossy, &options).unwrap_err());
+
+        assert_eq!(error.kind, ErrorKind::PrecisionLoss);
+        assert_eq!(
+            error.message,
+            "transaction 2 for client 1 would round a balance of \
+             98765432109876543210987654.32 changed by 0.001, losing precision"
+        );
+    }
+
+    fn disputed_deposit_and_withdrawal() -> Vec<Transaction> {
+        let mut txs = [(1, dec!(10.0)), (3, dec!(10.0))]
+            .map(|(tx, amount)| Transaction {
+                kind: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(amount),
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            })
+            .to_vec();
+
+        txs.push(Transaction {
+            kind:         TransactionType::Withdrawal,
+            client:       1,
+            tx:           2,
+            amount:       Some(dec!(4.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        });
+
+        for tx in [1, 2] {
+            txs.push(Transaction {
+                kind: TransactionType::Dispute,
+                client: 1,
+                tx,
+                amount: None,
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            });
+        }
+
+        txs
+    }
+
+    /// Returns the outcomes of disputing the deposit and the withdrawal,
+    /// and the client's resulting available, held and total funds.
+    fn dispute_outcomes(disputable: Disputable) -> (Vec<Outcome>, [Decimal; 3]) {
+        let mut engine = Engine::new(Options {
+            disputable,
+            ..Options::default()
+        });
+        let outcomes = disputed_deposit_and_withdrawal()
+            .into_iter()
+            .map(|tx| engine.apply(tx).unwrap())
+            .skip(3)
+            .collect();
+        let client = &engine.clients()[&1];
+
+        (outcomes, [client.available, client.held, client.total])
+    }
+
+    #[test]
+    fn only_deposits_are_disputable_by_default() {
+        let (outcomes, funds) = dispute_outcomes(Disputable::default());
+
+        assert_eq!(
+            outcomes,
+            vec![Outcome::Applied, Outcome::Skipped(Skip::UnknownTransaction)]
+        );
+        assert_eq!(funds, [dec!(6.0), dec!(10.0), dec!(16.0)]);
+    }
+
+    #[test]
+    fn withdrawals_can_be_disputable() {
+        let (outcomes, funds) = dispute_outcomes(Disputable::Withdrawals);
+
+        assert_eq!(
+            outcomes,
+            vec![Outcome::Skipped(Skip::UnknownTransaction), Outcome::Applied]
+        );
+        assert_eq!(funds, [dec!(16.0), dec!(4.0), dec!(20.0)]);
+    }
+
+    #[test]
+    fn deposits_and_withdrawals_can_both_be_disputable() {
+        let (outcomes, funds) = dispute_outcomes(Disputable::Both);
+
+        assert_eq!(outcomes, vec![Outcome::Applied, Outcome::Applied]);
+        assert_eq!(funds, [dec!(6.0), dec!(14.0), dec!(20.0)]);
+    }
+
+    #[test]
+    fn withdrawal_disputes_resolve_and_charge_back() {
+        let options = Options {
+            disputable: Disputable::Withdrawals,
+            check_conservation: true,
+            ..Options::default()
+        };
+        let settle = |kind| {
+            let mut txs = disputed_deposit_and_withdrawal();
+
+            txs.push(Transaction {
+                kind,
+                client: 1,
+                tx: 2,
+                amount: None,
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            });
+
+            process_slice(&txs, &options).unwrap()[&1].clone()
+        };
+
+        // Resolving lets the withdrawal stand.
+        let resolved = settle(TransactionType::Resolve);
+
+        assert_eq!(resolved.available, dec!(16.0));
+        assert_eq!(resolved.held, dec!(0.0));
+        assert_eq!(resolved.total, dec!(16.0));
+        assert!(!resolved.locked);
+
+        // Charging back returns the withdrawn funds.
+        let charged_back = settle(TransactionType::Chargeback);
+
+        assert_eq!(charged_back.available, dec!(20.0));
+        assert_eq!(charged_back.held, dec!(0.0));
+        assert_eq!(charged_back.total, dec!(20.0));
+        assert!(charged_back.locked);
+    }
+
+    #[test]
+    fn only_touched_clients_are_kept() {
+        let loaded = [1, 2, 3]
+            .map(|id| {
+                (
+                    id,
+                    ClientData {
+                        available: dec!(5.0),
+                        total: dec!(5.0),
+                        ..ClientData::default()
+                    }
+                )
+            })
+            .into();
+        let mut engine = Engine::with_clients(Options::default(), loaded);
+
+        for tx in [
+            Transaction {
+                kind:         TransactionType::Transfer,
+                client:       2,
+                tx:           1,
+                amount:       Some(dec!(1.0)),
+                counterparty: Some(4),
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       3,
+                tx:           2,
+                amount:       Some(dec!(100.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            }
+        ] {
+            engine.apply(tx).unwrap();
+        }
+
+        // The skipped withdrawal doesn't touch client 3.
+        let clients = engine.into_touched_clients();
+        let mut ids = clients
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        ids.sort_unstable();
+
+        assert_eq!(ids, vec![2, 4]);
+        assert_eq!(clients[&2].available, dec!(4.0));
+    }
+
+    #[test]
+    fn batches_return_only_changed_clients() {
+        let mut engine = Engine::new(Options::default());
+
+        engine
+            .apply_batch([1, 2, 3].map(|client| Transaction {
+                kind: TransactionType::Deposit,
+                client,
+                tx: u32::from(client),
+                amount: Some(dec!(5.0)),
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            }))
+            .unwrap();
+
+        let changed = engine
+            .apply_batch([
+                Transaction {
+                    kind:         TransactionType::Withdrawal,
+                    client:       1,
+                    tx:           4,
+                    amount:       Some(dec!(2.0)),
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                Transaction {
+                    kind:         TransactionType::Deposit,
+                    client:       3,
+                    tx:           5,
+                    amount:       Some(dec!(1.0)),
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                Transaction {
+                    kind:         TransactionType::Withdrawal,
+                    client:       2,
+                    tx:           6,
+                    amount:       Some(dec!(100.0)),
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                }
+            ])
+            .unwrap();
+
+        // The skipped withdrawal leaves client 2 unchanged.
+        assert_eq!(changed.len(), 2);
+        assert_eq!(changed[&1].available, dec!(3.0));
+        assert_eq!(changed[&3].available, dec!(6.0));
+    }
+
+    #[test]
+    fn balances_are_normalized() {
+        let options = Options {
+            normalize_scale: Some(4),
+            ..Options::default()
+        };
+        let mut engine = Engine::new(options);
+
+        for (tx, amount) in [(1, dec!(1.00000000)), (2, dec!(2.5)), (3, dec!(0.000049))] {
+            engine
+                .apply(Transaction {
+                    kind: TransactionType::Deposit,
+                    client: 1,
+                    tx,
+                    amount: Some(amount),
+                    counterparty: None,
+                    seq: None,
+                    timestamp: None
+                })
+                .unwrap();
+        }
+
+        // The exact balance is kept while processing.
+        assert_eq!(engine.clients()[&1].total.scale(), 8);
+
+        let client = &engine.into_clients()[&1];
+
+        assert_eq!(client.total.to_string(), "3.5000");
+        assert_eq!(client.available.to_string(), "3.5000");
+        assert_eq!(client.held.to_string(), "0.0000");
+    }
+
+    #[test]
+    fn dispute_cycles_are_capped() {
+        let mut engine = Engine::new(Options {
+            max_dispute_cycles: Some(2),
+            ..Options::default()
+        });
+        let tx = |kind, amount| Transaction {
+            kind,
+            client: 1,
+            tx: 1,
+            amount,
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        };
+
+        engine
+            .apply(tx(TransactionType::Deposit, Some(dec!(10.0))))
+            .unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(
+                engine
+                    .apply(tx(TransactionType::Dispute, None))
+                    .unwrap(),
+                Outcome::Applied
+            );
+            assert_eq!(
+                engine
+                    .apply(tx(TransactionType::Resolve, None))
+                    .unwrap(),
+                Outcome::Applied
+            );
+        }
+
+        assert_eq!(
+            engine
+                .apply(tx(TransactionType::Dispute, None))
+                .unwrap(),
+            Outcome::Skipped(Skip::TooManyDisputes)
+        );
+        assert_eq!(engine.clients()[&1].available, dec!(10.0));
+        assert_eq!(engine.clients()[&1].held, dec!(0.0));
+    }
+
+    #[test]
+    fn open_disputes_are_capped_globally() {
+        let mut engine = Engine::new(Options {
+            max_open_disputes: Some(2),
+            ..Options::default()
+        });
+        let tx = |kind, client, tx, amount| Transaction {
+            kind,
+            client,
+            tx,
+            amount,
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        };
+
+        for id in 1..=3 {
+            engine
+                .apply(tx(TransactionType::Deposit, id as u16, id, Some(dec!(5.0))))
+                .unwrap();
+        }
+
+        // Disputes across clients count towards the same cap.
+        for id in 1..=2 {
+            assert_eq!(
+                engine
+                    .apply(tx(TransactionType::Dispute, id as u16, id, None))
+                    .unwrap(),
+                Outcome::Applied
+            );
+        }
+
+        assert_eq!(
+            engine
+                .apply(tx(TransactionType::Dispute, 3, 3, None))
+                .unwrap(),
+            Outcome::Skipped(Skip::TooManyOpenDisputes)
+        );
+        assert_eq!(engine.clients()[&3].held, dec!(0.0));
+
+        // Resolving one frees a slot.
+        engine
+            .apply(tx(TransactionType::Resolve, 1, 1, None))
+            .unwrap();
+
+        assert_eq!(
+            engine
+                .apply(tx(TransactionType::Dispute, 3, 3, None))
+                .unwrap(),
+            Outcome::Applied
+        );
+        assert_eq!(engine.clients()[&3].held, dec!(5.0));
+    }
+
+    #[test]
+    fn lenient_runs_collect_errors_and_carry_on() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::Reject,
+            ..Options::default()
+        };
+        let tx = |kind, tx, amount| Transaction {
+            kind,
+            client: 1,
+            tx,
+            amount,
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        };
+        let txs = vec![
+            Ok(tx(TransactionType::Deposit, 1, Some(dec!(5.0)))),
+            Ok(tx(TransactionType::Deposit, 2, None)),
+            Err(anyhow::anyhow!("bad row")),
+            Ok(tx(TransactionType::Deposit, 1, Some(dec!(5.0)))),
+            Ok(tx(TransactionType::Withdrawal, 3, None)),
+            Ok(tx(TransactionType::Deposit, 4, Some(dec!(2.0)))),
+        ];
+        let mut diagnostics = Diagnostics::new(Vec::new(), true, false);
+        let (clients, errors) = process_lenient(txs, &options, &mut diagnostics).unwrap();
+        let kinds = errors
+            .iter()
+            .map(|e| e.kind)
+            .collect::<Vec<_>>();
+
+        assert_eq!(clients[&1].available, dec!(7.0));
+        assert_eq!(
+            kinds,
+            vec![
+                ErrorKind::MissingAmount,
+                ErrorKind::Other,
+                ErrorKind::DuplicateTx,
+                ErrorKind::MissingAmount
+            ]
+        );
+        assert_eq!(
+            crate::error::summary(&errors),
+            "2 missing-amount, 1 duplicate-tx, 1 other"
+        );
+
+        let log = String::from_utf8(diagnostics.into_inner()).unwrap();
+
+        assert!(log.contains("error (duplicate-tx): transaction 1 is a duplicate deposit"));
+    }
+
+    #[test]
+    fn errors_can_abandon_their_client() {
+        let tx = |kind, client, tx, amount| {
+            Ok(Transaction {
+                kind,
+                client,
+                tx,
+                amount,
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            })
+        };
+        let txs = vec![
+            tx(TransactionType::Deposit, 1, 1, Some(dec!(5.0))),
+            tx(TransactionType::Deposit, 2, 2, Some(dec!(5.0))),
+            tx(TransactionType::Withdrawal, 1, 3, None),
+            Err(anyhow::anyhow!("bad row")),
+            tx(TransactionType::Deposit, 1, 4, Some(dec!(2.0))),
+            tx(TransactionType::Deposit, 2, 5, Some(dec!(2.0))),
+            tx(TransactionType::Withdrawal, 1, 6, None),
+        ];
+        let mut engine = Engine::new(Options::default());
+        let errors = run_on_error(
+            &mut engine,
+            txs,
+            &mut Diagnostics::silent(),
+            None,
+            OnError::SkipClient
+        )
+        .unwrap();
+        let kinds = errors
+            .iter()
+            .map(|e| e.kind)
+            .collect::<Vec<_>>();
+
+        // Client 1's later transactions are skipped, even the bad one.
+        assert_eq!(kinds, vec![ErrorKind::MissingAmount, ErrorKind::Other]);
+        assert_eq!(engine.errored(), &HashSet::from([1]));
+        assert_eq!(engine.clients()[&1].total, dec!(5.0));
+        assert_eq!(engine.clients()[&2].total, dec!(7.0));
+        assert_eq!(engine.metrics().skipped, 2);
+    }
+
+    #[test]
+    fn transactions_after_a_lock_can_be_rejected() {
+        let options = Options {
+            reject_after_lock: true,
+            ..Options::default()
+        };
+        let tx = |kind, tx, amount| {
+            Ok(Transaction {
+                kind,
+                client: 1,
+                tx,
+                amount,
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            })
+        };
+        let txs = || {
+            vec![
+                tx(TransactionType::Deposit, 1, Some(dec!(5.0))),
+                tx(TransactionType::Dispute, 1, None),
+                tx(TransactionType::Chargeback, 1, None),
+                tx(TransactionType::Deposit, 2, Some(dec!(3.0))),
+            ]
+        };
+        let mut diagnostics = Diagnostics::new(Vec::new(), false, false);
+        let clients = process_with(txs(), &options, &mut diagnostics).unwrap();
+
+        assert_eq!(clients[&1].total, dec!(0.0));
+        assert_eq!(
+            String::from_utf8(diagnostics.into_inner()).unwrap(),
+            "deposit 2 for client 1 was rejected because the client is locked\n"
+        );
+
+        let (_, errors) = process_lenient(txs(), &options, &mut Diagnostics::silent()).unwrap();
+
+        assert_eq!(
+            errors,
+            vec![ErrorKind::LockedClient
+                .error("deposit 2 for client 1 was rejected because the client is locked")]
+        );
+
+        // Without the option, the deposit is silently skipped.
+        let mut diagnostics = Diagnostics::new(Vec::new(), false, false);
+
+        process_with(txs(), &Options::default(), &mut diagnostics).unwrap();
+        assert!(diagnostics.into_inner().is_empty());
+    }
+
+    #[test]
+    fn balances_can_be_queried_mid_stream() {
+        let mut engine = Engine::new(Options::default());
+
+        assert_eq!(engine.balance(1), None);
+        assert!(!engine.is_locked(1));
+
+        engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(7.5)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+
+        let balance = engine.balance(1).unwrap();
+
+        assert_eq!(balance.available, dec!(7.5));
+        assert_eq!(balance.total, dec!(7.5));
+        assert!(!engine.is_locked(1));
+
+        for kind in [TransactionType::Dispute, TransactionType::Chargeback] {
+            engine
+                .apply(Transaction {
+                    kind,
+                    client: 1,
+                    tx: 1,
+                    amount: None,
+                    counterparty: None,
+                    seq: None,
+                    timestamp: None
+                })
+                .unwrap();
+        }
+
+        // The earlier copy is unaffected by later transactions.
+        assert_eq!(balance.total, dec!(7.5));
+        assert_eq!(engine.balance(1).unwrap().total, dec!(0.0));
+        assert!(engine.is_locked(1));
+        assert_eq!(engine.balance(2), None);
+    }
+
+    #[test]
+    fn explain_traces_a_dispute_then_resolve() {
+        let txs = [
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(5.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Resolve,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Resolve,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            }
+        ];
+        let mut diagnostics = Diagnostics::new(Vec::new(), false, false).with_explain(Some(1));
+
+        process_with(txs.map(Ok), &Options::default(), &mut diagnostics).unwrap();
+
+        let explanation = String::from_utf8(diagnostics.into_inner()).unwrap();
+
+        assert_eq!(
+            explanation,
+            "tx 1 deposit for client 1: applied, available 0 -> 10.0, total 0 -> 10.0\n\
+             tx 1 dispute for client 1: applied, available 15.0 -> 5.0, held 0 -> 10.0\n\
+             tx 1 resolve for client 1: applied, available 5.0 -> 15.0, held 10.0 -> 0.0\n\
+             tx 1 resolve for client 1: skipped, not disputed\n"
+        );
+    }
+
+    #[test]
+    fn dispute_resolve_withdraw_cycle() {
+        // Each step is checked, so a step mismanaging held can't hide.
+        let mut engine = Engine::default();
+        let steps = [
+            (
+                Transaction {
+                    kind:         TransactionType::Deposit,
+                    client:       1,
+                    tx:           1,
+                    amount:       Some(dec!(10.0)),
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(10.0),
+                dec!(0.0)
+            ),
+            (
+                Transaction {
+                    kind:         TransactionType::Dispute,
+                    client:       1,
+                    tx:           1,
+                    amount:       None,
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(0.0),
+                dec!(10.0)
+            ),
+            (
+                Transaction {
+                    kind:         TransactionType::Resolve,
+                    client:       1,
+                    tx:           1,
+                    amount:       None,
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(10.0),
+                dec!(0.0)
+            ),
+            (
+                Transaction {
+                    kind:         TransactionType::Withdrawal,
+                    client:       1,
+                    tx:           2,
+                    amount:       Some(dec!(10.0)),
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(0.0),
+                dec!(0.0)
+            )
+        ];
+
+        for (tx, available, held) in steps {
+            let id = tx.tx;
+
+            assert_eq!(engine.apply(tx).unwrap(), Outcome::Applied);
+
+            let client = &engine.clients()[&1];
+
+            assert_eq!(client.available, available, "available after tx {id}");
+            assert_eq!(client.held, held, "held after tx {id}");
+            assert!(client.held >= Decimal::ZERO);
+            assert_eq!(client.total, available + held);
+        }
+    }
+
+    #[test]
+    fn dispute_resolve_redispute_cycle() {
+        // A resolved deposit can be disputed again, and resolved again.
+        let mut engine = Engine::default();
+        let steps = [
+            (
+                Transaction {
+                    kind:         TransactionType::Deposit,
+                    client:       1,
+                    tx:           1,
+                    amount:       Some(dec!(10.0)),
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(10.0),
+                dec!(0.0)
+            ),
+            (
+                Transaction {
+                    kind:         TransactionType::Dispute,
+                    client:       1,
+                    tx:           1,
+                    amount:       None,
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(0.0),
+                dec!(10.0)
+            ),
+            (
+                Transaction {
+                    kind:         TransactionType::Resolve,
+                    client:       1,
+                    tx:           1,
+                    amount:       None,
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(10.0),
+                dec!(0.0)
+            ),
+            (
+                Transaction {
+                    kind:         TransactionType::Dispute,
+                    client:       1,
+                    tx:           1,
+                    amount:       None,
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(0.0),
+                dec!(10.0)
+            ),
+            (
+                Transaction {
+                    kind:         TransactionType::Resolve,
+                    client:       1,
+                    tx:           1,
+                    amount:       None,
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(10.0),
+                dec!(0.0)
+            ),
+            (
+                Transaction {
+                    kind:         TransactionType::Withdrawal,
+                    client:       1,
+                    tx:           2,
+                    amount:       Some(dec!(4.0)),
+                    counterparty: None,
+                    seq:          None,
+                    timestamp:    None
+                },
+                dec!(6.0),
+                dec!(0.0)
+            )
+        ];
+
+        for (tx, available, held) in steps {
+            let id = tx.tx;
+
+            assert_eq!(engine.apply(tx).unwrap(), Outcome::Applied);
+
+            let client = &engine.clients()[&1];
+
+            assert_eq!(client.available, available, "available after tx {id}");
+            assert_eq!(client.held, held, "held after tx {id}");
+            assert!(client.held >= Decimal::ZERO);
+            assert_eq!(client.total, available + held);
+        }
+    }
+
+    #[test]
+    fn idempotent_policy_ignores_repeated_deposits() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::Idempotent,
+            ..Options::default()
+        };
+        let mut engine = Engine::new(options);
+        let deposit = Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(10.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        };
+
+        assert_eq!(
+            engine
+                .apply(deposit.clone())
+                .unwrap(),
+            Outcome::Applied
+        );
+        assert_eq!(
+            engine.apply(deposit).unwrap(),
+            Outcome::Skipped(Skip::Duplicate)
+        );
+        assert_eq!(engine.clients()[&1].available, dec!(10.0));
+        assert_eq!(engine.clients()[&1].total, dec!(10.0));
+    }
+
+    #[test]
+    fn idempotent_policy_applies_redelivered_failed_deposits() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::Idempotent,
+            ..Options::default()
+        };
+        let mut engine = Engine::with_clients(
+            options,
+            HashMap::from([(
+                1,
+                ClientData {
+                    available: Decimal::MAX,
+                    total: Decimal::MAX,
+                    ..ClientData::default()
+                }
+            )])
+        );
+        let deposit = Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(10.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        };
+
+        assert!(engine
+            .apply(deposit.clone())
+            .is_err());
+
+        // Make room for the deposit, then redeliver it.
+        engine
+            .apply(Transaction {
+                kind: TransactionType::Withdrawal,
+                tx: 2,
+                amount: Some(dec!(20.0)),
+                ..deposit.clone()
+            })
+            .unwrap();
+
+        assert_eq!(
+            engine
+                .apply(deposit.clone())
+                .unwrap(),
+            Outcome::Applied
+        );
+        assert_eq!(
+            engine.apply(deposit).unwrap(),
+            Outcome::Skipped(Skip::Duplicate)
+        );
+        assert_eq!(engine.clients()[&1].available, Decimal::MAX - dec!(10.0));
+    }
+
+    #[test]
+    fn idempotent_policy_fails_on_conflicting_deposits() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::Idempotent,
+            ..Options::default()
+        };
+        let mut engine = Engine::new(options);
+        let deposit = Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(10.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        };
+
+        engine
+            .apply(deposit.clone())
+            .unwrap();
+
+        let err = engine
+            .apply(Transaction {
+                amount: Some(dec!(12.0)),
+                ..deposit
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "transaction 1 conflicts with an earlier deposit of 10.0 for client 1"
+        );
+        assert_eq!(engine.clients()[&1].available, dec!(10.0));
+    }
+
+    #[test]
+    fn process_stops_at_the_first_error() {
+        let txs = vec![
+            Ok(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(1.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            }),
+            Err(anyhow::anyhow!("bad row")),
+        ];
+
+        let err = process(txs, &Options::default()).unwrap_err();
+
+        assert_eq!(err.to_string(), "bad row");
+    }
+
+    #[test]
+    fn only_allowed_clients_are_processed() {
+        let txs = [1, 2, 3, 2].map(|client| Transaction {
+            kind: TransactionType::Deposit,
+            client,
+            tx: u32::from(client),
+            amount: Some(dec!(1.0)),
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        });
+        let options = Options {
+            clients: Some(HashSet::from([2, 3])),
+            ..Options::default()
+        };
+
+        let clients = process_slice(&txs, &options).unwrap();
+        let mut ids = clients
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        ids.sort();
+
+        assert_eq!(ids, vec![2, 3]);
+        assert_eq!(clients[&2].total, dec!(2.0));
+    }
+
+    #[test]
+    fn disputes_of_ambiguous_ids_are_skipped() {
+        let txs = [
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            }
+        ];
+        // Only deposits are disputable by default, so there's no doubt.
+        let mut engine = Engine::default();
+
+        engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+
+        let outcomes = txs
+            .clone()
+            .map(|tx| engine.apply(tx).unwrap());
+
+        assert_eq!(outcomes[2], Outcome::Applied);
+        assert_eq!(engine.clients()[&1].available, dec!(7.0));
+        assert_eq!(engine.clients()[&1].held, dec!(10.0));
+
+        let options = Options {
+            disputable: Disputable::Both,
+            ..Options::default()
+        };
+        let mut engine = Engine::new(options.clone());
+        let outcomes = txs
+            .clone()
+            .map(|tx| engine.apply(tx).unwrap());
+
+        assert_eq!(outcomes[2], Outcome::Skipped(Skip::Ambiguous));
+        assert_eq!(engine.clients()[&1].available, dec!(7.0));
+        assert_eq!(engine.clients()[&1].held, dec!(0.0));
+
+        let options = Options {
+            strict: true,
+            ..options
+        };
+        let err = process_slice(&txs, &options).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "transaction 1 refers to both a deposit and a withdrawal"
+        );
+    }
+
+    #[test]
+    fn strict_references_fail_on_missing_transactions() {
+        let options = Options {
+            strict_references: true,
+            ..Options::default()
+        };
+        let mut engine = Engine::new(options);
+
+        let err = engine
+            .apply(Transaction {
+                kind:         TransactionType::Resolve,
+                client:       1,
+                tx:           9,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "resolve references transaction 9, which doesn't exist"
+        );
+    }
+
+    #[test]
+    fn strict_references_skip_existing_transactions() {
+        let options = Options {
+            strict_references: true,
+            disputable: Disputable::Both,
+            ..Options::default()
+        };
+        let mut engine = Engine::new(options);
+        let txs = [
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Withdrawal,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(1.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Resolve,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       1,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            }
+        ];
+        let outcomes = txs.map(|tx| engine.apply(tx).unwrap());
+
+        assert_eq!(outcomes[2], Outcome::Skipped(Skip::NotDisputed));
+        assert_eq!(outcomes[3], Outcome::Skipped(Skip::NotDisputed));
+        assert_eq!(engine.clients()[&1].available, dec!(9.0));
+        assert!(!engine.clients()[&1].locked);
+    }
+
+    /// Deposits 10, withdraws 4 and disputes the deposit.
+    fn partially_withdrawn_dispute() -> Engine {
+        let options = Options {
+            dispute_mode: DisputeMode::Proportional,
+            ..Options::default()
+        };
+        let mut engine = Engine::new(options);
+
+        for (kind, tx, amount) in [
+            (TransactionType::Deposit, 1, Some(dec!(10.0))),
+            (TransactionType::Withdrawal, 2, Some(dec!(4.0))),
+            (TransactionType::Dispute, 1, None)
+        ] {
+            let outcome = engine
+                .apply(Transaction {
+                    kind,
+                    client: 1,
+                    tx,
+                    amount,
+                    counterparty: None,
+                    seq: None,
+                    timestamp: None
+                })
+                .unwrap();
+
+            assert_eq!(outcome, Outcome::Applied);
+        }
+
+        engine
+    }
+
+    /// Applies a resolve or chargeback of the deposit.
+    fn settle(engine: &mut Engine, kind: TransactionType) -> ClientData {
+        let outcome = engine
+            .apply(Transaction {
+                kind,
+                client: 1,
+                tx: 1,
+                amount: None,
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            })
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::Applied);
+        engine.clients()[&1].clone()
+    }
+
+    #[test]
+    fn proportional_disputes_hold_what_is_available() {
+        let engine = partially_withdrawn_dispute();
+        let client = &engine.clients()[&1];
+
+        assert_eq!(client.available, dec!(0.0));
+        assert_eq!(client.held, dec!(6.0));
+        assert_eq!(client.total, dec!(6.0));
+        assert_eq!(engine.shortfall(1), Some(dec!(4.0)));
+    }
+
+    #[test]
+    fn proportional_disputes_resolve_what_was_held() {
+        let mut engine = partially_withdrawn_dispute();
+        let client = settle(&mut engine, TransactionType::Resolve);
+
+        assert_eq!(client.available, dec!(6.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(6.0));
+        assert_eq!(engine.shortfall(1), None);
+    }
+
+    #[test]
+    fn proportional_disputes_charge_back_the_whole_deposit() {
+        let mut engine = partially_withdrawn_dispute();
+        let client = settle(&mut engine, TransactionType::Chargeback);
+
+        assert_eq!(client.available, dec!(-4.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(-4.0));
+        assert!(client.locked);
+    }
+
+    #[test]
+    fn transactions_are_counted_per_client() {
+        let mut engine = Engine::new(Options::default());
+
+        for (kind, client, tx, amount) in [
+            (TransactionType::Deposit, 1, 1, Some(dec!(5.0))),
+            (TransactionType::Withdrawal, 1, 2, Some(dec!(9.0))),
+            (TransactionType::Dispute, 1, 1, None),
+            (TransactionType::Deposit, 2, 3, Some(dec!(1.0)))
+        ] {
+            engine
+                .apply(Transaction {
+                    kind,
+                    client,
+                    tx,
+                    amount,
+                    counterparty: None,
+                    seq: None,
+                    timestamp: None
+                })
+                .unwrap();
+        }
+
+        assert_eq!(engine.tx_counts(true), HashMap::from([(1, 3), (2, 1)]));
+        assert_eq!(engine.tx_counts(false), HashMap::from([(1, 2), (2, 1)]));
+    }
+
+    /// An engine with a disputed deposit of 5.0 whose client has only
+    /// 2.0 held, as if a bug had shaved off the rest.
+    fn short_held_dispute(options: Options) -> Engine {
+        let mut engine = Engine::new(options);
+
+        for kind in [TransactionType::Deposit, TransactionType::Dispute] {
+            engine
+                .apply(Transaction {
+                    kind,
+                    client: 1,
+                    tx: 1,
+                    amount: (kind == TransactionType::Deposit).then_some(dec!(5.0)),
+                    counterparty: None,
+                    seq: None,
+                    timestamp: None
+                })
+                .unwrap();
+        }
+
+        let client = engine.clients.get_mut(&1).unwrap();
+
+        client.held = dec!(2.0);
+        client.total = dec!(2.0);
+        engine
+    }
+
+    #[test]
+    fn resolves_never_take_held_negative() {
+        let mut engine = short_held_dispute(Options::default());
+        let client = settle(&mut engine, TransactionType::Resolve);
+
+        assert_eq!(client.available, dec!(2.0));
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(2.0));
+        assert_eq!(engine.clamped(), Some(dec!(3.0)));
+
+        let mut engine = short_held_dispute(Options::default());
+        let client = settle(&mut engine, TransactionType::Chargeback);
+
+        assert_eq!(client.held, dec!(0.0));
+        assert_eq!(client.total, dec!(0.0));
+        assert_eq!(engine.clamped(), Some(dec!(3.0)));
+    }
+
+    #[test]
+    fn strict_runs_fail_rather_than_clamp_held() {
+        let mut engine = short_held_dispute(Options {
+            strict: true,
+            ..Options::default()
+        });
+        let error = engine
+            .apply(Transaction {
+                kind:         TransactionType::Resolve,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap_err();
+
+        assert_eq!(ProcessError::from(error).kind, ErrorKind::NegativeHeld);
+        assert_eq!(engine.clients()[&1].held, dec!(2.0));
+    }
+
+    #[test]
+    fn negative_held_can_be_allowed() {
+        let mut engine = short_held_dispute(Options {
+            allow_negative_held: true,
+            ..Options::default()
+        });
+        let client = settle(&mut engine, TransactionType::Resolve);
+
+        assert_eq!(client.available, dec!(5.0));
+        assert_eq!(client.held, dec!(-3.0));
+        assert_eq!(engine.clamped(), None);
+    }
+
+    #[test]
+    fn stored_deposits_without_amounts_fail_rather_than_panic() {
+        let mut engine = Engine::new(Options::default());
+
+        // Sidestep `verify`, as a bug elsewhere might.
+        engine.deposits.insert(
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            &HashSet::new()
+        );
+
+        let result = engine.apply(Transaction {
+            kind:         TransactionType::Dispute,
+            client:       1,
+            tx:           1,
+            amount:       None,
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        });
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use std::{collections::BTreeMap, error::Error, fmt};
+
+/// The kinds of error processing a transaction can fail with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+    /// A row couldn't be read or parsed.
+    MalformedRow,
+
+    /// A deposit, withdrawal or transfer has no amount.
+    MissingAmount,
+
+    /// A deposit, withdrawal or transfer has a negative or zero amount,
+    /// with strict amount signs.
+    NonPositiveAmount,
+
+    /// A transfer has no counterparty, or sends to its own client.
+    InvalidTransfer,
+
+    /// A deposit reuses a transaction id the duplicate policy rejects, or
+    /// a client reuses one when they must be unique.
+    DuplicateTx,
+
+    /// A balance would overflow.
+    Overflow,
+
+    /// A balance would be rounded because it needs more than 28
+    /// significant digits, with strict precision.
+    PrecisionLoss,
+
+    /// A strict run saw an amount over the maximum.
+    OverMaxAmount,
+
+    /// A strict run saw a dispute, resolve or chargeback for a locked
+    /// client.
+    LockedClient,
+
+    /// A strict run saw a dispute referring to both a deposit and a
+    /// withdrawal.
+    AmbiguousTx,
+
+    /// A strict run saw a resolve or chargeback that would take held
+    /// funds negative.
+    NegativeHeld,
+
+    /// A dispute, resolve or chargeback refers to a transaction that
+    /// doesn't exist, with strict references.
+    UnknownReference,
+
+    /// Anything else.
+    Other
+}
+
+impl ErrorKind {
+    /// Creates an error of this kind.
+    pub fn error(self, message: impl Into<String>) -> ProcessError {
+        ProcessError {
+            kind:    self,
+            message: message.into()
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::MalformedRow => "malformed-row",
+            Self::MissingAmount => "missing-amount",
+            Self::NonPositiveAmount => "non-positive-amount",
+            Self::InvalidTransfer => "invalid-transfer",
+            Self::DuplicateTx => "duplicate-tx",
+            Self::Overflow => "overflow",
+            Self::PrecisionLoss => "precision-loss",
+            Self::OverMaxAmount => "over-max-amount",
+            Self::LockedClient => "locked-client",
+            Self::AmbiguousTx => "ambiguous-tx",
+            Self::NegativeHeld => "negative-held",
+            Self::UnknownReference => "unknown-reference",
+            Self::Other => "other"
+        })
+    }
+}
+
+/// An error processing a transaction, which a lenient run collects
+/// rather than failing on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessError {
+    /// What kind of error it is.
+    pub kind: ErrorKind,
+
+    /// What went wrong.
+    pub message: String
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ProcessError {}
+
+impl From<anyhow::Error> for ProcessError {
+    /// Recovers the kind of an error, treating csv errors as malformed
+    /// rows and any other untyped error as `Other`.
+    fn from(error: anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<ProcessError>() {
+            return error.clone();
+        }
+
+        let kind = if error.is::<csv::Error>() {
+            ErrorKind::MalformedRow
+        } else {
+            ErrorKind::Other
+        };
+
+        kind.error(error.to_string())
+    }
+}
+
+/// Summarizes errors by kind, most common first, like
+/// `12 missing-amount, 3 duplicate-tx, 1 overflow`.
+pub fn summary(errors: &[ProcessError]) -> String {
+    let mut counts = BTreeMap::<ErrorKind, usize>::new();
+
+    for error in errors {
+        *counts
+            .entry(error.kind)
+            .or_default() += 1;
+    }
+
+    let mut counts = counts
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    // The sort is stable, so ties stay in kind order.
+    counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    counts
+        .iter()
+        .map(|(kind, count)| format!("{count} {kind}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn kinds_survive_anyhow() {
+        let error = anyhow::Error::from(ErrorKind::Overflow.error("too big"));
+
+        assert_eq!(error.to_string(), "too big");
+        assert_eq!(ProcessError::from(error).kind, ErrorKind::Overflow);
+        assert_eq!(ProcessError::from(anyhow!("huh")).kind, ErrorKind::Other);
+    }
+
+    #[test]
+    fn summary_counts_errors_by_kind() {
+        let errors = [
+            ErrorKind::DuplicateTx,
+            ErrorKind::MissingAmount,
+            ErrorKind::Overflow,
+            ErrorKind::MissingAmount,
+            ErrorKind::DuplicateTx,
+            ErrorKind::MissingAmount
+        ]
+        .map(|kind| kind.error("oops"));
+
+        assert_eq!(
+            summary(&errors),
+            "3 missing-amount, 2 duplicate-tx, 1 overflow"
+        );
+        assert_eq!(summary(&[]), "");
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    diff::{self, Difference},
+    output::format_amount,
+    ClientData, PRECISION
+};
+use anyhow::{anyhow, Result};
+use csv::{ReaderBuilder, Trim};
+use serde::Deserialize;
+use std::{collections::HashMap, fmt, io::Read};
+
+/// A row of expected output. Any other columns are ignored.
+#[derive(Deserialize)]
+struct Row {
+    client:    String,
+    available: String,
+    held:      String,
+    total:     String,
+    locked:    String
+}
+
+/// How computed client data first differs from what was expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// An expected client wasn't computed.
+    Missing(u16),
+
+    /// A computed client wasn't expected.
+    Unexpected(u16),
+
+    /// A client's data differs.
+    Differs {
+        client:   u16,
+        expected: ClientData,
+        actual:   ClientData
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let row = |c: &ClientData| {
+            format!(
+                "available {}, held {}, total {}, locked {}",
+                format_amount(c.available, PRECISION),
+                format_amount(c.held, PRECISION),
+                format_amount(c.total, PRECISION),
+                c.locked
+            )
+        };
+
+        match self {
+            Self::Missing(client) => write!(f, "client {client} is expected but missing"),
+            Self::Unexpected(client) => write!(f, "client {client} isn't expected"),
+            Self::Differs {
+                client,
+                expected,
+                actual
+            } => write!(
+                f,
+                "client {client} has {} but {} is expected",
+                row(actual),
+                row(expected)
+            )
+        }
+    }
+}
+
+/// Reads expected client data from csv output, such as a previous run's.
+/// Amounts may have any scale, a `TOTAL` row is skipped and columns other
+/// than `client`, `available`, `held`, `total` and `locked` are ignored.
+pub fn read_expected<R: Read>(input: R) -> Result<HashMap<u16, ClientData>> {
+    let mut reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(input);
+    let mut clients = HashMap::new();
+
+    for row in reader.deserialize::<Row>() {
+        let row = row?;
+
+        if row.client == "TOTAL" {
+            continue;
+        }
+
+        let amount = |s: &str| {
+            s.parse()
+                .map_err(|_| anyhow!("client {} has an invalid amount {s:?}", row.client))
+        };
+
+        clients.insert(
+            row.client.parse()?,
+            ClientData {
+                available: amount(&row.available)?,
+                held:      amount(&row.held)?,
+                total:     amount(&row.total)?,
+                locked:    row.locked.parse()?
+            }
+        );
+    }
+
+    Ok(clients)
+}
+
+/// Compares computed client data to what was expected, numerically and
+/// regardless of order, returning the mismatch of the lowest client id if
+/// there are any.
+pub fn compare(
+    expected: &HashMap<u16, ClientData>,
+    actual: &HashMap<u16, ClientData>
+) -> Option<Mismatch> {
+    let difference = diff::differences(expected, actual)
+        .into_iter()
+        .next()?;
+
+    Some(match difference {
+        Difference::Removed(client) => Mismatch::Missing(client),
+        Difference::Added(client) => Mismatch::Unexpected(client),
+        Difference::Changed {
+            client,
+            before,
+            after
+        } => Mismatch::Differs {
+            client,
+            expected: before,
+            actual: after
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input, Diagnostics, InputOptions, Options};
+    use rust_decimal_macros::dec;
+
+    fn computed() -> HashMap<u16, ClientData> {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,10.0\n\
+                   withdrawal,1,2,4.0\n\
+                   deposit,2,3,2.5\n";
+
+        input::process_csv(
+            csv.as_bytes(),
+            &Options::default(),
+            &InputOptions::default(),
+            &mut Diagnostics::silent()
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn matching_output_is_accepted_in_any_order_and_scale() {
+        let expected = "client,available,held,total,locked,tx_count\n\
+                        2, 2.5, 0, 2.50, false, 1\n\
+                        1,6.0000,0.0000,6.0000,false,2\n\
+                        TOTAL,8.5000,0.0000,8.5000,0,3\n";
+        let expected = read_expected(expected.as_bytes()).unwrap();
+
+        assert_eq!(compare(&expected, &computed()), None);
+    }
+
+    #[test]
+    fn the_first_mismatch_is_reported() {
+        let expected = "client,available,held,total,locked\n\
+                        1,6.0,0.0,6.0,false\n\
+                        2,3.0,0.0,3.0,false\n\
+                        3,1.0,0.0,1.0,false\n";
+        let expected = read_expected(expected.as_bytes()).unwrap();
+        let mismatch = compare(&expected, &computed()).unwrap();
+
+        assert_eq!(
+            mismatch,
+            Mismatch::Differs {
+                client:   2,
+                expected: ClientData {
+                    available: dec!(3.0),
+                    total: dec!(3.0),
+                    ..ClientData::default()
+                },
+                actual:   ClientData {
+                    available: dec!(2.5),
+                    total: dec!(2.5),
+                    ..ClientData::default()
+                }
+            }
+        );
+        assert_eq!(
+            mismatch.to_string(),
+            "client 2 has available 2.5000, held 0.0000, total 2.5000, locked false but \
+             available 3.0000, held 0.0000, total 3.0000, locked false is expected"
+        );
+
+        let mut missing = computed();
+
+        missing.remove(&1);
+        assert_eq!(compare(&computed(), &missing), Some(Mismatch::Missing(1)));
+        assert_eq!(
+            compare(&missing, &computed()),
+            Some(Mismatch::Unexpected(1))
+        );
+    }
+}
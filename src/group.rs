@@ -0,0 +1,248 @@
+use crate::{
+    output::{columns, reported, Column, OutputOptions},
+    ClientData
+};
+use anyhow::{anyhow, bail, Result};
+use csv::WriterBuilder;
+use rust_decimal::Decimal;
+use std::{collections::HashMap, io::Write, str::FromStr};
+
+/// How clients are aggregated for reporting, parsed from `none`, `all`
+/// or `bucket:N`.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Report every client on its own.
+    #[default]
+    None,
+
+    /// Report one row summing every client.
+    All,
+
+    /// Report a row per range of this many client ids, so `bucket:100`
+    /// groups clients 0 to 99, 100 to 199 and so on.
+    Bucket(u16)
+}
+
+impl FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "none" => Ok(Self::None),
+            "all" => Ok(Self::All),
+            other => {
+                let size = other
+                    .strip_prefix("bucket:")
+                    .ok_or_else(|| anyhow!("expected none, all or bucket:N, got {s}"))?
+                    .parse::<u16>()?;
+
+                if size == 0 {
+                    bail!("buckets must hold at least one client");
+                }
+
+                Ok(Self::Bucket(size))
+            }
+        }
+    }
+}
+
+/// The summed client data of a group.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    /// What the group covers, `all` or a range of client ids like
+    /// `100-199`.
+    pub label: String,
+
+    /// The summed `available` column.
+    pub available: Decimal,
+
+    /// The summed `held` column.
+    pub held: Decimal,
+
+    /// The summed `total` column.
+    pub total: Decimal,
+
+    /// The number of clients in the group.
+    pub clients: usize,
+
+    /// The number of locked clients in the group.
+    pub locked: usize
+}
+
+/// Returns the groups of the reported clients, sorted by client id. The
+/// columns are summed as they'd be output, so `held_as_negative` and
+/// `locked_only` apply. Fails if a sum overflows.
+pub fn groups(
+    clients: &HashMap<u16, ClientData>,
+    group_by: GroupBy,
+    options: &OutputOptions
+) -> Result<Vec<Group>> {
+    let mut groups: Vec<Group> = Vec::new();
+
+    for (id, client) in reported(clients, options) {
+        let label = match group_by {
+            GroupBy::None => id.to_string(),
+            GroupBy::All => "all".to_string(),
+            GroupBy::Bucket(size) => {
+                let start = id / size * size;
+
+                format!("{start}-{}", start.saturating_add(size - 1))
+            }
+        };
+
+        // Clients are sorted, so a group's clients are adjacent.
+        if groups
+            .last()
+            .is_none_or(|group| group.label != label)
+        {
+            groups.push(Group {
+                label,
+                ..Group::default()
+            });
+        }
+
+        let group = groups.last_mut().unwrap();
+        let (available, held, total) = columns(client, options)?;
+        let sum = |a: Decimal, b: Decimal| {
+            a.checked_add(b)
+                .ok_or_else(|| anyhow!("group {} overflows", group.label))
+        };
+
+        group.available = sum(group.available, available)?;
+        group.held = sum(group.held, held)?;
+        group.total = sum(group.total, total)?;
+        group.clients += 1;
+        group.locked += usize::from(client.locked);
+    }
+
+    Ok(groups)
+}
+
+/// Writes the groups as csv, flushing once done.
+pub fn write_groups<W: Write>(
+    out: &mut W,
+    groups: &[Group],
+    options: &OutputOptions
+) -> Result<()> {
+    let mut writer = WriterBuilder::new()
+        .terminator(options.terminator.into())
+        .quote_style(options.quoting.into())
+        .from_writer(&mut *out);
+
+    writer.write_record(["group", "available", "held", "total", "clients", "locked"])?;
+
+    for group in groups {
+        writer.write_record([
+            group.label.clone(),
+            options.format(group.available, Column::Available),
+            options.format(group.held, Column::Held),
+            options.format(group.total, Column::Total),
+            group.clients.to_string(),
+            group.locked.to_string()
+        ])?;
+    }
+
+    // Releasing the writer flushes it.
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn clients() -> HashMap<u16, ClientData> {
+        HashMap::from([
+            (
+                1,
+                ClientData {
+                    available: dec!(10.0),
+                    total: dec!(10.0),
+                    ..ClientData::default()
+                }
+            ),
+            (
+                2,
+                ClientData {
+                    available: dec!(2.5),
+                    held:      dec!(1.5),
+                    total:     dec!(4.0),
+                    locked:    false
+                }
+            ),
+            (
+                150,
+                ClientData {
+                    available: dec!(0.25),
+                    total: dec!(0.25),
+                    locked: true,
+                    ..ClientData::default()
+                }
+            )
+        ])
+    }
+
+    #[test]
+    fn group_by_is_parsed() {
+        assert_eq!("none".parse::<GroupBy>().unwrap(), GroupBy::None);
+        assert_eq!("all".parse::<GroupBy>().unwrap(), GroupBy::All);
+        assert_eq!(
+            "bucket:100"
+                .parse::<GroupBy>()
+                .unwrap(),
+            GroupBy::Bucket(100)
+        );
+        assert!("bucket:0"
+            .parse::<GroupBy>()
+            .is_err());
+        assert!("bucket:x"
+            .parse::<GroupBy>()
+            .is_err());
+        assert!("client".parse::<GroupBy>().is_err());
+    }
+
+    #[test]
+    fn all_sums_every_client() {
+        let groups = groups(&clients(), GroupBy::All, &OutputOptions::default()).unwrap();
+
+        assert_eq!(
+            groups,
+            vec![Group {
+                label:     "all".to_string(),
+                available: dec!(12.75),
+                held:      dec!(1.5),
+                total:     dec!(14.25),
+                clients:   3,
+                locked:    1
+            }]
+        );
+
+        let mut out = Vec::new();
+
+        write_groups(&mut out, &groups, &OutputOptions::default()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "group,available,held,total,clients,locked\n\
+             all,12.7500,1.5000,14.2500,3,1\n"
+        );
+    }
+
+    #[test]
+    fn buckets_group_client_id_ranges() {
+        let groups = groups(&clients(), GroupBy::Bucket(100), &OutputOptions::default()).unwrap();
+        let labels = groups
+            .iter()
+            .map(|g| (g.label.as_str(), g.clients, g.total))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            labels,
+            vec![("0-99", 2, dec!(14.0)), ("100-199", 1, dec!(0.25))]
+        );
+    }
+}
@@ -0,0 +1,482 @@
+use crate::{output, Diagnostics, Engine, OutputOptions, SharedEngine};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{Condvar, Mutex, PoisonError},
+    thread,
+    time::Duration
+};
+
+/// How long a connection may take to send its request or accept the
+/// response before it's dropped.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest request or header line accepted, in bytes.
+const MAX_LINE: usize = 8 * 1024;
+
+/// The most header bytes accepted in a request.
+const MAX_HEADERS: usize = 64 * 1024;
+
+/// The most connections handled at once. Any more wait to be accepted
+/// until one finishes.
+const MAX_CONNECTIONS: usize = 64;
+
+/// A response to an http request, with a json body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    /// The status code.
+    pub status: u16,
+
+    /// The json body.
+    pub body: String
+}
+
+impl Response {
+    /// A successful response, with fields in the order they're declared.
+    fn ok<T: Serialize>(body: &T) -> Result<Self> {
+        Ok(Self {
+            status: 200,
+            body:   serde_json::to_string(body)?
+        })
+    }
+
+    /// An error response, with the message in an `error` field.
+    fn error(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: json!({ "error": message.into() }).to_string()
+        }
+    }
+
+    /// The reason phrase of the status code.
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            414 => "URI Too Long",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error"
+        }
+    }
+}
+
+/// Expands an address given as just `:port` to listen on every
+/// interface, passing any other address through.
+pub fn listen_addr(addr: &str) -> String {
+    match addr.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{port}"),
+        None => addr.to_string()
+    }
+}
+
+/// Answers a request for client data. `GET /clients` returns every client
+/// as a json array like `--format json` outputs, and `GET /clients/{id}`
+/// returns just that client as a json object. Balances are normalized
+/// like those of the final output.
+pub fn route(
+    engine: &Engine,
+    output_options: &OutputOptions,
+    method: &str,
+    path: &str
+) -> Result<Response> {
+    if method != "GET" {
+        return Ok(Response::error(405, format!("{method} isn't supported")));
+    }
+
+    // Ignore any query string.
+    let path = path
+        .split_once('?')
+        .map_or(path, |(path, _)| path);
+
+    if path == "/clients" {
+        let rows = output::json_rows(&engine.normalized_clients(), output_options)?;
+
+        return Response::ok(&rows);
+    }
+
+    let Some(id) = path.strip_prefix("/clients/") else {
+        return Ok(Response::error(404, format!("{path} doesn't exist")));
+    };
+    let Ok(id) = id.parse::<u16>() else {
+        return Ok(Response::error(400, format!("{id} isn't a client id")));
+    };
+
+    // The row is filtered out if the output options don't report it.
+    let row = match engine.normalized_balance(id) {
+        Some(client) => output::json_rows(&HashMap::from([(id, client)]), output_options)?.pop(),
+        None => None
+    };
+
+    match row {
+        Some(row) => Response::ok(&row),
+        None => Ok(Response::error(404, format!("client {id} doesn't exist")))
+    }
+}
+
+/// Reads a line of at most `MAX_LINE` bytes, or `None` if it's longer.
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>> {
+    let mut line = String::new();
+
+    reader
+        .take(MAX_LINE as u64 + 1)
+        .read_line(&mut line)?;
+
+    Ok((line.len() <= MAX_LINE).then_some(line))
+}
+
+/// Reads a request's method and path, skipping its headers so the peer
+/// doesn't see its request cut off. A request that's malformed or too
+/// large gives the error response to send instead.
+fn read_request<R: BufRead>(reader: &mut R) -> Result<Result<(String, String), Response>> {
+    let Some(line) = read_line(reader)? else {
+        return Ok(Err(Response::error(414, "the request line is too long")));
+    };
+
+    let mut parts = line.split_ascii_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Ok(Err(Response::error(
+            400,
+            format!("malformed request line {line:?}")
+        )));
+    };
+    let request = (method.to_string(), path.to_string());
+    let mut headers = 0;
+
+    loop {
+        let Some(header) = read_line(reader)? else {
+            return Ok(Err(Response::error(431, "a header is too long")));
+        };
+
+        headers += header.len();
+
+        if headers > MAX_HEADERS {
+            return Ok(Err(Response::error(431, "the headers are too long")));
+        }
+
+        if header.trim().is_empty() {
+            return Ok(Ok(request));
+        }
+    }
+}
+
+/// Reads a single request from a connection and writes the response back.
+/// Request bodies are ignored, and the response asks for the connection
+/// to be closed.
+pub fn handle<S: Read + Write>(
+    mut stream: S,
+    engine: &SharedEngine,
+    output_options: &OutputOptions
+) -> Result<()> {
+    let response = match read_request(&mut BufReader::new(&mut stream))? {
+        Ok((method, path)) => {
+            engine.with(|engine| route(engine, output_options, &method, &path))??
+        },
+        Err(response) => response
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        response.status,
+        response.reason(),
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+/// Closes a connection once the peer has, discarding up to `MAX_HEADERS`
+/// bytes of whatever's left of its request. Closing with some of the
+/// request unread would reset the connection, and the peer could lose
+/// the response. The response has been sent, so failing here is moot.
+fn linger(stream: &TcpStream) {
+    if stream
+        .shutdown(Shutdown::Write)
+        .is_ok()
+    {
+        io::copy(&mut stream.take(MAX_HEADERS as u64), &mut io::sink()).ok();
+    }
+}
+
+/// Counts the connections being handled, so at most a limit are.
+struct Slots {
+    /// How many connections are being handled.
+    open: Mutex<usize>,
+
+    /// Signalled whenever a connection finishes.
+    freed: Condvar,
+
+    /// The most connections handled at once.
+    limit: usize
+}
+
+impl Slots {
+    /// Waits until fewer than the limit of connections are being handled,
+    /// then takes up a slot. The count is only ever incremented or
+    /// decremented, so a poisoned lock still holds a good one.
+    fn take(&self) {
+        let open = self
+            .open
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut open = self
+            .freed
+            .wait_while(open, |open| *open >= self.limit)
+            .unwrap_or_else(PoisonError::into_inner);
+
+        *open += 1;
+    }
+
+    /// Frees up a slot once a connection finishes.
+    fn free(&self) {
+        *self
+            .open
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) -= 1;
+        self.freed.notify_one();
+    }
+}
+
+/// Serves client data over http until the process is stopped, handling
+/// each connection on its own thread, up to `MAX_CONNECTIONS` at once.
+/// Connections that are too slow time out, and a connection that fails
+/// is reported as a diagnostic rather than bringing the server down.
+pub fn serve<W: Write + Send>(
+    listener: &TcpListener,
+    engine: &SharedEngine,
+    output_options: &OutputOptions,
+    diagnostics: &mut Diagnostics<W>
+) -> Result<()> {
+    serve_at_most(
+        listener,
+        engine,
+        output_options,
+        diagnostics,
+        MAX_CONNECTIONS
+    )
+}
+
+/// Serves client data like `serve`, handling at most `limit` connections
+/// at once.
+fn serve_at_most<W: Write + Send>(
+    listener: &TcpListener,
+    engine: &SharedEngine,
+    output_options: &OutputOptions,
+    diagnostics: &mut Diagnostics<W>,
+    limit: usize
+) -> Result<()> {
+    let slots = &Slots {
+        open: Mutex::new(0),
+        freed: Condvar::new(),
+        limit
+    };
+    let diagnostics = Mutex::new(diagnostics);
+    let report = &|error: anyhow::Error| {
+        if let Ok(mut diagnostics) = diagnostics.lock() {
+            diagnostics.print(format_args!("http: {error}"));
+        }
+    };
+
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    report(error.into());
+                    continue;
+                }
+            };
+
+            // Leave any further connections queued until one finishes.
+            slots.take();
+            scope.spawn(move || {
+                let result = stream
+                    .set_read_timeout(Some(TIMEOUT))
+                    .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+                    .map_err(anyhow::Error::from)
+                    .and_then(|()| handle(&stream, engine, output_options));
+
+                match result {
+                    Ok(()) => linger(&stream),
+                    Err(error) => report(error)
+                }
+
+                slots.free();
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{engine, input, InputOptions, Options};
+    use std::{net::TcpStream, thread};
+
+    /// Sends a request to the server, returning the status line and body.
+    fn get(addr: &str, path: &str) -> (String, serde_json::Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut response = String::new();
+
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        stream
+            .read_to_string(&mut response)
+            .unwrap();
+
+        let (head, body) = response
+            .split_once("\r\n\r\n")
+            .unwrap();
+        let status = head.lines().next().unwrap();
+
+        (status.to_string(), serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn bare_ports_listen_everywhere() {
+        assert_eq!(listen_addr(":8080"), "0.0.0.0:8080");
+        assert_eq!(listen_addr("127.0.0.1:8080"), "127.0.0.1:8080");
+    }
+
+    #[test]
+    fn clients_are_served_as_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let csv = "type,client,tx,amount\n\
+                   deposit,2,1,2.5\n\
+                   deposit,1,2,10.0\n\
+                   withdrawal,1,3,4.0\n";
+        let input_options = InputOptions::default();
+        let mut reader = input::reader(csv.as_bytes(), &input_options);
+        let mut engine = Engine::new(Options::default());
+
+        engine::run(
+            &mut engine,
+            input::transactions(&mut reader, &input_options).unwrap(),
+            &mut Diagnostics::silent(),
+            None
+        )
+        .unwrap();
+
+        // The server never returns, so it's left running.
+        thread::spawn(move || {
+            serve(
+                &listener,
+                &SharedEngine::from(engine),
+                &OutputOptions::default(),
+                &mut Diagnostics::silent()
+            )
+        });
+
+        // A connection that never sends its request doesn't hold up others.
+        let _idle = TcpStream::connect(&addr).unwrap();
+        let (status, client) = get(&addr, "/clients/1");
+
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(
+            client,
+            json!({
+                "client": 1,
+                "available": "6.0000",
+                "held": "0.0000",
+                "total": "6.0000",
+                "locked": false
+            })
+        );
+
+        let (status, clients) = get(&addr, "/clients");
+
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(clients[0]["client"], 1);
+        assert_eq!(clients[1]["available"], "2.5000");
+
+        let (status, error) = get(&addr, "/clients/3");
+
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        assert_eq!(error["error"], "client 3 doesn't exist");
+        assert_eq!(get(&addr, "/clients/x").0, "HTTP/1.1 400 Bad Request");
+
+        // Oversized requests are refused rather than read without end.
+        let long = format!("/clients/{}", "1".repeat(MAX_LINE));
+
+        assert_eq!(get(&addr, &long).0, "HTTP/1.1 414 URI Too Long");
+
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        let mut response = String::new();
+
+        write!(stream, "GET /clients HTTP/1.1\r\n").unwrap();
+
+        for _ in 0..=MAX_HEADERS / 16 {
+            write!(stream, "X-Padding: 1234\r\n").unwrap();
+        }
+
+        write!(stream, "\r\n").unwrap();
+        stream
+            .read_to_string(&mut response)
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large"));
+    }
+
+    #[test]
+    fn connections_beyond_the_limit_wait() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener
+            .local_addr()
+            .unwrap()
+            .to_string();
+
+        thread::spawn(move || {
+            serve_at_most(
+                &listener,
+                &SharedEngine::new(Options::default()),
+                &OutputOptions::default(),
+                &mut Diagnostics::silent(),
+                1
+            )
+        });
+
+        // An idle connection takes up the only slot, so the next request
+        // isn't answered until it closes.
+        let idle = TcpStream::connect(&addr).unwrap();
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        let mut response = String::new();
+
+        write!(stream, "GET /clients HTTP/1.1\r\n\r\n").unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        assert!(stream
+            .read_to_string(&mut response)
+            .is_err());
+        assert!(response.is_empty());
+
+        drop(idle);
+        stream
+            .set_read_timeout(None)
+            .unwrap();
+        stream
+            .read_to_string(&mut response)
+            .unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+}
@@ -0,0 +1,1310 @@
+use crate::{
+    error::ErrorKind, process_with, ClientData, Diagnostics, Options, Transaction, TransactionType
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use csv::{Reader, ReaderBuilder, StringRecord};
+use encoding_rs::Encoding;
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    str::FromStr
+};
+
+/// Options that control how transactions are read.
+#[derive(Default, Debug, Clone)]
+pub struct InputOptions {
+    /// Whether the `type` column is lowercased before it's parsed, so
+    /// `Deposit` and `DEPOSIT` are accepted as well as `deposit`.
+    pub normalize_case: bool,
+
+    /// Whether invisible characters padding the `type` column, like
+    /// zero-width spaces and byte order marks, are stripped before it's
+    /// parsed. Unicode whitespace, including non-breaking spaces, is
+    /// always trimmed. Otherwise such types fail with their raw bytes.
+    pub normalize_type_whitespace: bool,
+
+    /// Whether a deposit, withdrawal or transfer repeating the client and
+    /// transaction id of an earlier one anywhere in the input fails to
+    /// parse, naming both lines. Disputes, resolves and chargebacks refer
+    /// to an earlier transaction, so they're exempt. Every pair seen is
+    /// kept in memory.
+    pub strict_unique_client_tx: bool,
+
+    /// Whether rows with more fields than the header fail to parse. By
+    /// default fields are mapped by header, so extra trailing fields are
+    /// ignored.
+    pub strict_schema: bool,
+
+    /// Whether the input has no header row, in which case fields are read
+    /// by position in the order
+    /// `type,client,tx,amount,counterparty,seq,timestamp`.
+    pub no_header: bool,
+
+    /// Whether the input only has a header row if its first field isn't a
+    /// transaction type. A headerless file whose first row has a malformed
+    /// type is mistaken for one with a header, so its first row is lost
+    /// and the rest fail to map.
+    pub auto_header: bool,
+
+    /// The header names to read each field from, for files that don't
+    /// use the standard names.
+    pub column_map: Vec<ColumnMapping>,
+
+    /// Whether a leading currency symbol, like the `$` in `$10.00`, is
+    /// stripped from amounts. Otherwise such amounts fail to parse.
+    pub strip_currency_symbol: bool,
+
+    /// The character starting comment lines, which are skipped. When
+    /// unset, no lines are comments.
+    pub comment_char: Option<u8>,
+
+    /// The encoding the input is transcoded to UTF-8 from, such as
+    /// Windows-1252 for some legacy exports. When unset, the input must
+    /// already be UTF-8.
+    pub encoding: Option<&'static Encoding>,
+
+    /// The most bytes of input read before failing, so oversized inputs
+    /// are rejected rather than processed. When unset, inputs may be any
+    /// size.
+    pub max_size: Option<u64>
+}
+
+/// The currency symbols recognized at the start of an amount.
+const CURRENCY_SYMBOLS: [char; 8] = ['$', '€', '£', '¥', '₹', '₩', '₽', '₿'];
+
+/// Whether a character is invisible padding, being whitespace or a
+/// zero-width character.
+fn is_padding(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '\u{200b}'..='\u{200d}' | '\u{2060}' | '\u{feff}')
+}
+
+/// The fields a transaction is read from.
+const FIELDS: [&str; 7] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "counterparty",
+    "seq",
+    "timestamp"
+];
+
+/// The header a field is read from, parsed from `field=header`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub field:  String,
+    pub header: String
+}
+
+impl FromStr for ColumnMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (field, header) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected field=header, got {s}"))?;
+        let field = field.trim();
+
+        if !FIELDS.contains(&field) {
+            bail!(
+                "unknown field {field}, expected one of {}",
+                FIELDS.join(", ")
+            );
+        }
+
+        Ok(Self {
+            field:  field.to_string(),
+            header: header.trim().to_string()
+        })
+    }
+}
+
+/// Wraps a reader, failing once more than a maximum number of bytes are
+/// read. This enforces `max_size` on streams whose size isn't known up
+/// front, like stdin or a socket.
+#[derive(Debug)]
+pub struct Limited<R> {
+    /// The wrapped reader.
+    inner: R,
+
+    /// The bytes read so far.
+    read: u64,
+
+    /// The most bytes that may be read.
+    max: Option<u64>
+}
+
+impl<R> Limited<R> {
+    /// Wraps a reader that may read at most `max` bytes.
+    pub fn new(inner: R, max: Option<u64>) -> Self {
+        Self {
+            inner,
+            read: 0,
+            max
+        }
+    }
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+
+        self.read += n as u64;
+
+        if let Some(max) = self.max
+            && self.read > max
+        {
+            return Err(io::Error::other(format!(
+                "the input is larger than the maximum of {max} bytes"
+            )));
+        }
+
+        Ok(n)
+    }
+}
+
+/// Makes sure a file is no larger than `max_size`, if set, before any of
+/// it is read.
+pub fn check_size(file: &File, options: &InputOptions) -> Result<()> {
+    let Some(max) = options.max_size else {
+        return Ok(());
+    };
+
+    let len = file.metadata()?.len();
+
+    if len > max {
+        bail!("the input is {len} bytes, larger than the maximum of {max} bytes");
+    }
+
+    Ok(())
+}
+
+/// Creates a csv reader for transactions, failing once more than
+/// `max_size` bytes are read.
+pub fn reader<R: Read>(
+    input: R,
+    options: &InputOptions
+) -> Reader<DecodeReaderBytes<Limited<R>, Vec<u8>>> {
+    // Transcode the input if it isn't UTF-8, otherwise pass it through
+    // untouched.
+    let input = DecodeReaderBytesBuilder::new()
+        .encoding(options.encoding)
+        .utf8_passthru(true)
+        .bom_sniffing(false)
+        .build(Limited::new(input, options.max_size));
+
+    // Allow for whitespace and missing columns.
+    ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .comment(options.comment_char)
+        .has_headers(!options.no_header)
+        .from_reader(input)
+}
+
+/// Parses an encoding label, like `latin1` or `windows-1252`.
+pub fn encoding(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or_else(|| anyhow!("unknown encoding {label}"))
+}
+
+/// Deserializes transactions from a csv reader.
+pub fn transactions<'a, R: Read>(
+    reader: &'a mut Reader<R>,
+    options: &InputOptions
+) -> Result<impl Iterator<Item = Result<Transaction>> + 'a> {
+    Ok(located_transactions(reader, options)?.map(|tx| tx.map(|(_, tx)| tx)))
+}
+
+/// Deserializes transactions from a csv reader like `transactions`, along
+/// with the line each starts on.
+pub fn located_transactions<'a, R: Read>(
+    reader: &'a mut Reader<R>,
+    options: &InputOptions
+) -> Result<impl Iterator<Item = Result<(u64, Transaction)>> + 'a> {
+    let (mut parser, first) = if options.no_header {
+        (RecordParser::positional(options), None)
+    } else {
+        let headers = reader.headers()?.clone();
+
+        match RecordParser::under(&headers, options) {
+            // The header row is really the first record.
+            (parser, true) => (parser, Some(headers)),
+            (parser, false) => (parser, None)
+        }
+    };
+
+    let records = first
+        .map(Ok)
+        .into_iter()
+        .chain(reader.records());
+
+    Ok(records.map(move |record| parser.parse(record?)))
+}
+
+/// Turns csv records into transactions, keeping the state that spans
+/// records: where each field is and, if asked to, every client and
+/// transaction id pair seen.
+#[derive(Debug, Clone)]
+pub struct RecordParser {
+    /// The input options.
+    options: InputOptions,
+
+    /// The header names, in the order their fields appear.
+    headers: StringRecord,
+
+    /// Where the `type` field is.
+    kind: Option<usize>,
+
+    /// Where the `tx` field is.
+    tx: Option<usize>,
+
+    /// Where the `amount` field is.
+    amount: Option<usize>,
+
+    /// Where each id field is, with its name and largest value.
+    ids: [(Option<usize>, &'static str, u64); 2],
+
+    /// The line each client and transaction id pair was first seen on.
+    seen: HashMap<(u16, u32), u64>
+}
+
+impl RecordParser {
+    /// Creates a parser reading fields by position, in the order
+    /// `type,client,tx,amount,counterparty,seq,timestamp`.
+    pub fn positional(options: &InputOptions) -> Self {
+        Self::new(StringRecord::from(FIELDS.to_vec()), options)
+    }
+
+    /// Creates a parser reading fields under a header row. With
+    /// `auto_header`, a row that's really a record gives a positional
+    /// parser instead, along with `true` so the row can be parsed as the
+    /// first record.
+    pub fn under(row: &StringRecord, options: &InputOptions) -> (Self, bool) {
+        if options.auto_header && is_record(row, options) {
+            (Self::positional(options), true)
+        } else {
+            (
+                Self::new(mapped_headers(row, &options.column_map), options),
+                false
+            )
+        }
+    }
+
+    /// Creates a parser reading fields under the given header names.
+    fn new(headers: StringRecord, options: &InputOptions) -> Self {
+        let position = |name| {
+            headers
+                .iter()
+                .position(|h| h == name)
+        };
+
+        Self {
+            kind: position("type"),
+            tx: position("tx"),
+            amount: position("amount"),
+            ids: [
+                (position("client"), "client", u64::from(u16::MAX)),
+                (position("tx"), "tx", u64::from(u32::MAX))
+            ],
+            options: options.clone(),
+            seen: HashMap::new(),
+            headers
+        }
+    }
+
+    /// Parses a record into a transaction, along with the line it starts
+    /// on.
+    pub fn parse(&mut self, mut record: StringRecord) -> Result<(u64, Transaction)> {
+        let options = &self.options;
+        let line = record
+            .position()
+            .map_or(0, |p| p.line());
+
+        // Reject extra trailing fields if asked to.
+        if options.strict_schema && record.len() > self.headers.len() {
+            bail!(ErrorKind::MalformedRow.error(format!(
+                "{} has {} fields but the header has {}",
+                location(&record),
+                record.len(),
+                self.headers.len()
+            )));
+        }
+
+        // Give out of range ids a clearer error than serde would.
+        for (index, name, max) in self.ids {
+            if let Some(field) = index.and_then(|i| record.get(i)) {
+                check_id(&record, field, name, max)?;
+            }
+        }
+
+        // Strip or reject invisible padding around the transaction type.
+        if let Some(kind) = self.kind
+            && let Some(field) = record.get(kind)
+            && field.trim_matches(is_padding) != field
+        {
+            if !options.normalize_type_whitespace {
+                let bytes = field
+                    .bytes()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<_>>();
+
+                bail!(ErrorKind::MalformedRow.error(format!(
+                    "{} has type {:?} padded with invisible characters, bytes {}",
+                    location(&record),
+                    field,
+                    bytes.join(" ")
+                )));
+            }
+
+            let stripped = field
+                .trim_matches(is_padding)
+                .to_string();
+
+            record = replace_field(&record, kind, &stripped);
+        }
+
+        // Normalize the transaction type if asked to.
+        if options.normalize_case
+            && let Some(kind) = self.kind
+        {
+            let lowered = record[kind].to_lowercase();
+
+            record = replace_field(&record, kind, &lowered);
+        }
+
+        // Strip or reject a leading currency symbol.
+        if let Some(amount) = self.amount
+            && let Some(field) = record.get(amount)
+            && let Some(stripped) = field.strip_prefix(CURRENCY_SYMBOLS)
+        {
+            if !options.strip_currency_symbol {
+                bail!(ErrorKind::MalformedRow.error(format!(
+                    "{} has amount {:?} for tx {}, which has a currency symbol",
+                    location(&record),
+                    field,
+                    self.tx
+                        .and_then(|i| record.get(i))
+                        .unwrap_or_default()
+                )));
+            }
+
+            let stripped = stripped.trim_start().to_string();
+
+            record = replace_field(&record, amount, &stripped);
+        }
+
+        let tx: Transaction = record.deserialize(Some(&self.headers))?;
+
+        // Make sure no client reuses a transaction id if asked to.
+        if options.strict_unique_client_tx
+            && matches!(
+                tx.kind,
+                TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+            )
+            && let Some(first) = self
+                .seen
+                .insert((tx.client, tx.tx), line)
+        {
+            bail!(ErrorKind::DuplicateTx.error(format!(
+                "line {line} repeats client {} and tx {} from line {first}",
+                tx.client, tx.tx
+            )));
+        }
+
+        Ok((line, tx))
+    }
+}
+
+/// Parses csv that arrives a few complete lines at a time, like a file
+/// being tailed or a stream read a line at a time, as a single reader
+/// would parse it all at once. The header, line numbers, byte count and
+/// `strict_unique_client_tx` pairs carry over from chunk to chunk, and
+/// the input fails once more than `max_size` bytes have arrived.
+#[derive(Debug, Clone)]
+pub struct StreamParser {
+    /// The input options.
+    options: InputOptions,
+
+    /// The parser for records, once the header, if any, has arrived.
+    parser: Option<RecordParser>,
+
+    /// The lines that have arrived.
+    lines: u64,
+
+    /// The bytes that have arrived.
+    bytes: u64
+}
+
+impl StreamParser {
+    /// Creates a parser for a stream that hasn't sent anything yet.
+    pub fn new(options: &InputOptions) -> Self {
+        Self {
+            options: options.clone(),
+            parser:  options
+                .no_header
+                .then(|| RecordParser::positional(options)),
+            lines:   0,
+            bytes:   0
+        }
+    }
+
+    /// Parses a chunk of complete lines, returning each transaction in it
+    /// along with the line it starts on in the whole stream, or why its
+    /// record failed to parse. Only fails if the stream is now too large.
+    pub fn parse(&mut self, chunk: &[u8]) -> Result<Vec<Result<(u64, Transaction)>>> {
+        let (lines, bytes) = (self.lines, self.bytes);
+
+        self.skip(chunk)?;
+
+        // Split the chunk into records, none of them a header, numbered
+        // from where the chunk starts in the stream.
+        let options = InputOptions {
+            no_header: true,
+            max_size: None,
+            ..self.options.clone()
+        };
+        let mut reader = reader(chunk, &options);
+        let mut txs = Vec::new();
+
+        for record in reader.records() {
+            let mut record = match record {
+                Ok(record) => record,
+                Err(error) => {
+                    txs.push(Err(error.into()));
+                    continue;
+                }
+            };
+
+            if let Some(position) = record.position() {
+                let mut moved = position.clone();
+
+                moved
+                    .set_line(lines + position.line())
+                    .set_byte(bytes + position.byte());
+                record.set_position(Some(moved));
+            }
+
+            let parser = match &mut self.parser {
+                Some(parser) => parser,
+                None => {
+                    let (parser, is_record) = RecordParser::under(&record, &self.options);
+                    let parser = self.parser.insert(parser);
+
+                    if !is_record {
+                        continue;
+                    }
+
+                    parser
+                }
+            };
+
+            txs.push(parser.parse(record));
+        }
+
+        Ok(txs)
+    }
+
+    /// Counts a chunk of complete lines that aren't csv, like a control
+    /// line, towards the stream's lines and bytes.
+    pub fn skip(&mut self, chunk: &[u8]) -> Result<()> {
+        self.lines += chunk
+            .iter()
+            .filter(|&&b| b == b'\n')
+            .count() as u64;
+        self.bytes += chunk.len() as u64;
+
+        if let Some(max) = self.options.max_size
+            && self.bytes > max
+        {
+            bail!("the input is larger than the maximum of {max} bytes");
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads and processes csv transactions.
+pub fn process_csv<R: Read, W: Write>(
+    input: R,
+    options: &Options,
+    input_options: &InputOptions,
+    diagnostics: &mut Diagnostics<W>
+) -> Result<HashMap<u16, ClientData>> {
+    let mut reader = reader(input, input_options);
+
+    process_with(
+        transactions(&mut reader, input_options)?,
+        options,
+        diagnostics
+    )
+}
+
+/// Parses newline-delimited json, one transaction object per line, like
+/// `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`. Amounts may be
+/// strings or numbers, and blank lines are skipped.
+pub fn ndjson_transactions<R: BufRead>(input: R) -> impl Iterator<Item = Result<Transaction>> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            line.as_ref()
+                .map_or(true, |line| !line.trim().is_empty())
+        })
+        .map(|(index, line)| {
+            let line = line?;
+            let tx = serde_json::from_str(&line).map_err(|error| {
+                ErrorKind::MalformedRow
+                    .error(format!("line {} isn't a transaction: {error}", index + 1))
+            })?;
+
+            Ok(tx)
+        })
+}
+
+/// Processes newline-delimited json transactions, returning the final
+/// client data like `process_csv`.
+pub fn process_ndjson<R: BufRead, W: Write>(
+    input: R,
+    options: &Options,
+    diagnostics: &mut Diagnostics<W>
+) -> Result<HashMap<u16, ClientData>> {
+    process_with(ndjson_transactions(input), options, diagnostics)
+}
+
+/// A range of time to process transactions from, parsed from
+/// `start..end` with ISO-8601 timestamps. Either end may be left off, so
+/// `start..` has no end. The start is inclusive and the end exclusive.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: Option<DateTime<Utc>>,
+    pub end:   Option<DateTime<Utc>>
+}
+
+impl TimeWindow {
+    /// Whether a timestamp falls within the window.
+    pub fn contains(&self, timestamp: DateTime<Utc>) -> bool {
+        self.start
+            .is_none_or(|start| timestamp >= start)
+            && self
+                .end
+                .is_none_or(|end| timestamp < end)
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| anyhow!("expected start..end, got {s}"))?;
+        let parse = |timestamp: &str| {
+            let timestamp = timestamp.trim();
+
+            (!timestamp.is_empty())
+                .then(|| {
+                    DateTime::parse_from_rfc3339(timestamp)
+                        .map(|t| t.with_timezone(&Utc))
+                        .map_err(|e| anyhow!("invalid timestamp {timestamp:?}: {e}"))
+                })
+                .transpose()
+        };
+
+        Ok(Self {
+            start: parse(start)?,
+            end:   parse(end)?
+        })
+    }
+}
+
+/// Selects the transactions within a window of time. Transactions
+/// without a timestamp fail, since there's no telling if they're in it.
+/// Disputes, resolves and chargebacks inside the window that refer to
+/// deposits outside it are skipped as unknown transactions, since those
+/// deposits are never seen.
+pub fn within<I>(txs: I, window: TimeWindow) -> impl Iterator<Item = Result<Transaction>>
+where
+    I: IntoIterator<Item = Result<Transaction>>
+{
+    txs.into_iter()
+        .filter_map(move |tx| match tx {
+            Ok(tx) => match tx.timestamp {
+                Some(timestamp) => window
+                    .contains(timestamp)
+                    .then_some(Ok(tx)),
+                None => Some(Err(anyhow!(
+                    "transaction {} has no timestamp to window by",
+                    tx.tx
+                )))
+            },
+            Err(e) => Some(Err(e))
+        })
+}
+
+/// Buffers every transaction and sorts them by their `seq` column, for
+/// sources that emit transactions out of order. This trades holding the
+/// whole input in memory for processing it in the intended order.
+/// Transactions with the same `seq` keep their order in the input, and
+/// any without one fail.
+pub fn sort_by_seq<I>(txs: I) -> Result<Vec<Transaction>>
+where
+    I: IntoIterator<Item = Result<Transaction>>
+{
+    let mut txs = txs
+        .into_iter()
+        .map(|tx| {
+            let tx = tx?;
+            let seq = tx
+                .seq
+                .ok_or_else(|| anyhow!("transaction {} has no seq to sort by", tx.tx))?;
+
+            Ok((seq, tx))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    txs.sort_by_key(|&(seq, _)| seq);
+
+    Ok(txs
+        .into_iter()
+        .map(|(_, tx)| tx)
+        .collect())
+}
+
+/// Whether the first row of a file is a record rather than a header,
+/// going by whether its first field is a transaction type.
+fn is_record(row: &StringRecord, options: &InputOptions) -> bool {
+    row.get(0)
+        .is_some_and(|field| TransactionType::from_str(field, options.normalize_case).is_ok())
+}
+
+/// Returns the headers with mapped header names renamed to the fields
+/// they're read into.
+fn mapped_headers(headers: &StringRecord, column_map: &[ColumnMapping]) -> StringRecord {
+    headers
+        .iter()
+        .map(|h| {
+            column_map
+                .iter()
+                .rev()
+                .find(|m| m.header == h)
+                .map_or(h, |m| m.field.as_str())
+        })
+        .collect()
+}
+
+/// Reads client ids, one per line. Blank lines and lines starting with
+/// `#` are ignored.
+pub fn read_client_ids<R: Read>(input: R) -> Result<HashSet<u16>> {
+    BufReader::new(input)
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| match line {
+            Ok(line) => {
+                let line = line.trim();
+
+                (!line.is_empty() && !line.starts_with('#')).then(|| {
+                    line.parse()
+                        .map_err(|_| anyhow!("line {} has invalid client id {line:?}", i + 1))
+                })
+            },
+            Err(e) => Some(Err(e.into()))
+        })
+        .collect()
+}
+
+/// Describes where a record starts in the input, as its line and byte
+/// offset, so errors can be traced back to the file.
+fn location(record: &StringRecord) -> String {
+    record.position().map_or_else(
+        || "an unknown line".to_string(),
+        |p| format!("line {} (byte {})", p.line(), p.byte())
+    )
+}
+
+/// Selects records `[skip, skip + take)` of a stream, for debugging a
+/// window of a large file. Records outside the window are still parsed,
+/// so they can still fail processing. Disputes, resolves and chargebacks
+/// inside the window that refer to deposits before it are skipped as
+/// unknown transactions.
+pub fn window<I: Iterator>(
+    txs: I,
+    skip: usize,
+    take: Option<usize>
+) -> impl Iterator<Item = I::Item> {
+    txs.skip(skip)
+        .take(take.unwrap_or(usize::MAX))
+}
+
+/// Makes sure an id field is a number from zero to `max`.
+fn check_id(record: &StringRecord, field: &str, name: &str, max: u64) -> Result<()> {
+    let message = match field.parse::<i128>() {
+        Ok(id) if (0..=i128::from(max)).contains(&id) => return Ok(()),
+        Ok(id) => format!(
+            "{} has {name} id {id}, which is out of range (0 to {max})",
+            location(record)
+        ),
+        Err(_) => format!(
+            "{} has {name} id {field:?}, which is not a number",
+            location(record)
+        )
+    };
+
+    bail!(ErrorKind::MalformedRow.error(message))
+}
+
+/// Returns a copy of the record with one field replaced.
+fn replace_field(record: &StringRecord, index: usize, value: &str) -> StringRecord {
+    let mut replaced = record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| if i == index { value } else { field })
+        .collect::<StringRecord>();
+
+    replaced.set_position(record.position().cloned());
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process, process_slice, ProcessError, TransactionType};
+    use rust_decimal_macros::dec;
+
+    fn parse(csv: &str, options: &InputOptions) -> Result<Vec<Transaction>> {
+        let mut reader = reader(csv.as_bytes(), options);
+
+        transactions(&mut reader, options)?.collect()
+    }
+
+    #[test]
+    fn mixed_case_types_are_rejected_by_default() {
+        let csv = "type,client,tx,amount\nDEPOSIT,1,1,1.0\n";
+
+        assert!(parse(csv, &InputOptions::default()).is_err());
+    }
+
+    #[test]
+    fn mixed_case_types_parse_when_normalized() {
+        let csv = "type,client,tx,amount\nDEPOSIT,1,1,2.0\nWithdrawal,1,2,1.0\n";
+        let options = InputOptions {
+            normalize_case: true,
+            ..InputOptions::default()
+        };
+        let txs = parse(csv, &options).unwrap();
+
+        assert_eq!(txs[0].kind, TransactionType::Deposit);
+        assert_eq!(txs[0].amount, Some(dec!(2.0)));
+        assert_eq!(txs[1].kind, TransactionType::Withdrawal);
+        assert_eq!(txs[1].amount, Some(dec!(1.0)));
+    }
+
+    #[test]
+    fn extra_fields_are_ignored_by_default() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,2.0,7,extra\ndeposit,1,2,3.0\n";
+        let txs = parse(csv, &InputOptions::default()).unwrap();
+
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0].amount, Some(dec!(2.0)));
+        assert_eq!(txs[0].counterparty, None);
+        assert_eq!(txs[1].amount, Some(dec!(3.0)));
+    }
+
+    #[test]
+    fn malformed_rows_report_their_byte_offset() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,abc\n";
+        let err = parse(csv, &InputOptions::default()).unwrap_err();
+
+        // The header and first row take up 38 bytes.
+        assert!(err
+            .to_string()
+            .contains("line: 3, byte: 38"));
+    }
+
+    #[test]
+    fn window_selects_records() {
+        let csv = (1..=6)
+            .map(|tx| format!("deposit,1,{tx},1.0\n"))
+            .collect::<String>();
+        let csv = format!("type,client,tx,amount\n{csv}");
+        let ids = |skip, take| {
+            let mut reader = reader(csv.as_bytes(), &InputOptions::default());
+            let txs = transactions(&mut reader, &InputOptions::default()).unwrap();
+
+            window(txs, skip, take)
+                .map(|tx| tx.unwrap().tx)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(2, Some(3)), vec![3, 4, 5]);
+        assert_eq!(ids(4, None), vec![5, 6]);
+        assert_eq!(ids(0, Some(0)), Vec::<u32>::new());
+        assert_eq!(ids(10, Some(2)), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn sorting_by_seq_restores_the_intended_order() {
+        let csv = "type,client,tx,amount,seq\n\
+                   resolve,1,1,,4\n\
+                   dispute,1,1,,2\n\
+                   deposit,1,1,5.0,1\n\
+                   withdrawal,1,2,1.0,3\n";
+        let txs = || parse(csv, &InputOptions::default()).unwrap();
+
+        // In file order the dispute and resolve refer to nothing yet, so
+        // the withdrawal goes through.
+        let unsorted = process_slice(&txs(), &Options::default()).unwrap();
+
+        assert_eq!(unsorted[&1].available, dec!(4.0));
+
+        // In seq order the dispute holds the funds, so the withdrawal is
+        // skipped, and the resolve releases them.
+        let sorted = sort_by_seq(txs().into_iter().map(Ok)).unwrap();
+        let ids = sorted
+            .iter()
+            .map(|tx| tx.seq.unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+
+        let clients = process_slice(&sorted, &Options::default()).unwrap();
+
+        assert_eq!(clients[&1].available, dec!(5.0));
+        assert_eq!(clients[&1].held, dec!(0.0));
+        assert_eq!(clients[&1].total, dec!(5.0));
+    }
+
+    #[test]
+    fn sorting_by_seq_needs_a_seq() {
+        let csv = "type,client,tx,amount,seq\ndeposit,1,1,5.0,1\ndeposit,1,2,5.0,\n";
+        let txs = parse(csv, &InputOptions::default()).unwrap();
+
+        assert!(sort_by_seq(txs.into_iter().map(Ok)).is_err());
+    }
+
+    #[test]
+    fn time_windows_are_parsed() {
+        let window = "2024-01-01T00:00:00Z..2024-02-01T00:00:00+01:00"
+            .parse::<TimeWindow>()
+            .unwrap();
+
+        assert_eq!(
+            window.start.unwrap().to_rfc3339(),
+            "2024-01-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            window.end.unwrap().to_rfc3339(),
+            "2024-01-31T23:00:00+00:00"
+        );
+
+        let open = "2024-01-01T00:00:00Z.."
+            .parse::<TimeWindow>()
+            .unwrap();
+
+        assert!(open.end.is_none());
+        assert!("2024-01-01"
+            .parse::<TimeWindow>()
+            .is_err());
+        assert!("yesterday.."
+            .parse::<TimeWindow>()
+            .is_err());
+    }
+
+    #[test]
+    fn windowing_excludes_transactions_outside_it() {
+        let csv = "type,client,tx,amount,timestamp\n\
+                   deposit,1,1,5.0,2024-01-01T09:00:00Z\n\
+                   deposit,1,2,3.0,2024-01-02T09:00:00Z\n\
+                   withdrawal,1,3,1.0,2024-01-02T17:30:00Z\n\
+                   dispute,1,1,,2024-01-02T18:00:00Z\n\
+                   deposit,1,4,7.0,2024-01-03T00:00:00Z\n";
+        let window = "2024-01-02T00:00:00Z..2024-01-03T00:00:00Z"
+            .parse::<TimeWindow>()
+            .unwrap();
+        let txs = parse(csv, &InputOptions::default()).unwrap();
+        let ids = within(txs.into_iter().map(Ok), window)
+            .map(|tx| tx.unwrap().tx)
+            .collect::<Vec<_>>();
+
+        // The end is exclusive, so deposit 4 is out.
+        assert_eq!(ids, vec![2, 3, 1]);
+
+        // The dispute refers to a deposit before the window, so it's
+        // skipped.
+        let txs = parse(csv, &InputOptions::default()).unwrap();
+        let clients =
+            process(within(txs.into_iter().map(Ok), window), &Options::default()).unwrap();
+
+        assert_eq!(clients[&1].available, dec!(2.0));
+        assert_eq!(clients[&1].held, dec!(0.0));
+    }
+
+    #[test]
+    fn windowing_needs_a_timestamp() {
+        let csv = "type,client,tx,amount,timestamp\ndeposit,1,1,5.0,\n";
+        let txs = parse(csv, &InputOptions::default()).unwrap();
+
+        assert!(within(txs.into_iter().map(Ok), TimeWindow::default())
+            .next()
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn extra_fields_fail_with_strict_schema() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,2.0,7,extra\n";
+        let options = InputOptions {
+            strict_schema: true,
+            ..InputOptions::default()
+        };
+        let err = parse(csv, &options).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "line 2 (byte 22) has 6 fields but the header has 4"
+        );
+    }
+
+    #[test]
+    fn renamed_columns_are_mapped() {
+        let csv = "action,account,id,value\n\
+                   deposit,1,1,10.0\n\
+                   withdrawal,1,2,4.0\n\
+                   deposit,2,3,3.0\n\
+                   dispute,2,3,\n";
+        let options = InputOptions {
+            column_map: ["type=action", "client=account", "tx=id", "amount=value"]
+                .into_iter()
+                .map(|m| m.parse().unwrap())
+                .collect(),
+            ..InputOptions::default()
+        };
+        let clients = process_csv(
+            csv.as_bytes(),
+            &Options::default(),
+            &options,
+            &mut Diagnostics::silent()
+        )
+        .unwrap();
+
+        assert_eq!(clients[&1].available, dec!(6.0));
+        assert_eq!(clients[&1].total, dec!(6.0));
+        assert_eq!(clients[&2].available, dec!(0.0));
+        assert_eq!(clients[&2].held, dec!(3.0));
+    }
+
+    #[test]
+    fn unknown_mapped_fields_fail() {
+        let err = "kind=action"
+            .parse::<ColumnMapping>()
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "unknown field kind, expected one of type, client, tx, amount, counterparty, seq, \
+             timestamp"
+        );
+    }
+
+    #[test]
+    fn comment_lines_are_skipped() {
+        let csv = "# exported from ledger v2\n\
+                   type,client,tx,amount\n\
+                   deposit,1,1,2.0\n\
+                   # source: branch 7\n\
+                   deposit,1,2,3.0\n\
+                   #withdrawal,1,3,5.0\n";
+        let options = InputOptions {
+            comment_char: Some(b'#'),
+            ..InputOptions::default()
+        };
+        let txs = parse(csv, &options).unwrap();
+
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0].amount, Some(dec!(2.0)));
+        assert_eq!(txs[1].amount, Some(dec!(3.0)));
+    }
+
+    #[test]
+    fn comment_lines_fail_by_default() {
+        let csv = "type,client,tx,amount\n# note\ndeposit,1,1,2.0\n";
+
+        assert!(parse(csv, &InputOptions::default()).is_err());
+    }
+
+    #[test]
+    fn out_of_range_ids_fail_clearly() {
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,70000,2,1.0\n";
+        let err = parse(csv, &InputOptions::default()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "line 3 (byte 38) has client id 70000, which is out of range (0 to 65535)"
+        );
+
+        let csv = "type,client,tx,amount\ndeposit,1,4294967296,1.0\n";
+        let err = parse(csv, &InputOptions::default()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "line 2 (byte 22) has tx id 4294967296, which is out of range (0 to 4294967295)"
+        );
+    }
+
+    #[test]
+    fn non_numeric_ids_fail_clearly() {
+        let csv = "type,client,tx,amount\ndeposit,-1,1,1.0\ndeposit,abc,2,1.0\n";
+        let mut reader = reader(csv.as_bytes(), &InputOptions::default());
+        let errors = transactions(&mut reader, &InputOptions::default())
+            .unwrap()
+            .map(|tx| tx.unwrap_err().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            errors,
+            vec![
+                "line 2 (byte 22) has client id -1, which is out of range (0 to 65535)",
+                "line 3 (byte 39) has client id \"abc\", which is not a number"
+            ]
+        );
+    }
+
+    #[test]
+    fn client_ids_are_read_one_per_line() {
+        let ids = read_client_ids("# shard 1\n1\n\n 7 \n".as_bytes()).unwrap();
+
+        assert_eq!(ids, HashSet::from([1, 7]));
+
+        let err = read_client_ids("1\nx\n".as_bytes()).unwrap_err();
+
+        assert_eq!(err.to_string(), "line 2 has invalid client id \"x\"");
+    }
+
+    #[test]
+    fn invisible_type_padding_fails_clearly() {
+        let csv = "type,client,tx,amount\n\u{a0}deposit\u{a0},1,7,1.0\n\u{200b}deposit,1,8,1.0\n";
+        let err = parse(csv, &InputOptions::default()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "line 3 (byte 42) has type \"\\u{200b}deposit\" padded with invisible characters, \
+             bytes e2 80 8b 64 65 70 6f 73 69 74"
+        );
+    }
+
+    #[test]
+    fn invisible_type_padding_is_stripped_if_asked() {
+        let csv = "type,client,tx,amount\n\u{a0}deposit\u{a0},1,7,1.0\n\u{feff}\u{200b}deposit\u{2060},1,8,1.0\n";
+        let options = InputOptions {
+            normalize_type_whitespace: true,
+            ..InputOptions::default()
+        };
+        let txs = parse(csv, &options).unwrap();
+
+        assert_eq!(txs.len(), 2);
+        assert!(txs
+            .iter()
+            .all(|tx| tx.kind == TransactionType::Deposit));
+    }
+
+    #[test]
+    fn currency_symbols_fail_clearly() {
+        let csv = "type,client,tx,amount\ndeposit,1,7,$10.00\n";
+        let err = parse(csv, &InputOptions::default()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "line 2 (byte 22) has amount \"$10.00\" for tx 7, which has a currency symbol"
+        );
+    }
+
+    #[test]
+    fn currency_symbols_are_stripped_if_asked() {
+        let csv = "type,client,tx,amount\ndeposit,1,7,$10.00\ndeposit,1,8,€ 2.5\n";
+        let options = InputOptions {
+            strip_currency_symbol: true,
+            ..InputOptions::default()
+        };
+        let txs = parse(csv, &options).unwrap();
+
+        assert_eq!(txs[0].amount, Some(dec!(10.00)));
+        assert_eq!(txs[1].amount, Some(dec!(2.5)));
+    }
+
+    #[test]
+    fn headerless_input_is_read_by_position() {
+        let csv = "deposit,1,1,10.0\nwithdrawal,1,2,4.0\ndeposit,2,3,1.5\ndispute,2,3\n";
+        let options = InputOptions {
+            no_header: true,
+            ..InputOptions::default()
+        };
+        let clients = process_csv(
+            csv.as_bytes(),
+            &Options::default(),
+            &options,
+            &mut Diagnostics::silent()
+        )
+        .unwrap();
+
+        assert_eq!(clients[&1].available, dec!(6.0));
+        assert_eq!(clients[&2].available, dec!(0.0));
+        assert_eq!(clients[&2].held, dec!(1.5));
+    }
+
+    #[test]
+    fn client_tx_pairs_can_be_required_unique() {
+        let options = InputOptions {
+            strict_unique_client_tx: true,
+            ..InputOptions::default()
+        };
+        let distinct = "type,client,tx,amount\n\
+                        deposit,1,1,10.0\n\
+                        deposit,2,1,5.0\n\
+                        dispute,1,1,\n\
+                        withdrawal,1,2,4.0\n";
+
+        assert_eq!(
+            parse(distinct, &options)
+                .unwrap()
+                .len(),
+            4
+        );
+
+        let repeated = "type,client,tx,amount\n\
+                        deposit,1,1,10.0\n\
+                        deposit,2,1,5.0\n\
+                        withdrawal,1,1,4.0\n";
+        let error = parse(repeated, &options).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "line 4 repeats client 1 and tx 1 from line 2"
+        );
+        assert_eq!(ProcessError::from(error).kind, ErrorKind::DuplicateTx);
+        assert!(parse(repeated, &InputOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn headers_are_detected_if_asked() {
+        let options = InputOptions {
+            auto_header: true,
+            ..InputOptions::default()
+        };
+        let headered = "type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n";
+        let headerless = "deposit,1,1,10.0\nwithdrawal,1,2,4.0\n";
+
+        for csv in [headered, headerless] {
+            let txs = parse(csv, &options).unwrap();
+
+            assert_eq!(txs.len(), 2, "{csv:?}");
+            assert_eq!(txs[0].kind, TransactionType::Deposit);
+            assert_eq!(txs[0].amount, Some(dec!(10.0)));
+            assert_eq!(txs[1].kind, TransactionType::Withdrawal);
+        }
+
+        // Reordered headers are still read by name.
+        let txs = parse("client,type,tx,amount\n1,deposit,1,10.0\n", &options).unwrap();
+
+        assert_eq!(txs[0].client, 1);
+        assert_eq!(txs[0].amount, Some(dec!(10.0)));
+    }
+
+    /// Parses chunks of a stream, returning each transaction along with
+    /// its line, or its error.
+    fn parse_chunks(chunks: &[&str], options: &InputOptions) -> Vec<Result<(u64, Transaction)>> {
+        let mut parser = StreamParser::new(options);
+
+        chunks
+            .iter()
+            .flat_map(|chunk| {
+                parser
+                    .parse(chunk.as_bytes())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn streams_parse_like_a_single_reader() {
+        let chunks = [
+            "type,client,tx,amount\ndeposit,1,1,10.0\n",
+            "withdrawal,1,2,4.0\n"
+        ];
+        let txs = parse_chunks(&chunks, &InputOptions::default())
+            .into_iter()
+            .map(|tx| tx.unwrap())
+            .collect::<Vec<_>>();
+
+        // The header is only read once and lines count across chunks.
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0].0, 2);
+        assert_eq!(txs[1].0, 3);
+        assert_eq!(txs[1].1.kind, TransactionType::Withdrawal);
+
+        // Without a header every line is a transaction.
+        let headerless = InputOptions {
+            no_header: true,
+            ..InputOptions::default()
+        };
+
+        assert_eq!(
+            parse_chunks(&["deposit,1,1,10.0\n", "deposit,1,2,1.0\n"], &headerless).len(),
+            2
+        );
+
+        // Detected headers are only looked for in the first line.
+        let auto = InputOptions {
+            auto_header: true,
+            ..InputOptions::default()
+        };
+
+        for first in ["type,client,tx,amount\n", ""] {
+            let chunks = [first, "deposit,1,1,10.0\n", "deposit,1,2,1.0\n"];
+
+            assert_eq!(parse_chunks(&chunks, &auto).len(), 2, "{first:?}");
+        }
+    }
+
+    #[test]
+    fn streams_keep_state_across_chunks() {
+        let options = InputOptions {
+            strict_unique_client_tx: true,
+            ..InputOptions::default()
+        };
+        let chunks = [
+            "type,client,tx,amount\ndeposit,1,1,10.0\n",
+            "\n",
+            "deposit,1,1,1.0\n"
+        ];
+        let txs = parse_chunks(&chunks, &options);
+
+        assert_eq!(
+            txs[1]
+                .as_ref()
+                .unwrap_err()
+                .to_string(),
+            "line 4 repeats client 1 and tx 1 from line 2"
+        );
+
+        let options = InputOptions {
+            max_size: Some(40),
+            ..InputOptions::default()
+        };
+        let mut parser = StreamParser::new(&options);
+
+        parser
+            .parse(b"type,client,tx,amount\ndeposit,1,1,1.0\n")
+            .unwrap();
+
+        assert_eq!(
+            parser
+                .parse(b"deposit,1,2,1.0\n")
+                .unwrap_err()
+                .to_string(),
+            "the input is larger than the maximum of 40 bytes"
+        );
+    }
+}
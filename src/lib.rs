@@ -0,0 +1,46 @@
+// Amounts are always `Decimal`, never floating point.
+#![deny(clippy::float_arithmetic)]
+
+pub mod audit;
+pub mod checks;
+pub mod daemon;
+pub mod deposits;
+pub mod diagnostics;
+pub mod diff;
+pub mod distribution;
+pub mod engine;
+pub mod error;
+pub mod expect;
+pub mod group;
+pub mod http;
+pub mod input;
+pub mod lifecycle;
+pub mod metrics;
+pub mod output;
+pub mod parallel;
+#[cfg(feature = "parquet")]
+pub mod parquet_output;
+pub mod schema;
+pub mod server;
+pub mod shared;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_output;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod transaction;
+pub mod watch;
+
+pub use audit::{replay_audit, AuditEntry};
+pub use diagnostics::Diagnostics;
+pub use engine::{
+    process, process_lenient, process_slice, process_with, ClientData, Disputable, DisputeMode,
+    DuplicatePolicy, Engine, LockedBlocks, OnError, Options, Outcome, Skip
+};
+pub use error::{ErrorKind, ProcessError};
+pub use input::InputOptions;
+pub use metrics::EngineMetrics;
+pub use output::{clients_sorted, OutputOptions};
+pub use shared::SharedEngine;
+pub use snapshot::EngineSnapshot;
+pub use transaction::{Transaction, TransactionType, PRECISION};
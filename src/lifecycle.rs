@@ -0,0 +1,211 @@
+use crate::{Transaction, TransactionType};
+use anyhow::Result;
+use std::{collections::HashSet, fmt, io::Write};
+
+/// How a dispute, resolve or chargeback breaks the order transactions
+/// should arrive in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    /// A dispute of a transaction that wasn't deposited before it.
+    UndepositedDispute,
+
+    /// A resolve of a transaction that wasn't being disputed.
+    UndisputedResolve,
+
+    /// A chargeback of a transaction that wasn't being disputed.
+    UndisputedChargeback
+}
+
+/// A violation found on a line of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Issue {
+    /// The line the transaction starts on.
+    pub line: u64,
+
+    /// The transaction id referred to.
+    pub tx: u32,
+
+    /// What's wrong.
+    pub violation: Violation
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, reason) = match self.violation {
+            Violation::UndepositedDispute => ("dispute", "wasn't deposited before it"),
+            Violation::UndisputedResolve => ("resolve", "isn't being disputed"),
+            Violation::UndisputedChargeback => ("chargeback", "isn't being disputed")
+        };
+
+        write!(
+            f,
+            "line {}: {kind} of transaction {}, which {reason}",
+            self.line, self.tx
+        )
+    }
+}
+
+/// Scans transactions for lifecycle violations without processing any
+/// balances: disputes of transactions that weren't deposited first, and
+/// resolves or chargebacks of transactions that aren't being disputed.
+/// Every violation is returned, in input order, while malformed rows
+/// fail.
+pub fn check_lifecycle<I>(txs: I) -> Result<Vec<Issue>>
+where
+    I: IntoIterator<Item = Result<(u64, Transaction)>>
+{
+    let mut deposited = HashSet::new();
+    let mut disputed = HashSet::new();
+    let mut issues = Vec::new();
+
+    for tx in txs {
+        let (line, tx) = tx?;
+        let violation = match tx.kind {
+            TransactionType::Deposit => {
+                deposited.insert(tx.tx);
+                None
+            },
+
+            TransactionType::Dispute if !deposited.contains(&tx.tx) => {
+                Some(Violation::UndepositedDispute)
+            },
+
+            TransactionType::Dispute => {
+                disputed.insert(tx.tx);
+                None
+            },
+
+            TransactionType::Resolve => {
+                (!disputed.remove(&tx.tx)).then_some(Violation::UndisputedResolve)
+            },
+
+            TransactionType::Chargeback => {
+                (!disputed.remove(&tx.tx)).then_some(Violation::UndisputedChargeback)
+            },
+
+            TransactionType::Withdrawal | TransactionType::Transfer => None
+        };
+
+        if let Some(violation) = violation {
+            issues.push(Issue {
+                line,
+                tx: tx.tx,
+                violation
+            });
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Writes the issues, one per line.
+pub fn write_issues<W: Write>(out: &mut W, issues: &[Issue]) -> Result<()> {
+    for issue in issues {
+        writeln!(out, "{issue}")?;
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input, InputOptions};
+
+    fn check(csv: &str) -> Vec<Issue> {
+        let options = InputOptions::default();
+        let mut reader = input::reader(csv.as_bytes(), &options);
+
+        check_lifecycle(input::located_transactions(&mut reader, &options).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn well_formed_files_have_no_issues() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,5.0\n\
+                   dispute,1,1,\n\
+                   resolve,1,1,\n\
+                   dispute,1,1,\n\
+                   chargeback,1,1,\n";
+
+        assert!(check(csv).is_empty());
+    }
+
+    #[test]
+    fn disputes_before_deposits_are_reported() {
+        let csv = "type,client,tx,amount\n\
+                   dispute,1,1,\n\
+                   deposit,1,1,5.0\n\
+                   dispute,1,7,\n";
+
+        assert_eq!(
+            check(csv),
+            vec![
+                Issue {
+                    line:      2,
+                    tx:        1,
+                    violation: Violation::UndepositedDispute
+                },
+                Issue {
+                    line:      4,
+                    tx:        7,
+                    violation: Violation::UndepositedDispute
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn undisputed_resolves_are_reported() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,5.0\n\
+                   resolve,1,1,\n\
+                   dispute,1,1,\n\
+                   resolve,1,1,\n\
+                   resolve,1,1,\n";
+
+        assert_eq!(
+            check(csv),
+            vec![
+                Issue {
+                    line:      3,
+                    tx:        1,
+                    violation: Violation::UndisputedResolve
+                },
+                Issue {
+                    line:      6,
+                    tx:        1,
+                    violation: Violation::UndisputedResolve
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn undisputed_chargebacks_are_reported() {
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,1,5.0\n\
+                   chargeback,1,1,\n";
+        let issues = check(csv);
+
+        assert_eq!(
+            issues,
+            vec![Issue {
+                line:      3,
+                tx:        1,
+                violation: Violation::UndisputedChargeback
+            }]
+        );
+
+        let mut out = Vec::new();
+
+        write_issues(&mut out, &issues).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "line 3: chargeback of transaction 1, which isn't being disputed\n"
+        );
+    }
+}
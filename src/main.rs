@@ -1,572 +1,1697 @@
-use anyhow::{anyhow, Result};
-use clap::Parser;
-use csv::ReaderBuilder;
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use encoding_rs::Encoding;
 use rust_decimal::Decimal;
-use serde::Deserialize;
 use std::{
-    collections::{HashMap, HashSet},
-    fs::File
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, IsTerminal, Write},
+    net::TcpListener,
+    num::NonZeroUsize,
+    path::PathBuf,
+    time::Duration
+};
+#[cfg(feature = "parquet")]
+use transactions::parquet_output;
+#[cfg(feature = "sqlite")]
+use transactions::sqlite_output;
+use transactions::{
+    audit::Sample,
+    checks, daemon, diff,
+    distribution::distribution,
+    engine, error, expect,
+    group::{self, GroupBy},
+    http,
+    input::{self, ColumnMapping, TimeWindow},
+    lifecycle, metrics,
+    output::{self, ColumnPrecision, Format, HeldAsNegative, Quoting, Terminator, TxCount},
+    parallel, schema, server,
+    snapshot::{self, Checkpoints, SnapshotFormat},
+    transaction::RoundMode,
+    watch, ClientData, Diagnostics, Disputable, DisputeMode, DuplicatePolicy, Engine, InputOptions,
+    LockedBlocks, OnError, Options, OutputOptions, SharedEngine, Transaction, TransactionType
 };
 
 /// The command line arguments.
 #[derive(Parser, Default)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    /// The subcommand to run.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// The filename to process.
-    pub filename: String
+    pub filename: Option<String>,
+
+    /// Allow withdrawals to overdraw the available funds.
+    #[arg(long, global = true)]
+    pub ignore_withdrawals_overdraft: bool,
+
+    /// Skip zero-amount deposits and withdrawals entirely.
+    #[arg(long, global = true)]
+    pub skip_zero_amounts: bool,
+
+    /// Round amounts with more than four decimal places.
+    #[arg(long, global = true, value_enum)]
+    pub round_mode: Option<RoundMode>,
+
+    /// Only process these transaction types (comma separated).
+    #[arg(long, global = true, value_enum, value_delimiter = ',')]
+    pub tx_type_filter: Option<Vec<TransactionType>>,
+
+    /// Only process clients listed in this file, one id per line.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub clients_file: Option<PathBuf>,
+
+    /// The maximum number of deposits remembered for disputes. The reject
+    /// and idempotent duplicate policies still remember every deposit's
+    /// id, client and amount, to spot duplicates of evicted ones.
+    #[arg(long, global = true)]
+    pub max_deposits: Option<usize>,
+
+    /// Which transactions a locked account blocks.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub locked_blocks: LockedBlocks,
+
+    /// Report transactions a locked account blocks as rejected rather than
+    /// silently skipping them.
+    #[arg(long, global = true)]
+    pub reject_future_tx_after_lock: bool,
+
+    /// What happens when a disputed deposit is more than the available
+    /// funds.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub dispute_mode: DisputeMode,
+
+    /// Which transactions may be disputed.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub disputable: Disputable,
+
+    /// Skip disputes of a transaction that was already disputed N times.
+    #[arg(long, global = true, value_name = "N")]
+    pub max_dispute_cycles: Option<u32>,
+
+    /// Skip disputes while N disputes are open across all clients, until
+    /// a resolve or chargeback closes one.
+    #[arg(long, global = true, value_name = "N")]
+    pub max_open_disputes_global: Option<usize>,
+
+    /// Let resolves and chargebacks take held funds negative when they're
+    /// short of the disputed amount, rather than clamping them at zero
+    /// (or failing with --strict).
+    #[arg(long, global = true)]
+    pub allow_resolve_to_negative_held: bool,
+
+    /// Which deposit is kept when a deposit reuses a transaction id.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub duplicate_policy: DuplicatePolicy,
+
+    /// Skip deposits, withdrawals and transfers over this amount.
+    #[arg(long, global = true)]
+    pub max_amount: Option<Decimal>,
+
+    /// Fail on transactions that indicate bad data instead of skipping
+    /// them.
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Fail on disputes, resolves and chargebacks referencing a
+    /// transaction that doesn't exist.
+    #[arg(long, global = true)]
+    pub strict_references: bool,
+
+    /// Fail on deposits, withdrawals and transfers with a negative or zero
+    /// amount, since their type gives the direction.
+    #[arg(long, global = true)]
+    pub strict_amount_sign: bool,
+
+    /// Treat deposits and withdrawals missing an amount as zero.
+    #[arg(long, global = true)]
+    pub coerce_missing_amount: bool,
+
+    /// Fail if the clients' totals don't add up to the deposits net of
+    /// withdrawals and chargebacks.
+    #[arg(long, global = true)]
+    pub check_conservation: bool,
+
+    /// Fail when a balance would be rounded because it needs more than 28
+    /// significant digits, rather than silently losing precision.
+    #[arg(long, global = true)]
+    pub decimal_context: bool,
+
+    /// Saturate balances at the largest and smallest amounts instead of
+    /// failing when they'd overflow. Only meant for analytics, since
+    /// saturated balances no longer add up.
+    #[arg(long, global = true)]
+    pub saturating: bool,
+
+    /// Rescale final balances, and those in basic snapshots, to this many
+    /// decimal places, rounding half away from zero.
+    #[arg(long, global = true, value_name = "PLACES")]
+    pub normalize_scale: Option<u32>,
+
+    /// Carry on past transactions that fail to parse or apply, printing a
+    /// summary of the errors by kind once done. Short for `--on-error
+    /// continue`.
+    #[arg(long, global = true, conflicts_with = "on_error")]
+    pub lenient: bool,
+
+    /// What to do about transactions that fail to parse or apply:
+    /// `continue` skips them and `skip-client` also skips every later
+    /// transaction for their client. Either prints a summary of the
+    /// errors by kind once done.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub on_error: OnError,
+
+    /// Accept transaction types in any case.
+    #[arg(long, global = true)]
+    pub normalize_case: bool,
+
+    /// Strip invisible characters, like zero-width spaces, padding the
+    /// `type` column rather than failing on them.
+    #[arg(long, global = true)]
+    pub normalize_type_whitespace: bool,
+
+    /// Fail on rows with more fields than the header.
+    #[arg(long, global = true)]
+    pub strict_schema: bool,
+
+    /// Read input without a header row, by position in the order
+    /// `type,client,tx,amount,counterparty,seq,timestamp`.
+    #[arg(long, global = true, conflicts_with = "column_map")]
+    pub no_header: bool,
+
+    /// Fail on a deposit, withdrawal or transfer repeating the client and
+    /// tx of an earlier one, naming both lines.
+    #[arg(long, global = true)]
+    pub strict_unique_client_tx: bool,
+
+    /// Read input without a header row if its first field is a
+    /// transaction type, and with one otherwise.
+    #[arg(long, global = true, conflicts_with = "no_header")]
+    pub auto_header: bool,
+
+    /// The header to read a field from, e.g. `amount=value` (comma
+    /// separated, repeatable).
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub column_map: Vec<ColumnMapping>,
+
+    /// Strip a leading currency symbol, like the `$` in `$10.00`, from
+    /// amounts.
+    #[arg(long, global = true)]
+    pub strip_currency_symbol: bool,
+
+    /// Transcode the input to UTF-8 from this encoding, e.g. `latin1` or
+    /// `windows-1252`.
+    #[arg(long, global = true, value_name = "LABEL", value_parser = input::encoding)]
+    pub input_encoding: Option<&'static Encoding>,
+
+    /// Fail on input larger than this many bytes. Files are checked
+    /// before they're read, while streams fail once they exceed it.
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub max_file_size: Option<u64>,
+
+    /// Skip input lines starting with this character, e.g. `#`.
+    #[arg(long, global = true, value_parser = ascii_byte)]
+    pub comment_char: Option<u8>,
+
+    /// Start from the client data in a json snapshot. A snapshot written
+    /// by `--checkpoint-every` also skips the input records read before
+    /// it was taken, unless `--skip` says otherwise.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub load_snapshot: Option<PathBuf>,
+
+    /// Compare the balances, as they'd be output, to expected csv output,
+    /// like a previous run's, failing with the first differing client if
+    /// they differ.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub expect: Option<PathBuf>,
+
+    /// Output how each client changed since a json snapshot, omitting
+    /// unchanged clients, instead of their balances.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub diff_against: Option<PathBuf>,
+
+    /// Write per transaction type counts and amounts, skip reasons and
+    /// open disputes as json to this file.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub metrics_out: Option<PathBuf>,
+
+    /// Write a json snapshot every N transactions, along with how many
+    /// input records had been read, to resume from with `--load-snapshot`.
+    #[arg(long, global = true, value_name = "N", requires = "checkpoint_file")]
+    pub checkpoint_every: Option<NonZeroUsize>,
+
+    /// Where checkpoint snapshots are written.
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILE",
+        requires = "checkpoint_every"
+    )]
+    pub checkpoint_file: Option<PathBuf>,
+
+    /// What checkpoint snapshots hold. Extended snapshots also keep the
+    /// deposits and disputes, so disputes survive a restart.
+    #[arg(long, global = true, value_enum, default_value_t = SnapshotFormat::Basic)]
+    pub snapshot_format: SnapshotFormat,
+
+    /// Only output clients with a transaction applied in this run, say
+    /// to leave out the untouched clients of a loaded snapshot.
+    #[arg(long)]
+    pub touched_only: bool,
+
+    /// Also process these files, each on its own thread, merging the
+    /// client data of all files. Disputes can't refer to deposits in
+    /// other files.
+    #[arg(long, value_name = "FILE", num_args = 1..)]
+    pub merge_parallel: Vec<PathBuf>,
+
+    /// Skip the first N records, after the header, rather than those read
+    /// before a loaded checkpoint.
+    #[arg(long, value_name = "N")]
+    pub skip: Option<usize>,
+
+    /// Process at most N records, after any skipped ones.
+    #[arg(long, value_name = "N")]
+    pub take: Option<usize>,
+
+    /// Only process transactions with a `timestamp` in this range, e.g.
+    /// `2024-01-01T00:00:00Z..2024-02-01T00:00:00Z`. The start is
+    /// inclusive, the end exclusive, and either may be left off.
+    #[arg(long, value_name = "START..END")]
+    pub window: Option<TimeWindow>,
+
+    /// Buffer the whole input and process it sorted by its `seq`
+    /// column, for sources that emit transactions out of order.
+    #[arg(long)]
+    pub sort_by_seq: bool,
+
+    /// Print a distribution of client totals to stderr.
+    #[arg(long)]
+    pub distribution: bool,
+
+    /// Fail if any client ends up with a negative balance.
+    #[arg(long, global = true)]
+    pub validate_balances_nonnegative: bool,
+
+    /// Only output locked clients.
+    #[arg(long, global = true)]
+    pub locked_only: bool,
+
+    /// Flush the output after every row.
+    #[arg(long, global = true)]
+    pub flush_each_row: bool,
+
+    /// Output an aligned table instead of csv, highlighting locked
+    /// clients when stdout is a terminal.
+    #[arg(long, global = true)]
+    pub pretty: bool,
+
+    /// The decimal places for a column, e.g. `held=2` (repeatable).
+    #[arg(long, global = true)]
+    pub precision_col: Vec<ColumnPrecision>,
+
+    /// The output format.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub format: Format,
+
+    /// Write the output to this file instead of stdout. Required for
+    /// parquet and sqlite.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// End csv output with a `TOTAL` row summing the amounts, with the
+    /// number of locked clients in the `locked` column.
+    #[arg(long, global = true)]
+    pub output_totals_row: bool,
+
+    /// Aggregate the output: `none` outputs every client, `all` one row
+    /// summing them and `bucket:N` a row per N client ids.
+    #[arg(long, global = true, value_name = "GROUPING", default_value = "none")]
+    pub group_by: GroupBy,
+
+    /// Output amounts without trailing zeros, e.g. `10.5` rather than
+    /// `10.5000`.
+    #[arg(long, global = true)]
+    pub trim_zeros: bool,
+
+    /// Output a `tx_count` column with how many transactions each client
+    /// sent, counting `all` of them (the default) or only those `applied`.
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "all"
+    )]
+    pub with_tx_count: Option<TxCount>,
+
+    /// Zero-pad client ids in csv output to N digits.
+    #[arg(long, global = true, value_name = "N")]
+    pub client_width: Option<usize>,
+
+    /// How csv output rows are terminated.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub output_terminator: Terminator,
+
+    /// Which csv output fields are quoted.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub output_quoting: Quoting,
+
+    /// Output canonical csv, so the same balances are always the same
+    /// bytes, ignoring the other formatting flags.
+    #[arg(long, global = true)]
+    pub canonicalize: bool,
+
+    /// Report skipped transactions on stderr.
+    #[arg(long, short, global = true)]
+    pub verbose: bool,
+
+    /// Explain every state change a transaction id caused, including
+    /// disputes referring to it, on stderr.
+    #[arg(long, global = true, value_name = "TX")]
+    pub explain: Option<u32>,
+
+    /// Report the effects of a random sample of N transactions on stderr
+    /// once done, for spot-checks.
+    #[arg(long, global = true, value_name = "N")]
+    pub sample: Option<usize>,
+
+    /// The seed the sample is picked with, so the same seed and input
+    /// always sample the same transactions.
+    #[arg(
+        long,
+        global = true,
+        value_name = "S",
+        default_value_t = 0,
+        requires = "sample"
+    )]
+    pub seed: u64,
+
+    /// Suppress everything on stderr, taking precedence over --verbose,
+    /// --explain, --sample and --distribution.
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+
+    /// Output held funds as a negative amount, subtracted from
+    /// `available` (the default) or in the `held` column itself.
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "available"
+    )]
+    pub output_held_as_negative: Option<HeldAsNegative>
 }
 
-/// The transaction type.
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "lowercase")]
-enum TransactionType {
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback
+/// The subcommands.
+#[derive(Subcommand)]
+enum Command {
+    /// Apply csv transactions as they stream in, on stdin or a single
+    /// tcp connection, writing the current client data whenever a
+    /// `report` line arrives.
+    Daemon {
+        /// Accept a connection on this address instead of reading
+        /// stdin, writing reports back to the peer.
+        #[arg(long)]
+        addr: Option<String>
+    },
+
+    /// Compare two csv outputs, like two runs' or two implementations',
+    /// printing how each client differs and failing if any do.
+    Diff {
+        /// The first output.
+        a: PathBuf,
+
+        /// The second output.
+        b: PathBuf
+    },
+
+    /// Serve client data over http, from a snapshot loaded with
+    /// `--load-snapshot`, a csv file or both. `GET /clients` returns
+    /// every client and `GET /clients/{id}` a single client, as json.
+    Http {
+        /// The address to listen on, where `:8080` listens on every
+        /// interface.
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+
+        /// A csv file to process before serving.
+        filename: Option<PathBuf>
+    },
+
+    /// Accept a single tcp connection streaming csv transactions and
+    /// write the client data back once it closes.
+    Serve {
+        /// The address to listen on.
+        #[arg(long, default_value = "127.0.0.1:9000")]
+        addr: String
+    },
+
+    /// Print the input and output columns, their types and which are
+    /// optional.
+    Schema {
+        /// Print the schema as json.
+        #[arg(long)]
+        json: bool
+    },
+
+    /// Check a csv file without processing any balances, failing if
+    /// it's malformed.
+    Validate {
+        /// The file to check.
+        filename: PathBuf,
+
+        /// Also report every dispute of a transaction that wasn't
+        /// deposited first, and every resolve or chargeback of one that
+        /// isn't being disputed, with its line.
+        #[arg(long)]
+        strict_lifecycle: bool
+    },
+
+    /// Tail a growing csv file, applying transactions as they're
+    /// appended and reprinting the client data periodically.
+    Watch {
+        /// The file to watch.
+        filename: PathBuf,
+
+        /// The seconds between reprints.
+        #[arg(long, default_value_t = 5)]
+        interval: u64
+    }
 }
 
-/// A transaction.
-#[derive(Deserialize, Debug)]
-struct Transaction {
-    /// The transaction type.
-    #[serde(rename = "type")]
-    pub kind: TransactionType,
+/// What a flag configures, so subcommands can reject the flags they
+/// don't honor.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FlagGroup {
+    /// How transactions are processed.
+    Processing,
+
+    /// How the input is read.
+    Input,
 
-    /// The client id.
-    pub client: u16,
+    /// Which clients are output and how their amounts are formatted.
+    Amounts,
 
-    /// The transaction id.
-    pub tx: u32,
+    /// How csv is written.
+    Csv,
 
-    /// The amount.
-    pub amount: Option<Decimal>
+    /// `--pretty`.
+    Table,
+
+    /// What's reported on stderr.
+    Diagnostics,
+
+    /// `--load-snapshot`.
+    Snapshot,
+
+    /// Flags only processing a file honors.
+    File
 }
 
-impl Transaction {
-    /// Makes sure transactions are well-formed.
-    fn verify(&self) -> Result<()> {
-        match self.kind {
-            TransactionType::Deposit | TransactionType::Withdrawal => {
-                if self.amount.is_none() {
-                    return Err(anyhow!("transaction {} has no amount", self.tx));
-                }
+impl Command {
+    /// The subcommand's name.
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Daemon { .. } => "daemon",
+            Self::Diff { .. } => "diff",
+            Self::Http { .. } => "http",
+            Self::Serve { .. } => "serve",
+            Self::Schema { .. } => "schema",
+            Self::Validate { .. } => "validate",
+            Self::Watch { .. } => "watch"
+        }
+    }
+
+    /// Whether the subcommand honors a group of flags.
+    fn honors(&self, group: FlagGroup) -> bool {
+        match self {
+            Self::Daemon { .. } | Self::Watch { .. } => {
+                !matches!(group, FlagGroup::Snapshot | FlagGroup::File)
             },
+            Self::Serve { .. } => !matches!(
+                group,
+                FlagGroup::Table | FlagGroup::Snapshot | FlagGroup::File
+            ),
+            Self::Http { .. } => {
+                !matches!(group, FlagGroup::Csv | FlagGroup::Table | FlagGroup::File)
+            },
+            Self::Validate { .. } => group == FlagGroup::Input,
+            Self::Diff { .. } | Self::Schema { .. } => false
+        }
+    }
+}
 
-            _ => {}
+impl From<&Args> for Options {
+    fn from(args: &Args) -> Self {
+        Self {
+            allow_overdraft:       args.ignore_withdrawals_overdraft,
+            skip_zero_amounts:     args.skip_zero_amounts,
+            round_mode:            args.round_mode,
+            tx_types:              args.tx_type_filter.clone(),
+            clients:               None,
+            max_deposits:          args.max_deposits,
+            locked_blocks:         args.locked_blocks,
+            reject_after_lock:     args.reject_future_tx_after_lock,
+            dispute_mode:          args.dispute_mode,
+            disputable:            args.disputable,
+            max_dispute_cycles:    args.max_dispute_cycles,
+            max_open_disputes:     args.max_open_disputes_global,
+            allow_negative_held:   args.allow_resolve_to_negative_held,
+            duplicate_policy:      args.duplicate_policy,
+            max_amount:            args.max_amount,
+            strict:                args.strict,
+            strict_amount_sign:    args.strict_amount_sign,
+            coerce_missing_amount: args.coerce_missing_amount,
+            strict_references:     args.strict_references,
+            check_conservation:    args.check_conservation,
+            strict_precision:      args.decimal_context,
+            saturating:            args.saturating,
+            normalize_scale:       args.normalize_scale
         }
+    }
+}
 
-        Ok(())
+impl From<&Args> for InputOptions {
+    fn from(args: &Args) -> Self {
+        Self {
+            normalize_case:            args.normalize_case,
+            normalize_type_whitespace: args.normalize_type_whitespace,
+            strict_schema:             args.strict_schema,
+            no_header:                 args.no_header,
+            auto_header:               args.auto_header,
+            strict_unique_client_tx:   args.strict_unique_client_tx,
+            column_map:                args.column_map.clone(),
+            comment_char:              args.comment_char,
+            strip_currency_symbol:     args.strip_currency_symbol,
+            encoding:                  args.input_encoding,
+            max_size:                  args.max_file_size
+        }
     }
 }
 
-/// Aggregated client data.
-#[derive(Default, Debug)]
-struct ClientData {
-    pub available: Decimal,
-    pub held:      Decimal,
-    pub total:     Decimal,
-    pub locked:    bool
+impl From<&Args> for OutputOptions {
+    fn from(args: &Args) -> Self {
+        let options = Self {
+            locked_only:      args.locked_only,
+            held_as_negative: args.output_held_as_negative,
+            flush_each_row:   args.flush_each_row,
+            pretty:           args.pretty,
+            color:            args.pretty && args.output.is_none() && io::stdout().is_terminal(),
+            column_precision: args.precision_col.clone(),
+            totals_row:       args.output_totals_row,
+            trim_zeros:       args.trim_zeros,
+            client_width:     args.client_width,
+            tx_counts:        None,
+            terminator:       args.output_terminator,
+            quoting:          args.output_quoting
+        };
+
+        if args.canonicalize {
+            options.canonical()
+        } else {
+            options
+        }
+    }
 }
 
-/// The entry point.
-fn main() -> Result<()> {
-    let args = Args::parse();
-    let file = File::open(&args.filename)?;
-
-    // Allow for whitespace and missing columns.
-    let mut reader = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .flexible(true)
-        .from_reader(file);
-    let txs = reader
-        .deserialize::<Transaction>()
-        .map(|r| r.map_err(Into::into));
-
-    // Process the transactions.
-    let clients = process(txs)?;
-
-    // Print the client data to stdout..
-    println!("client,available,held,total,locked");
-
-    for (id, client) in &clients {
-        println!(
-            "{},{:.4},{:.4},{:.4},{}",
-            id, client.available, client.held, client.total, client.locked
-        );
+impl Args {
+    /// What to do about transactions that fail, with `--lenient` meaning
+    /// `continue`.
+    fn on_error(&self) -> OnError {
+        if self.lenient {
+            OnError::Continue
+        } else {
+            self.on_error
+        }
     }
 
-    Ok(())
-}
+    /// Every flag, whether it was passed and what it configures.
+    fn flags(&self) -> Vec<(bool, FlagGroup, &'static str)> {
+        vec![
+            (
+                self.ignore_withdrawals_overdraft,
+                FlagGroup::Processing,
+                "--ignore-withdrawals-overdraft"
+            ),
+            (
+                self.skip_zero_amounts,
+                FlagGroup::Processing,
+                "--skip-zero-amounts"
+            ),
+            (
+                self.round_mode.is_some(),
+                FlagGroup::Processing,
+                "--round-mode"
+            ),
+            (
+                self.tx_type_filter.is_some(),
+                FlagGroup::Processing,
+                "--tx-type-filter"
+            ),
+            (
+                self.clients_file.is_some(),
+                FlagGroup::Processing,
+                "--clients-file"
+            ),
+            (
+                self.max_deposits.is_some(),
+                FlagGroup::Processing,
+                "--max-deposits"
+            ),
+            (
+                self.locked_blocks != LockedBlocks::default(),
+                FlagGroup::Processing,
+                "--locked-blocks"
+            ),
+            (
+                self.reject_future_tx_after_lock,
+                FlagGroup::Processing,
+                "--reject-future-tx-after-lock"
+            ),
+            (
+                self.dispute_mode != DisputeMode::default(),
+                FlagGroup::Processing,
+                "--dispute-mode"
+            ),
+            (
+                self.disputable != Disputable::default(),
+                FlagGroup::Processing,
+                "--disputable"
+            ),
+            (
+                self.max_dispute_cycles.is_some(),
+                FlagGroup::Processing,
+                "--max-dispute-cycles"
+            ),
+            (
+                self.max_open_disputes_global
+                    .is_some(),
+                FlagGroup::Processing,
+                "--max-open-disputes-global"
+            ),
+            (
+                self.allow_resolve_to_negative_held,
+                FlagGroup::Processing,
+                "--allow-resolve-to-negative-held"
+            ),
+            (
+                self.duplicate_policy != DuplicatePolicy::default(),
+                FlagGroup::Processing,
+                "--duplicate-policy"
+            ),
+            (
+                self.max_amount.is_some(),
+                FlagGroup::Processing,
+                "--max-amount"
+            ),
+            (self.strict, FlagGroup::Processing, "--strict"),
+            (
+                self.strict_references,
+                FlagGroup::Processing,
+                "--strict-references"
+            ),
+            (
+                self.strict_amount_sign,
+                FlagGroup::Processing,
+                "--strict-amount-sign"
+            ),
+            (
+                self.coerce_missing_amount,
+                FlagGroup::Processing,
+                "--coerce-missing-amount"
+            ),
+            (
+                self.check_conservation,
+                FlagGroup::Processing,
+                "--check-conservation"
+            ),
+            (
+                self.decimal_context,
+                FlagGroup::Processing,
+                "--decimal-context"
+            ),
+            (self.saturating, FlagGroup::Processing, "--saturating"),
+            (
+                self.normalize_scale.is_some(),
+                FlagGroup::Processing,
+                "--normalize-scale"
+            ),
+            (self.normalize_case, FlagGroup::Input, "--normalize-case"),
+            (
+                self.normalize_type_whitespace,
+                FlagGroup::Input,
+                "--normalize-type-whitespace"
+            ),
+            (self.strict_schema, FlagGroup::Input, "--strict-schema"),
+            (self.no_header, FlagGroup::Input, "--no-header"),
+            (
+                self.strict_unique_client_tx,
+                FlagGroup::Input,
+                "--strict-unique-client-tx"
+            ),
+            (self.auto_header, FlagGroup::Input, "--auto-header"),
+            (
+                !self.column_map.is_empty(),
+                FlagGroup::Input,
+                "--column-map"
+            ),
+            (
+                self.strip_currency_symbol,
+                FlagGroup::Input,
+                "--strip-currency-symbol"
+            ),
+            (
+                self.input_encoding.is_some(),
+                FlagGroup::Input,
+                "--input-encoding"
+            ),
+            (
+                self.max_file_size.is_some(),
+                FlagGroup::Input,
+                "--max-file-size"
+            ),
+            (
+                self.comment_char.is_some(),
+                FlagGroup::Input,
+                "--comment-char"
+            ),
+            (self.locked_only, FlagGroup::Amounts, "--locked-only"),
+            (
+                !self.precision_col.is_empty(),
+                FlagGroup::Amounts,
+                "--precision-col"
+            ),
+            (self.trim_zeros, FlagGroup::Amounts, "--trim-zeros"),
+            (
+                self.output_held_as_negative
+                    .is_some(),
+                FlagGroup::Amounts,
+                "--output-held-as-negative"
+            ),
+            (self.flush_each_row, FlagGroup::Csv, "--flush-each-row"),
+            (
+                self.output_totals_row,
+                FlagGroup::Csv,
+                "--output-totals-row"
+            ),
+            (
+                self.client_width.is_some(),
+                FlagGroup::Csv,
+                "--client-width"
+            ),
+            (
+                self.output_terminator != Terminator::default(),
+                FlagGroup::Csv,
+                "--output-terminator"
+            ),
+            (
+                self.output_quoting != Quoting::default(),
+                FlagGroup::Csv,
+                "--output-quoting"
+            ),
+            (self.canonicalize, FlagGroup::Csv, "--canonicalize"),
+            (self.pretty, FlagGroup::Table, "--pretty"),
+            (self.verbose, FlagGroup::Diagnostics, "--verbose"),
+            (self.quiet, FlagGroup::Diagnostics, "--quiet"),
+            (self.explain.is_some(), FlagGroup::Diagnostics, "--explain"),
+            (self.sample.is_some(), FlagGroup::Diagnostics, "--sample"),
+            (
+                self.load_snapshot.is_some(),
+                FlagGroup::Snapshot,
+                "--load-snapshot"
+            ),
+            (self.lenient, FlagGroup::File, "--lenient"),
+            (
+                self.on_error != OnError::default(),
+                FlagGroup::File,
+                "--on-error"
+            ),
+            (self.expect.is_some(), FlagGroup::File, "--expect"),
+            (
+                self.diff_against.is_some(),
+                FlagGroup::File,
+                "--diff-against"
+            ),
+            (self.metrics_out.is_some(), FlagGroup::File, "--metrics-out"),
+            (
+                self.checkpoint_every.is_some(),
+                FlagGroup::File,
+                "--checkpoint-every"
+            ),
+            (
+                self.checkpoint_file.is_some(),
+                FlagGroup::File,
+                "--checkpoint-file"
+            ),
+            (
+                self.snapshot_format != SnapshotFormat::default(),
+                FlagGroup::File,
+                "--snapshot-format"
+            ),
+            (self.touched_only, FlagGroup::File, "--touched-only"),
+            (
+                !self.merge_parallel.is_empty(),
+                FlagGroup::File,
+                "--merge-parallel"
+            ),
+            (self.skip.is_some(), FlagGroup::File, "--skip"),
+            (self.take.is_some(), FlagGroup::File, "--take"),
+            (self.window.is_some(), FlagGroup::File, "--window"),
+            (self.sort_by_seq, FlagGroup::File, "--sort-by-seq"),
+            (self.distribution, FlagGroup::File, "--distribution"),
+            (
+                self.validate_balances_nonnegative,
+                FlagGroup::File,
+                "--validate-balances-nonnegative"
+            ),
+            (
+                self.format != Format::default(),
+                FlagGroup::File,
+                "--format"
+            ),
+            (self.output.is_some(), FlagGroup::File, "--output"),
+            (
+                self.group_by != GroupBy::None,
+                FlagGroup::File,
+                "--group-by"
+            ),
+            (
+                self.with_tx_count.is_some(),
+                FlagGroup::File,
+                "--with-tx-count"
+            ),
+        ]
+    }
 
-/// Processes transactions.
-fn process<T>(txs: T) -> Result<HashMap<u16, ClientData>>
-where
-    T: IntoIterator<Item = Result<Transaction>>
-{
-    let mut clients = HashMap::<u16, ClientData>::new();
-    let mut deposits = HashMap::<u32, Transaction>::new();
-    let mut disputed = HashSet::<u32>::new();
-
-    // Read line by line to minimize our memory footprint.
-    for tx in txs {
-        let tx: Transaction = tx?;
-
-        // Verify the transaction.
-        tx.verify()?;
-
-        // Ensure this client exists.
-        let client = clients
-            .entry(tx.client)
-            .or_default();
-
-        // If the client is locked, do nothing.
-        if client.locked {
-            continue;
+    /// Makes sure no flags contradict each other, and that the chosen
+    /// subcommand honors every flag, rather than silently ignoring one
+    /// of them.
+    fn validate(&self) -> Result<()> {
+        if let Some(command) = &self.command {
+            let ignored = self
+                .flags()
+                .into_iter()
+                .filter(|(set, group, _)| *set && !command.honors(*group))
+                .map(|(_, _, flag)| flag)
+                .collect::<Vec<_>>();
+
+            if !ignored.is_empty() {
+                bail!("{} doesn't support {}", command.name(), ignored.join(", "));
+            }
         }
 
-        // Now match on the transaction type.
-        match tx.kind {
-            TransactionType::Deposit => {
-                let amount = &tx.amount.unwrap();
+        let mut conflicts = vec![
+            (
+                self.with_tx_count.is_some() && (self.pretty || self.canonicalize),
+                "--with-tx-count",
+                "--pretty or --canonicalize"
+            ),
+            (
+                self.client_width.is_some() && (self.pretty || self.canonicalize),
+                "--client-width",
+                "--pretty or --canonicalize"
+            ),
+            (
+                self.client_width.is_some() && self.diff_against.is_some(),
+                "--client-width",
+                "--diff-against"
+            ),
+            (
+                self.with_tx_count.is_some() && self.diff_against.is_some(),
+                "--with-tx-count",
+                "--diff-against"
+            ),
+            // Sorted input has no position to resume from.
+            (
+                self.checkpoint_every.is_some() && self.sort_by_seq,
+                "--checkpoint-every",
+                "--sort-by-seq"
+            ),
+            (
+                self.strict_amount_sign && self.coerce_missing_amount,
+                "--strict-amount-sign",
+                "--coerce-missing-amount"
+            ),
+            (
+                self.strict_amount_sign && self.skip_zero_amounts,
+                "--strict-amount-sign",
+                "--skip-zero-amounts"
+            ),
+            (
+                self.saturating && self.check_conservation,
+                "--saturating",
+                "--check-conservation"
+            ),
+            (
+                self.on_error() != OnError::Abort && self.check_conservation,
+                "--lenient or --on-error",
+                "--check-conservation"
+            ),
+            (
+                self.ignore_withdrawals_overdraft && self.validate_balances_nonnegative,
+                "--ignore-withdrawals-overdraft",
+                "--validate-balances-nonnegative"
+            ),
+            (
+                self.dispute_mode == DisputeMode::Proportional
+                    && self.validate_balances_nonnegative,
+                "--dispute-mode proportional",
+                "--validate-balances-nonnegative"
+            ),
+            (
+                self.allow_resolve_to_negative_held && self.validate_balances_nonnegative,
+                "--allow-resolve-to-negative-held",
+                "--validate-balances-nonnegative"
+            ),
+            (
+                self.saturating && self.validate_balances_nonnegative,
+                "--saturating",
+                "--validate-balances-nonnegative"
+            ),
+        ];
 
-                // Update the client data.
-                client.available += amount;
-                client.total += amount;
+        // Only csv output has these flags.
+        let format = self
+            .format
+            .to_possible_value()
+            .map(|value| format!("--format {}", value.get_name()))
+            .unwrap_or_default();
+
+        if self.format != Format::Csv {
+            conflicts.extend([
+                (self.canonicalize, format.as_str(), "--canonicalize"),
+                (self.pretty, &format, "--pretty"),
+                (self.output_totals_row, &format, "--output-totals-row"),
+                (self.flush_each_row, &format, "--flush-each-row"),
+                (self.client_width.is_some(), &format, "--client-width"),
+                (
+                    self.with_tx_count.is_some() && self.format != Format::Json,
+                    &format,
+                    "--with-tx-count"
+                ),
+                (self.diff_against.is_some(), &format, "--diff-against"),
+                (self.group_by != GroupBy::None, &format, "--group-by")
+            ]);
+        }
 
-                // Store the deposit.
-                deposits.insert(tx.tx, tx);
-            },
+        // Groups are written in their own csv layout.
+        if self.group_by != GroupBy::None {
+            let group_by = "--group-by";
+
+            conflicts.extend([
+                (self.pretty, group_by, "--pretty"),
+                (self.output_totals_row, group_by, "--output-totals-row"),
+                (self.flush_each_row, group_by, "--flush-each-row"),
+                (self.canonicalize, group_by, "--canonicalize"),
+                (self.client_width.is_some(), group_by, "--client-width"),
+                (self.with_tx_count.is_some(), group_by, "--with-tx-count"),
+                (self.diff_against.is_some(), group_by, "--diff-against")
+            ]);
+        }
 
-            TransactionType::Withdrawal => {
-                let amount = &tx.amount.unwrap();
+        // Files processed in parallel only have their client data merged.
+        if !self.merge_parallel.is_empty() {
+            let parallel = "--merge-parallel";
+
+            conflicts.extend([
+                (self.load_snapshot.is_some(), parallel, "--load-snapshot"),
+                (
+                    self.checkpoint_every.is_some(),
+                    parallel,
+                    "--checkpoint-every"
+                ),
+                (self.metrics_out.is_some(), parallel, "--metrics-out"),
+                (self.touched_only, parallel, "--touched-only"),
+                (self.sort_by_seq, parallel, "--sort-by-seq"),
+                (
+                    self.on_error() != OnError::Abort,
+                    parallel,
+                    "--lenient or --on-error"
+                ),
+                (self.with_tx_count.is_some(), parallel, "--with-tx-count"),
+                (self.window.is_some(), parallel, "--window"),
+                (
+                    self.skip.is_some() || self.take.is_some(),
+                    parallel,
+                    "--skip or --take"
+                ),
+                (
+                    self.verbose || self.explain.is_some() || self.sample.is_some(),
+                    parallel,
+                    "--verbose, --explain or --sample"
+                )
+            ]);
+        }
 
-                // Check if we have enough available funds.
-                if client.available - amount < Decimal::ZERO {
-                    continue;
-                }
+        let conflicts = conflicts
+            .into_iter()
+            .filter(|(conflict, ..)| *conflict)
+            .map(|(_, a, b)| format!("{a} with {b}"))
+            .collect::<Vec<_>>();
 
-                // Update the client data.
-                client.available -= amount;
-                client.total -= amount;
-            },
+        if !conflicts.is_empty() {
+            bail!("conflicting flags: {}", conflicts.join(", "));
+        }
 
-            TransactionType::Dispute => {
-                // Try and lookup the disputed transaction.
-                let Some(value) = deposits.get(&tx.tx) else {
-                    continue;
-                };
-
-                // Make sure it's not already being disputed.
-                if disputed.contains(&tx.tx) {
-                    continue;
-                }
-
-                // Only allow the dispute if we have available funds.
-                // This was unclear in the spec, but it aligns with
-                // what I'd expect from a bank in the real world.
-                if client.available < value.amount.unwrap() {
-                    continue;
-                }
-
-                // Update the client data.
-                client.available -= value.amount.unwrap();
-                client.held += value.amount.unwrap();
-
-                // Mark the transaction as disputed.
-                disputed.insert(tx.tx);
-            },
+        Ok(())
+    }
+}
 
-            TransactionType::Resolve => {
-                // Try and lookup the disputed transaction.
-                let Some(value) = deposits.get(&tx.tx) else {
-                    continue;
-                };
+/// Parses a single ascii character into a byte.
+fn ascii_byte(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [byte] if byte.is_ascii() => Ok(*byte),
+        _ => Err(format!("expected a single ascii character, got {s}"))
+    }
+}
 
-                // Make sure that it is being disputed.
-                if !disputed.contains(&tx.tx) {
-                    continue;
-                }
+/// Processes a file, resuming from a snapshot and writing checkpoints
+/// and metrics if asked to, and returns the client data to output. The
+/// transaction counts are set on the output options if they're output.
+fn process_file(
+    args: &Args,
+    file: File,
+    options: &Options,
+    input_options: &InputOptions,
+    output_options: &mut OutputOptions,
+    diagnostics: &mut Diagnostics<io::Stderr>
+) -> Result<HashMap<u16, ClientData>> {
+    // Resume from a snapshot if asked to, after the records a checkpoint
+    // had already read.
+    let (mut engine, records) = match &args.load_snapshot {
+        Some(path) => {
+            snapshot::load_engine_snapshot(File::open(path)?, options.clone(), diagnostics)?
+        },
+        None => (Engine::new(options.clone()), 0)
+    };
+    let skip = args.skip.unwrap_or(records);
+    let mut checkpoints = args
+        .checkpoint_every
+        .zip(args.checkpoint_file.clone())
+        .map(|(every, path)| {
+            Checkpoints::to_file(every, path, args.snapshot_format).starting_at(skip)
+        });
+
+    // Process the transactions, in seq order and within a window of
+    // time if asked to, counting those read for checkpoints.
+    let mut reader = input::reader(file, input_options);
+    let txs = input::window(
+        input::transactions(&mut reader, input_options)?,
+        skip,
+        args.take
+    );
+    let txs: Box<dyn Iterator<Item = Result<Transaction>>> = match &checkpoints {
+        Some(checkpoints) => Box::new(checkpoints.counting(txs)),
+        None => Box::new(txs)
+    };
+    let mut txs: Box<dyn Iterator<Item = Result<Transaction>>> = if args.sort_by_seq {
+        Box::new(
+            input::sort_by_seq(txs)?
+                .into_iter()
+                .map(Ok)
+        )
+    } else {
+        Box::new(txs)
+    };
+
+    // Only process a window of time if asked to.
+    if let Some(window) = args.window {
+        txs = Box::new(input::within(txs, window));
+    }
 
-                // Update the client data.
-                client.available += value.amount.unwrap();
-                client.held -= value.amount.unwrap();
+    if args.on_error() != OnError::Abort {
+        let errors = engine::run_on_error(
+            &mut engine,
+            txs,
+            diagnostics,
+            checkpoints.as_mut(),
+            args.on_error()
+        )?;
+
+        if !errors.is_empty() {
+            diagnostics.print(format_args!(
+                "{} transactions failed: {}",
+                errors.len(),
+                error::summary(&errors)
+            ));
+        }
 
-                // Mark the transaction as no longer disputed.
-                disputed.remove(&tx.tx);
-            },
+        if !engine.errored().is_empty() {
+            let mut errored = engine
+                .errored()
+                .iter()
+                .collect::<Vec<_>>();
+
+            errored.sort();
+            diagnostics.print(format_args!(
+                "abandoned clients after errors: {}",
+                errored
+                    .iter()
+                    .map(|client| client.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+    } else {
+        engine::run(&mut engine, txs, diagnostics, checkpoints.as_mut())?;
+    }
 
-            TransactionType::Chargeback => {
-                // Try and lookup the disputed transaction.
-                let Some(value) = deposits.get(&tx.tx) else {
-                    continue;
-                };
+    diagnostics.write_sample();
 
-                // Make sure that it is being disputed.
-                if !disputed.contains(&tx.tx) {
-                    continue;
-                }
+    // Write the metrics if asked to.
+    if let Some(path) = &args.metrics_out {
+        metrics::write_metrics(BufWriter::new(File::create(path)?), engine.metrics())?;
+    }
 
-                // Update the client data.
-                client.held -= value.amount.unwrap();
-                client.total -= value.amount.unwrap();
-                client.locked = true;
+    output_options.tx_counts = args
+        .with_tx_count
+        .map(|count| engine.tx_counts(count == TxCount::All));
 
-                // Mark the transaction as no longer disputed.
-                disputed.remove(&tx.tx);
-            }
+    Ok(if args.touched_only {
+        engine.into_touched_clients()
+    } else {
+        engine.into_clients()
+    })
+}
+
+/// Checks that a file parses, and that its disputes, resolves and
+/// chargebacks arrive in order if asked to, printing every violation.
+fn validate_file(
+    path: &PathBuf,
+    strict_lifecycle: bool,
+    input_options: &InputOptions
+) -> Result<()> {
+    let file = File::open(path)?;
+
+    input::check_size(&file, input_options)?;
+
+    let mut reader = input::reader(file, input_options);
+    let txs = input::located_transactions(&mut reader, input_options)?;
+
+    if !strict_lifecycle {
+        for tx in txs {
+            tx?;
         }
+
+        return Ok(());
     }
 
-    Ok(clients)
-}
+    let issues = lifecycle::check_lifecycle(txs)?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rust_decimal_macros::dec;
+    lifecycle::write_issues(&mut io::stdout().lock(), &issues)?;
 
-    #[test]
-    fn deposit_increases_available_and_total() {
-        let txs = vec![Ok(Transaction {
-            kind:   TransactionType::Deposit,
-            client: 1,
-            tx:     1,
-            amount: Some(dec!(10.0))
-        })];
+    if !issues.is_empty() {
+        bail!("found {} lifecycle violations", issues.len());
+    }
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+    Ok(())
+}
 
-        assert_eq!(client.available, dec!(10.0));
-        assert_eq!(client.total, dec!(10.0));
-        assert_eq!(client.held, dec!(0.0));
-        assert!(!client.locked);
+/// The entry point. Output cut short by its reader going away, like when
+/// piped into `head`, isn't an error, as is usual for unix tools.
+fn main() -> Result<()> {
+    match run() {
+        Err(error) if output::is_broken_pipe(&error) => Ok(()),
+        result => result
     }
+}
 
-    #[test]
-    fn withdrawal_reduces_available_and_total() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(5.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Withdrawal,
-                client: 1,
-                tx:     2,
-                amount: Some(dec!(3.0))
-            }),
-        ];
+/// Runs the command.
+fn run() -> Result<()> {
+    let args = Args::parse();
+
+    args.validate()?;
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+    let mut options = Options::from(&args);
+    let input_options = InputOptions::from(&args);
+    let mut output_options = OutputOptions::from(&args);
+    let mut diagnostics = Diagnostics::stderr(args.verbose, args.quiet)
+        .with_explain(args.explain)
+        .with_sample(
+            args.sample
+                .map(|size| Sample::new(size, args.seed))
+        );
 
-        assert_eq!(client.available, dec!(2.0));
-        assert_eq!(client.total, dec!(2.0));
+    // Read the client allowlist if there is one.
+    if let Some(path) = &args.clients_file {
+        options.clients = Some(input::read_client_ids(File::open(path)?)?);
     }
 
-    #[test]
-    fn withdrawal_fails_if_insufficient_funds() {
-        let txs = vec![Ok(Transaction {
-            kind:   TransactionType::Withdrawal,
-            client: 1,
-            tx:     1,
-            amount: Some(dec!(10.0))
-        })];
+    // Run the subcommand if there is one.
+    match &args.command {
+        Some(Command::Daemon { addr: None }) => {
+            return daemon::daemon(
+                io::stdin().lock(),
+                &mut io::stdout(),
+                &options,
+                &input_options,
+                &output_options,
+                &mut diagnostics
+            );
+        },
+
+        Some(Command::Daemon { addr: Some(addr) }) => {
+            let (stream, _) = TcpListener::bind(addr)?.accept()?;
+
+            return daemon::daemon(
+                BufReader::new(stream.try_clone()?),
+                &mut &stream,
+                &options,
+                &input_options,
+                &output_options,
+                &mut diagnostics
+            );
+        },
+
+        Some(Command::Diff { a, b }) => {
+            let differences = diff::diff_outputs(File::open(a)?, File::open(b)?)?;
+            let mut stdout = io::stdout().lock();
+
+            for difference in &differences {
+                writeln!(stdout, "{difference}")?;
+            }
+
+            if !differences.is_empty() {
+                bail!("{} clients differ", differences.len());
+            }
+
+            return Ok(());
+        },
+
+        Some(Command::Http { addr, filename }) => {
+            if args.load_snapshot.is_none() && filename.is_none() {
+                bail!("http needs a file to process, --load-snapshot or both");
+            }
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+            let (mut engine, records) = match &args.load_snapshot {
+                Some(path) => snapshot::load_engine_snapshot(
+                    File::open(path)?,
+                    options.clone(),
+                    &mut diagnostics
+                )?,
+                None => (Engine::new(options.clone()), 0)
+            };
+
+            if let Some(path) = filename {
+                let mut reader = input::reader(File::open(path)?, &input_options);
+
+                engine::run(
+                    &mut engine,
+                    input::window(
+                        input::transactions(&mut reader, &input_options)?,
+                        records,
+                        None
+                    ),
+                    &mut diagnostics,
+                    None
+                )?;
+            }
 
-        assert_eq!(client.available, dec!(0.0));
-        assert_eq!(client.total, dec!(0.0));
+            let listener = TcpListener::bind(http::listen_addr(addr))?;
+
+            return http::serve(
+                &listener,
+                &SharedEngine::from(engine),
+                &output_options,
+                &mut diagnostics
+            );
+        },
+
+        Some(Command::Serve { addr }) => {
+            let listener = TcpListener::bind(addr)?;
+
+            return server::serve(
+                &listener,
+                &options,
+                &input_options,
+                &output_options,
+                &mut diagnostics
+            );
+        },
+
+        Some(Command::Schema { json }) => {
+            return schema::write_schema(&mut io::stdout().lock(), *json);
+        },
+
+        Some(Command::Validate {
+            filename,
+            strict_lifecycle
+        }) => {
+            return validate_file(filename, *strict_lifecycle, &input_options);
+        },
+
+        Some(Command::Watch { filename, interval }) => {
+            return watch::watch(
+                filename,
+                Duration::from_secs(*interval),
+                &options,
+                &input_options,
+                &output_options,
+                &mut io::stdout(),
+                &mut diagnostics
+            );
+        },
+
+        None => {}
     }
 
-    #[test]
-    fn dispute_moves_funds_to_held() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(5.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Dispute,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-        ];
+    let filename = args
+        .filename
+        .as_ref()
+        .ok_or_else(|| anyhow!("no filename was provided"))?;
+    let file = File::open(filename)?;
+
+    input::check_size(&file, &input_options)?;
+
+    let clients = if args.merge_parallel.is_empty() {
+        process_file(
+            &args,
+            file,
+            &options,
+            &input_options,
+            &mut output_options,
+            &mut diagnostics
+        )?
+    } else {
+        // Process every file on its own thread, merging their client data.
+        let mut files = vec![file];
+
+        for path in &args.merge_parallel {
+            let file = File::open(path)?;
+
+            input::check_size(&file, &input_options)?;
+            files.push(file);
+        }
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+        parallel::process_parallel(files, &options, &input_options)?
+    };
 
-        assert_eq!(client.available, dec!(0.0));
-        assert_eq!(client.held, dec!(5.0));
-        assert_eq!(client.total, dec!(5.0));
-        assert!(!client.locked);
+    // Sanity check the balances if asked to.
+    if args.validate_balances_nonnegative {
+        checks::check_nonnegative(&clients, &options)?;
     }
 
-    #[test]
-    fn dispute_twice_does_nothing_the_second_time() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(5.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Dispute,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Dispute,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-        ];
+    // Compare the balances, as they'd be written, to what's expected if
+    // asked to.
+    if let Some(path) = &args.expect {
+        let expected = expect::read_expected(File::open(path)?)?;
+        let written = output::as_written(&clients, &output_options)?;
+
+        if let Some(mismatch) = expect::compare(&expected, &written) {
+            bail!("the balances differ from {}: {mismatch}", path.display());
+        }
+    }
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+    // Write parquet to its own file.
+    #[cfg(feature = "parquet")]
+    if args.format == Format::Parquet {
+        let path = args
+            .output
+            .as_ref()
+            .ok_or_else(|| anyhow!("parquet output needs --output"))?;
 
-        assert_eq!(client.available, dec!(0.0));
-        assert_eq!(client.held, dec!(5.0));
-        assert_eq!(client.total, dec!(5.0));
+        return parquet_output::write_parquet(File::create(path)?, &clients, &output_options);
     }
 
-    #[test]
-    fn dispute_is_ignored_if_funds_already_withdrawn() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(5.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Withdrawal,
-                client: 1,
-                tx:     2,
-                amount: Some(dec!(5.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Dispute,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-        ];
+    // Write sqlite to its own database.
+    #[cfg(feature = "sqlite")]
+    if args.format == Format::Sqlite {
+        let path = args
+            .output
+            .as_ref()
+            .ok_or_else(|| anyhow!("sqlite output needs --output"))?;
+
+        return sqlite_output::write_sqlite(path, &clients, &output_options);
+    }
+
+    // Print the client data to stdout, or the output file.
+    let mut stdout: BufWriter<Box<dyn Write>> = BufWriter::new(match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock())
+    });
+
+    if let Some(path) = &args.diff_against {
+        let prior = snapshot::read_snapshot(File::open(path)?)?;
+
+        diff::write_deltas(
+            &mut stdout,
+            &diff::deltas(&prior, &clients)?,
+            &output_options
+        )?;
+    } else if args.group_by != GroupBy::None {
+        let groups = group::groups(&clients, args.group_by, &output_options)?;
+
+        group::write_groups(&mut stdout, &groups, &output_options)?;
+    } else if args.format == Format::Json {
+        output::write_json(&mut stdout, &clients, &output_options)?;
+    } else {
+        output::write_output(&mut stdout, &clients, &output_options)?;
+    }
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+    // Print the distribution to stderr.
+    if args.distribution {
+        let d = distribution(&clients);
 
-        assert_eq!(client.available, dec!(0.0));
-        assert_eq!(client.held, dec!(0.0));
-        assert_eq!(client.total, dec!(0.0));
-        assert!(!client.locked);
+        diagnostics.print(format_args!("negative: {}", d.negative));
+        diagnostics.print(format_args!("zero: {}", d.zero));
+        diagnostics.print(format_args!("< 100: {}", d.under_100));
+        diagnostics.print(format_args!("< 1000: {}", d.under_1000));
+        diagnostics.print(format_args!(">= 1000: {}", d.rest));
     }
 
-    #[test]
-    fn resolve_returns_held_to_available() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(7.5))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Dispute,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Resolve,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-        ];
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+    fn validate(args: &[&str]) -> Result<()> {
+        Args::try_parse_from(args)?.validate()
+    }
 
-        assert_eq!(client.available, dec!(7.5));
-        assert_eq!(client.held, dec!(0.0));
-        assert_eq!(client.total, dec!(7.5));
-        assert!(!client.locked);
+    #[test]
+    fn compatible_flags_pass() {
+        assert!(validate(&["transactions", "test.csv", "--verbose", "--pretty"]).is_ok());
     }
 
     #[test]
-    fn resolve_ignored_if_tx_not_disputed() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(5.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Resolve,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-        ];
+    fn subcommands_reject_file_only_flags() {
+        for flag in ["--touched-only", "--sort-by-seq", "--distribution"] {
+            assert!(validate(&["transactions", "daemon", flag]).is_err());
+        }
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+        let err = Args::try_parse_from(["transactions", "watch", "f.csv", "--skip", "2"])
+            .err()
+            .unwrap();
 
-        assert_eq!(client.available, dec!(5.0));
-        assert_eq!(client.held, dec!(0.0));
-        assert_eq!(client.total, dec!(5.0));
-        assert!(!client.locked);
+        assert_eq!(err.kind(), clap::error::ErrorKind::UnknownArgument);
     }
 
     #[test]
-    fn chargeback_removes_held_and_locks() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(3.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Dispute,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Chargeback,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-        ];
-
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+    fn files_are_never_colored() {
+        let args = Args::try_parse_from([
+            "transactions",
+            "test.csv",
+            "--pretty",
+            "--output",
+            "out.txt"
+        ])
+        .unwrap();
+
+        assert!(!OutputOptions::from(&args).color);
+    }
 
-        assert_eq!(client.available, dec!(0.0));
-        assert_eq!(client.held, dec!(0.0));
-        assert_eq!(client.total, dec!(0.0));
-        assert!(client.locked);
+    #[test]
+    fn quiet_overrides_reporting_flags() {
+        assert!(validate(&[
+            "transactions",
+            "test.csv",
+            "--verbose",
+            "--explain",
+            "1",
+            "--distribution",
+            "--sample",
+            "2",
+            "--quiet"
+        ])
+        .is_ok());
     }
 
     #[test]
-    fn chargeback_ignored_if_tx_not_disputed() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(5.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Chargeback,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-        ];
+    fn subcommands_reject_ignored_flags() {
+        let err = validate(&[
+            "transactions",
+            "daemon",
+            "--format",
+            "json",
+            "--expect",
+            "/nonexistent",
+            "--load-snapshot",
+            "/nonexistent.json",
+            "--metrics-out",
+            "m.json"
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "daemon doesn't support --load-snapshot, --expect, --metrics-out, --format"
+        );
+
+        let err = validate(&[
+            "transactions",
+            "validate",
+            "f.csv",
+            "--format",
+            "json",
+            "--expect",
+            "/nope",
+            "--strict"
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "validate doesn't support --strict, --expect, --format"
+        );
 
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+        let err = validate(&[
+            "transactions",
+            "schema",
+            "--load-snapshot",
+            "/nope",
+            "--strict"
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "schema doesn't support --strict, --load-snapshot"
+        );
 
-        assert_eq!(client.available, dec!(5.0));
-        assert_eq!(client.held, dec!(0.0));
-        assert_eq!(client.total, dec!(5.0));
-        assert!(!client.locked);
+        assert!(validate(&["transactions", "http", "--pretty"]).is_err());
+        assert!(validate(&["transactions", "serve", "--pretty"]).is_err());
     }
 
     #[test]
-    fn locked_account_ignores_future_transactions() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(10.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Dispute,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Chargeback,
-                client: 1,
-                tx:     1,
-                amount: None
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     2,
-                amount: Some(dec!(5.0))
-            }),
-        ];
-
-        let clients = process(txs).unwrap();
-        let client = clients.get(&1).unwrap();
+    fn subcommands_accept_honored_flags() {
+        assert!(validate(&[
+            "transactions",
+            "daemon",
+            "--strict",
+            "--no-header",
+            "--pretty",
+            "--trim-zeros",
+            "--verbose"
+        ])
+        .is_ok());
+        assert!(validate(&[
+            "transactions",
+            "http",
+            "--load-snapshot",
+            "state.json",
+            "--locked-only",
+            "--strict"
+        ])
+        .is_ok());
+        assert!(validate(&["transactions", "validate", "f.csv", "--no-header"]).is_ok());
+    }
 
-        assert_eq!(client.available, dec!(0.0));
-        assert_eq!(client.total, dec!(0.0));
-        assert!(client.locked);
+    #[test]
+    fn overdraft_conflicts_with_nonnegative_validation() {
+        let err = validate(&[
+            "transactions",
+            "test.csv",
+            "--ignore-withdrawals-overdraft",
+            "--validate-balances-nonnegative"
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "conflicting flags: --ignore-withdrawals-overdraft with --validate-balances-nonnegative"
+        );
     }
 
     #[test]
-    fn handles_multiple_clients_independently() {
-        let txs = vec![
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 1,
-                tx:     1,
-                amount: Some(dec!(10.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Withdrawal,
-                client: 1,
-                tx:     2,
-                amount: Some(dec!(4.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Deposit,
-                client: 2,
-                tx:     3,
-                amount: Some(dec!(20.0))
-            }),
-            Ok(Transaction {
-                kind:   TransactionType::Withdrawal,
-                client: 2,
-                tx:     4,
-                amount: Some(dec!(15.0))
-            }),
-        ];
+    fn negative_balance_options_conflict_with_nonnegative_validation() {
+        let err = validate(&[
+            "transactions",
+            "test.csv",
+            "--dispute-mode",
+            "proportional",
+            "--allow-resolve-to-negative-held",
+            "--saturating",
+            "--validate-balances-nonnegative"
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "conflicting flags: --dispute-mode proportional with --validate-balances-nonnegative, \
+             --allow-resolve-to-negative-held with --validate-balances-nonnegative, --saturating \
+             with --validate-balances-nonnegative"
+        );
+    }
 
-        let clients = process(txs).unwrap();
+    #[test]
+    fn merge_parallel_conflicts_with_per_file_flags() {
+        let err = validate(&[
+            "transactions",
+            "a.csv",
+            "--merge-parallel",
+            "b.csv",
+            "--load-snapshot",
+            "state.json"
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "conflicting flags: --merge-parallel with --load-snapshot"
+        );
+    }
 
-        let c1 = clients.get(&1).unwrap();
-        assert_eq!(c1.available, dec!(6.0));
-        assert_eq!(c1.total, dec!(6.0));
-        assert_eq!(c1.held, dec!(0.0));
-        assert!(!c1.locked);
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_conflicts_with_csv_only_flags() {
+        let err = validate(&[
+            "transactions",
+            "test.csv",
+            "--format",
+            "parquet",
+            "--output",
+            "out.parquet",
+            "--output-totals-row"
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "conflicting flags: --format parquet with --output-totals-row"
+        );
+    }
 
-        let c2 = clients.get(&2).unwrap();
-        assert_eq!(c2.available, dec!(5.0));
-        assert_eq!(c2.total, dec!(5.0));
-        assert_eq!(c2.held, dec!(0.0));
-        assert!(!c2.locked);
+    #[test]
+    fn group_by_conflicts_with_row_flags() {
+        let err = validate(&[
+            "transactions",
+            "test.csv",
+            "--group-by",
+            "all",
+            "--output-totals-row"
+        ])
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "conflicting flags: --group-by with --output-totals-row"
+        );
+        assert!(validate(&["transactions", "test.csv", "--group-by", "bucket:10"]).is_ok());
     }
 }
@@ -0,0 +1,201 @@
+use crate::{Outcome, Skip, TransactionType};
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+/// How many transactions of a type were applied and their summed amount.
+#[derive(Serialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct Totals {
+    pub count:  usize,
+    pub amount: Decimal
+}
+
+/// Counters of the transactions an engine processed, for dashboards and
+/// library consumers, readable at any time through `Engine::metrics`.
+/// Transactions that fail to apply aren't counted at all.
+#[derive(Serialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct EngineMetrics {
+    /// How many transactions were applied, of any type.
+    pub applied: usize,
+
+    /// The applied deposits and their summed amount.
+    pub deposits: Totals,
+
+    /// The applied withdrawals and their summed amount.
+    pub withdrawals: Totals,
+
+    /// The applied transfers and their summed amount.
+    pub transfers: Totals,
+
+    /// How many disputes were applied, including later resolved or
+    /// charged back ones.
+    pub disputes: usize,
+
+    /// How many resolves were applied.
+    pub resolves: usize,
+
+    /// How many chargebacks were applied.
+    pub chargebacks: usize,
+
+    /// How many transactions were skipped, for any reason.
+    pub skipped: usize,
+
+    /// How many transactions were skipped for each reason. Reasons no
+    /// transaction was skipped for are left out.
+    pub skipped_by: BTreeMap<Skip, usize>,
+
+    /// How many disputes are currently open, neither resolved nor charged
+    /// back.
+    pub open_disputes: usize
+}
+
+impl EngineMetrics {
+    /// Records a transaction's type, amount and outcome.
+    pub fn record(&mut self, kind: TransactionType, amount: Option<Decimal>, outcome: Outcome) {
+        if let Outcome::Skipped(skip) = outcome {
+            self.skipped += 1;
+            *self
+                .skipped_by
+                .entry(skip)
+                .or_default() += 1;
+            return;
+        }
+
+        self.applied += 1;
+
+        let totals = match kind {
+            TransactionType::Deposit => &mut self.deposits,
+            TransactionType::Withdrawal => &mut self.withdrawals,
+            TransactionType::Transfer => &mut self.transfers,
+            TransactionType::Dispute => return self.disputes += 1,
+            TransactionType::Resolve => return self.resolves += 1,
+            TransactionType::Chargeback => return self.chargebacks += 1
+        };
+
+        totals.count += 1;
+        // Sums saturate, since they're only informational.
+        totals.amount = totals
+            .amount
+            .saturating_add(amount.unwrap_or_default());
+    }
+}
+
+/// Writes the metrics as json.
+pub fn write_metrics<W: Write>(out: W, metrics: &EngineMetrics) -> Result<()> {
+    serde_json::to_writer_pretty(out, metrics)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, Options, Transaction};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn metrics_are_written_as_json() {
+        let mut engine = Engine::new(Options::default());
+
+        for (kind, tx, amount, counterparty) in [
+            (TransactionType::Deposit, 1, Some(dec!(10.0)), None),
+            (TransactionType::Deposit, 2, Some(dec!(2.5)), None),
+            (TransactionType::Withdrawal, 3, Some(dec!(1.0)), None),
+            (TransactionType::Withdrawal, 4, Some(dec!(100.0)), None),
+            (TransactionType::Transfer, 5, Some(dec!(2.0)), Some(2)),
+            (TransactionType::Dispute, 2, None, None),
+            (TransactionType::Resolve, 2, None, None),
+            (TransactionType::Dispute, 2, None, None),
+            (TransactionType::Chargeback, 2, None, None)
+        ] {
+            engine
+                .apply(Transaction {
+                    kind,
+                    client: 1,
+                    tx,
+                    amount,
+                    counterparty,
+                    seq: None,
+                    timestamp: None
+                })
+                .unwrap();
+        }
+
+        let mut out = Vec::new();
+
+        write_metrics(&mut out, engine.metrics()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "applied": 8,
+                "deposits": { "count": 2, "amount": "12.5" },
+                "withdrawals": { "count": 1, "amount": "1.0" },
+                "transfers": { "count": 1, "amount": "2.0" },
+                "disputes": 2,
+                "resolves": 1,
+                "chargebacks": 1,
+                "skipped": 1,
+                "skipped_by": { "insufficient-funds": 1 },
+                "open_disputes": 0
+            })
+        );
+    }
+
+    #[test]
+    fn counters_track_a_mixed_sequence() {
+        let mut engine = Engine::new(Options::default());
+        let apply = |engine: &mut Engine, kind, tx, amount| {
+            engine
+                .apply(Transaction {
+                    kind,
+                    client: 1,
+                    tx,
+                    amount,
+                    counterparty: None,
+                    seq: None,
+                    timestamp: None
+                })
+                .unwrap();
+        };
+
+        apply(&mut engine, TransactionType::Deposit, 1, Some(dec!(10.0)));
+        apply(&mut engine, TransactionType::Deposit, 2, Some(dec!(5.0)));
+        apply(&mut engine, TransactionType::Dispute, 1, None);
+        apply(&mut engine, TransactionType::Dispute, 1, None);
+        apply(&mut engine, TransactionType::Dispute, 9, None);
+        apply(
+            &mut engine,
+            TransactionType::Withdrawal,
+            3,
+            Some(dec!(50.0))
+        );
+
+        let metrics = engine.metrics();
+
+        assert_eq!(metrics.applied, 3);
+        assert_eq!(metrics.skipped, 3);
+        assert_eq!(metrics.open_disputes, 1);
+        assert_eq!(
+            metrics.skipped_by,
+            BTreeMap::from([
+                (Skip::InsufficientFunds, 1),
+                (Skip::UnknownTransaction, 1),
+                (Skip::AlreadyDisputed, 1)
+            ])
+        );
+
+        apply(&mut engine, TransactionType::Chargeback, 1, None);
+        apply(&mut engine, TransactionType::Deposit, 4, Some(dec!(1.0)));
+
+        let metrics = engine.metrics();
+
+        assert_eq!(metrics.applied, 4);
+        assert_eq!(metrics.chargebacks, 1);
+        assert_eq!(metrics.open_disputes, 0);
+        assert_eq!(metrics.skipped_by[&Skip::Locked], 1);
+    }
+}
@@ -0,0 +1,1199 @@
+use crate::{ClientData, PRECISION};
+use anstyle::{AnsiColor, Style};
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use csv::{QuoteStyle, WriterBuilder};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    str::FromStr
+};
+
+/// Options that control how client data is output.
+#[derive(Default, Debug, Clone)]
+pub struct OutputOptions {
+    /// Whether only locked clients are output.
+    pub locked_only: bool,
+
+    /// How held funds are represented. When unset, `available`, `held`
+    /// and `total` are output as they are.
+    pub held_as_negative: Option<HeldAsNegative>,
+
+    /// Whether the output is flushed after every row rather than only
+    /// once at the end.
+    pub flush_each_row: bool,
+
+    /// Whether an aligned table is output instead of csv.
+    pub pretty: bool,
+
+    /// Whether the table highlights locked clients in color.
+    pub color: bool,
+
+    /// Per-column decimal places, overriding `PRECISION`. Later entries
+    /// win if a column is listed more than once.
+    pub column_precision: Vec<ColumnPrecision>,
+
+    /// Whether csv output ends with a `TOTAL` row summing `available`,
+    /// `held` and `total` across the output clients, with the number of
+    /// locked clients in the `locked` column.
+    pub totals_row: bool,
+
+    /// Whether amounts are output without trailing zeros, so `10.5000`
+    /// is output as `10.5` and `10.0000` as `10`. This only changes how
+    /// amounts are displayed.
+    pub trim_zeros: bool,
+
+    /// The width csv client ids are zero-padded to, so client 1 is
+    /// output as `00001` with a width of 5. When unset, ids aren't padded.
+    pub client_width: Option<usize>,
+
+    /// The number of transactions processed for each client. When set,
+    /// csv and json output have a `tx_count` column, counting clients
+    /// missing from it as zero.
+    pub tx_counts: Option<HashMap<u16, u64>>,
+
+    /// How csv rows are terminated.
+    pub terminator: Terminator,
+
+    /// Which csv fields are quoted.
+    pub quoting: Quoting
+}
+
+impl OutputOptions {
+    /// Returns options for canonical csv, so the same client data is
+    /// always output as the same bytes, say for hashing or signing. Rows
+    /// are sorted by client id and have every amount at `PRECISION`
+    /// places, no quoting and `\n` endings, overriding any formatting
+    /// options. Which clients are output is unchanged.
+    pub fn canonical(self) -> Self {
+        Self {
+            locked_only: self.locked_only,
+            ..Self::default()
+        }
+    }
+
+    /// The number of decimal places to output a column with.
+    pub fn precision(&self, column: Column) -> u32 {
+        self.column_precision
+            .iter()
+            .rev()
+            .find(|p| p.column == column)
+            .map_or(PRECISION, |p| p.places)
+    }
+
+    /// Formats an amount for a column, at the column's precision and
+    /// without trailing zeros if asked to.
+    pub fn format(&self, value: Decimal, column: Column) -> String {
+        let places = self.precision(column);
+
+        if self.trim_zeros {
+            value
+                .trunc_with_scale(places)
+                .normalize()
+                .to_string()
+        } else {
+            format_amount(value, places)
+        }
+    }
+}
+
+/// Which transactions the `tx_count` column counts.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxCount {
+    /// Count every transaction for the client, applied or skipped.
+    #[default]
+    All,
+
+    /// Count only the transactions that were applied.
+    Applied
+}
+
+/// A monetary output column.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    Available,
+    Held,
+    Total
+}
+
+/// The decimal places to output a column with, parsed from `column=places`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColumnPrecision {
+    pub column: Column,
+    pub places: u32
+}
+
+impl FromStr for ColumnPrecision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (column, places) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected column=places, got {s}"))?;
+
+        Ok(Self {
+            column: Column::from_str(column.trim(), true).map_err(|e| anyhow!(e))?,
+            places: places.trim().parse()?
+        })
+    }
+}
+
+/// The output format.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Csv, or an aligned table with `pretty`.
+    #[default]
+    Csv,
+
+    /// A json array of clients, sorted by client id, with amounts as
+    /// strings so no precision is lost.
+    Json,
+
+    /// A parquet file.
+    #[cfg(feature = "parquet")]
+    Parquet,
+
+    /// A `clients` table in a sqlite database, with amounts as text so
+    /// no precision is lost.
+    #[cfg(feature = "sqlite")]
+    Sqlite
+}
+
+/// How csv rows are terminated.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Terminator {
+    /// Terminate rows with `\n`.
+    #[default]
+    Lf,
+
+    /// Terminate rows with `\r\n`, as RFC 4180 specifies.
+    Crlf
+}
+
+impl From<Terminator> for csv::Terminator {
+    fn from(terminator: Terminator) -> Self {
+        match terminator {
+            Terminator::Lf => Self::Any(b'\n'),
+            Terminator::Crlf => Self::CRLF
+        }
+    }
+}
+
+/// Which csv fields are quoted.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quoting {
+    /// Only quote fields that need it.
+    #[default]
+    Necessary,
+
+    /// Quote every field.
+    Always,
+
+    /// Quote every field that isn't a number.
+    NonNumeric
+}
+
+impl From<Quoting> for QuoteStyle {
+    fn from(quoting: Quoting) -> Self {
+        match quoting {
+            Quoting::Necessary => Self::Necessary,
+            Quoting::Always => Self::Always,
+            Quoting::NonNumeric => Self::NonNumeric
+        }
+    }
+}
+
+/// Ledger conventions that represent held funds as a negative amount.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeldAsNegative {
+    /// Output `available - held` as `available`. `held` and `total` are
+    /// output unchanged.
+    Available,
+
+    /// Output `-held` as `held`. `available` and `total` are output
+    /// unchanged.
+    Held
+}
+
+impl HeldAsNegative {
+    /// Returns the `available`, `held` and `total` columns to output.
+    /// Fails if `available - held` overflows, which only an overdraft can
+    /// cause.
+    pub fn apply(&self, client: &ClientData) -> Result<(Decimal, Decimal, Decimal)> {
+        Ok(match self {
+            Self::Available => (
+                client
+                    .available
+                    .checked_sub(client.held)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "available {} less held {} overflows",
+                            client.available,
+                            client.held
+                        )
+                    })?,
+                client.held,
+                client.total
+            ),
+            Self::Held => (client.available, -client.held, client.total)
+        })
+    }
+}
+
+/// Returns the clients sorted by client id.
+pub fn clients_sorted(clients: &HashMap<u16, ClientData>) -> Vec<(u16, &ClientData)> {
+    let mut sorted = clients
+        .iter()
+        .map(|(id, client)| (*id, client))
+        .collect::<Vec<_>>();
+
+    sorted.sort_unstable_by_key(|(id, _)| *id);
+    sorted
+}
+
+/// Returns the clients that should be reported, sorted by client id.
+pub fn reported<'a>(
+    clients: &'a HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> impl Iterator<Item = (u16, &'a ClientData)> {
+    let locked_only = options.locked_only;
+
+    clients_sorted(clients)
+        .into_iter()
+        .filter(move |(_, client)| !locked_only || client.locked)
+}
+
+/// Returns the `available`, `held` and `total` columns, before they're
+/// formatted.
+pub(crate) fn columns(
+    client: &ClientData,
+    options: &OutputOptions
+) -> Result<(Decimal, Decimal, Decimal)> {
+    match &options.held_as_negative {
+        Some(convention) => convention.apply(client),
+        None => Ok((client.available, client.held, client.total))
+    }
+}
+
+/// Returns the reported clients with their balances as they're written:
+/// transformed like `held_as_negative` asks and truncated to each
+/// column's precision. Output read back, say by `--expect`, compares
+/// equal to this.
+pub fn as_written(
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<HashMap<u16, ClientData>> {
+    reported(clients, options)
+        .map(|(id, client)| {
+            let (available, held, total) = columns(client, options)?;
+            let written =
+                |value: Decimal, column| value.trunc_with_scale(options.precision(column));
+
+            Ok((
+                id,
+                ClientData {
+                    available: written(available, Column::Available),
+                    held:      written(held, Column::Held),
+                    total:     written(total, Column::Total),
+                    locked:    client.locked
+                }
+            ))
+        })
+        .collect()
+}
+
+/// Returns the formatted `available`, `held` and `total` columns.
+fn amounts(client: &ClientData, options: &OutputOptions) -> Result<[String; 3]> {
+    let (available, held, total) = columns(client, options)?;
+
+    Ok([
+        options.format(available, Column::Available),
+        options.format(held, Column::Held),
+        options.format(total, Column::Total)
+    ])
+}
+
+/// Returns the `tx_count` column of a client, if it's output.
+fn tx_count(id: u16, options: &OutputOptions) -> Option<u64> {
+    options
+        .tx_counts
+        .as_ref()
+        .map(|counts| {
+            counts
+                .get(&id)
+                .copied()
+                .unwrap_or_default()
+        })
+}
+
+/// Formats an amount to exactly `places` decimal places, truncating any
+/// beyond that. Unlike `{:.places$}`, this can't panic on amounts near
+/// the largest a `Decimal` holds.
+pub(crate) fn format_amount(value: Decimal, places: u32) -> String {
+    let text = value
+        .trunc_with_scale(places)
+        .to_string();
+    let places = places as usize;
+
+    if places == 0 {
+        return text;
+    }
+
+    match text.split_once('.') {
+        Some((whole, fraction)) => format!("{whole}.{fraction:0<places$}"),
+        None => format!("{text}.{:0<places$}", "")
+    }
+}
+
+/// Writes the client data, as csv or a table, flushing once done.
+pub fn write_output<W: Write>(
+    out: &mut W,
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<()> {
+    if options.pretty {
+        write_table(out, clients, options)
+    } else {
+        write_clients(out, clients, options)
+    }
+}
+
+/// Writes the client data as csv, flushing once done. Wrap unbuffered
+/// writers in a `BufWriter` to avoid a write per row.
+pub fn write_clients<W: Write>(
+    out: &mut W,
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<()> {
+    let mut writer = WriterBuilder::new()
+        .terminator(options.terminator.into())
+        .quote_style(options.quoting.into())
+        .from_writer(&mut *out);
+
+    let mut header = vec!["client", "available", "held", "total", "locked"];
+
+    if options.tx_counts.is_some() {
+        header.push("tx_count");
+    }
+
+    writer.write_record(&header)?;
+
+    for (id, client) in reported(clients, options) {
+        let [available, held, total] = amounts(client, options)?;
+        let mut row = vec![
+            format!(
+                "{id:0>width$}",
+                width = options
+                    .client_width
+                    .unwrap_or_default()
+            ),
+            available,
+            held,
+            total,
+            client.locked.to_string(),
+        ];
+
+        row.extend(tx_count(id, options).map(|count| count.to_string()));
+        writer.write_record(&row)?;
+
+        if options.flush_each_row {
+            writer.flush()?;
+        }
+    }
+
+    if options.totals_row {
+        let mut sum = ClientData::default();
+        let mut locked = 0;
+        let mut count = 0;
+
+        for (id, client) in reported(clients, options) {
+            let overflow = || anyhow!("the totals row overflows");
+
+            sum.available = sum
+                .available
+                .checked_add(client.available)
+                .ok_or_else(overflow)?;
+            sum.held = sum
+                .held
+                .checked_add(client.held)
+                .ok_or_else(overflow)?;
+            sum.total = sum
+                .total
+                .checked_add(client.total)
+                .ok_or_else(overflow)?;
+            locked += usize::from(client.locked);
+            count += tx_count(id, options).unwrap_or_default();
+        }
+
+        let [available, held, total] = amounts(&sum, options)?;
+        let mut row = vec![
+            "TOTAL".to_string(),
+            available,
+            held,
+            total,
+            locked.to_string(),
+        ];
+
+        if options.tx_counts.is_some() {
+            row.push(count.to_string());
+        }
+
+        writer.write_record(&row)?;
+    }
+
+    // Releasing the writer flushes it.
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())?;
+
+    Ok(())
+}
+
+/// Whether an error comes from writing to a pipe whose reader went away,
+/// like `head` exiting once it has the lines it wants.
+pub fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    let broken = |kind| kind == io::ErrorKind::BrokenPipe;
+
+    error.chain().any(|e| {
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            broken(e.kind())
+        } else if let Some(e) = e.downcast_ref::<csv::Error>() {
+            matches!(e.kind(), csv::ErrorKind::Io(e) if broken(e.kind()))
+        } else if let Some(e) = e.downcast_ref::<serde_json::Error>() {
+            e.io_error_kind()
+                .is_some_and(broken)
+        } else {
+            false
+        }
+    })
+}
+
+/// A client as it appears in json output.
+#[derive(Serialize)]
+pub struct JsonRow {
+    client:    u16,
+    available: String,
+    held:      String,
+    total:     String,
+    locked:    bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx_count: Option<u64>
+}
+
+/// Returns the reported clients as they appear in json output, sorted by
+/// client id.
+pub fn json_rows(
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<Vec<JsonRow>> {
+    reported(clients, options)
+        .map(|(client, data)| {
+            let [available, held, total] = amounts(data, options)?;
+
+            Ok(JsonRow {
+                client,
+                available,
+                held,
+                total,
+                locked: data.locked,
+                tx_count: tx_count(client, options)
+            })
+        })
+        .collect()
+}
+
+/// Writes the client data as newline-delimited json, one object per
+/// client sorted by client id, flushing once done.
+pub fn write_ndjson<W: Write>(
+    out: &mut W,
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<()> {
+    for row in json_rows(clients, options)? {
+        serde_json::to_writer(&mut *out, &row)?;
+        writeln!(out)?;
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Writes the client data as a json array sorted by client id, flushing
+/// once done.
+pub fn write_json<W: Write>(
+    out: &mut W,
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<()> {
+    serde_json::to_writer(&mut *out, &json_rows(clients, options)?)?;
+    writeln!(out)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Writes the client data as a table with aligned columns, flushing once
+/// done.
+pub fn write_table<W: Write>(
+    out: &mut W,
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<()> {
+    let header = ["client", "available", "held", "total", "locked"].map(String::from);
+    let rows = reported(clients, options)
+        .map(|(id, client)| {
+            let [available, held, total] = amounts(client, options)?;
+
+            Ok((
+                [
+                    id.to_string(),
+                    available,
+                    held,
+                    total,
+                    client.locked.to_string()
+                ],
+                client.locked
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Size each column to fit its widest cell.
+    let mut widths = header.each_ref().map(String::len);
+
+    for (row, _) in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    // Text is left aligned and numbers are right aligned.
+    let line = |row: &[String; 5]| {
+        format!(
+            "{:<w0$}  {:>w1$}  {:>w2$}  {:>w3$}  {:<w4$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4]
+        )
+        .trim_end()
+        .to_string()
+    };
+
+    writeln!(out, "{}", line(&header))?;
+
+    for (row, locked) in &rows {
+        if *locked && options.color {
+            let style = Style::new()
+                .bold()
+                .fg_color(Some(AnsiColor::Red.into()));
+
+            writeln!(out, "{style}{}{style:#}", line(row))?;
+        } else {
+            writeln!(out, "{}", line(row))?;
+        }
+    }
+
+    out.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        process_slice,
+        testing::{assert_amount_eq, assert_amount_formatted},
+        Options, Transaction, TransactionType
+    };
+    use rust_decimal_macros::dec;
+
+    /// A writer whose reader went away.
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn closed_pipes_are_recognized() {
+        let clients = HashMap::from([(1, ClientData::default())]);
+        let pretty = OutputOptions {
+            pretty: true,
+            ..OutputOptions::default()
+        };
+        let errors = [
+            write_clients(&mut ClosedPipe, &clients, &OutputOptions::default()),
+            write_json(&mut ClosedPipe, &clients, &OutputOptions::default()),
+            write_output(&mut ClosedPipe, &clients, &pretty)
+        ];
+
+        for error in errors {
+            assert!(is_broken_pipe(&error.unwrap_err()));
+        }
+
+        assert!(!is_broken_pipe(&anyhow!("something else")));
+        assert!(!is_broken_pipe(&io::Error::other("disk full").into()));
+    }
+
+    #[test]
+    fn trailing_zeros_are_trimmed() {
+        let options = OutputOptions {
+            trim_zeros: true,
+            ..OutputOptions::default()
+        };
+
+        assert_eq!(options.format(dec!(10.5000), Column::Total), "10.5");
+        assert_eq!(options.format(dec!(10.0000), Column::Total), "10");
+        assert_eq!(options.format(dec!(0.0000), Column::Held), "0");
+        assert_eq!(
+            OutputOptions::default().format(dec!(10.5), Column::Total),
+            "10.5000"
+        );
+    }
+
+    #[test]
+    fn amounts_are_formatted_to_places() {
+        assert_eq!(format_amount(dec!(1.5), 4), "1.5000");
+        assert_eq!(format_amount(dec!(-2), 2), "-2.00");
+        assert_eq!(format_amount(dec!(1.23456), 4), "1.2345");
+        assert_eq!(format_amount(dec!(7.9), 0), "7");
+
+        // These panic with `{:.4}`.
+        assert_eq!(
+            format_amount(dec!(7922816251426433759354395033.5), 4),
+            "7922816251426433759354395033.5000"
+        );
+    }
+
+    #[test]
+    fn locked_only_reports_only_locked_clients() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(3.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       2,
+                tx:           2,
+                amount:       Some(dec!(4.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       2,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Chargeback,
+                client:       2,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+
+        let all = reported(&clients, &OutputOptions::default())
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(all, vec![1, 2]);
+
+        let options = OutputOptions {
+            locked_only: true,
+            ..OutputOptions::default()
+        };
+        let locked = reported(&clients, &options)
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(locked, vec![2]);
+    }
+
+    #[test]
+    fn output_has_no_float_error() {
+        let txs = (1..=3)
+            .map(|tx| Transaction {
+                kind: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(dec!(0.1)),
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            })
+            .collect::<Vec<_>>();
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let mut out = Vec::new();
+
+        assert_amount_eq(clients[&1].available, dec!(0.3));
+        assert_amount_formatted(clients[&1].available, "0.3000");
+
+        write_clients(&mut out, &clients, &OutputOptions::default()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n1,0.3000,0.0000,0.3000,false\n"
+        );
+    }
+
+    #[test]
+    fn clients_sorted_orders_by_id() {
+        let clients = [7, 3, 11, 1]
+            .into_iter()
+            .map(|id| (id, ClientData::default()))
+            .collect::<HashMap<_, _>>();
+
+        let ids = clients_sorted(&clients)
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec![1, 3, 7, 11]);
+    }
+
+    #[test]
+    fn output_is_sorted_by_client() {
+        let clients = [3, 1, 2]
+            .into_iter()
+            .map(|id| (id, ClientData::default()))
+            .collect::<HashMap<_, _>>();
+        let mut out = Vec::new();
+
+        write_clients(&mut out, &clients, &OutputOptions::default()).unwrap();
+
+        let ids = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| {
+                line.split(',')
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec!["1", "2", "3"]);
+    }
+
+    #[test]
+    fn json_is_sorted_by_client() {
+        let mut clients = HashMap::new();
+
+        for id in [9, 2, 40, 1, 17] {
+            clients.insert(id, ClientData::default());
+        }
+
+        let mut out = Vec::new();
+
+        write_json(&mut out, &clients, &OutputOptions::default()).unwrap();
+
+        let rows: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        let ids = rows
+            .iter()
+            .map(|row| row["client"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec![1, 2, 9, 17, 40]);
+        assert_eq!(rows[0]["available"], "0.0000");
+        assert_eq!(rows[0]["locked"], false);
+    }
+
+    #[test]
+    fn held_as_negative_transforms_open_disputes() {
+        let txs = vec![
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           2,
+                amount:       Some(dec!(4.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           2,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+        ];
+
+        let clients = process_slice(&txs, &Options::default()).unwrap();
+        let (available, held, total) = HeldAsNegative::Held
+            .apply(&clients[&1])
+            .unwrap();
+
+        assert_amount_eq(available, dec!(10));
+        assert_amount_eq(held, dec!(-4));
+        assert_amount_eq(total, dec!(14));
+        assert_amount_formatted(held, "-4.0000");
+
+        let render = |held_as_negative| {
+            let options = OutputOptions {
+                held_as_negative,
+                ..OutputOptions::default()
+            };
+            let mut out = Vec::new();
+
+            write_clients(&mut out, &clients, &options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            render(None),
+            "client,available,held,total,locked\n1,10.0000,4.0000,14.0000,false\n"
+        );
+        assert_eq!(
+            render(Some(HeldAsNegative::Available)),
+            "client,available,held,total,locked\n1,6.0000,4.0000,14.0000,false\n"
+        );
+        assert_eq!(
+            render(Some(HeldAsNegative::Held)),
+            "client,available,held,total,locked\n1,10.0000,-4.0000,14.0000,false\n"
+        );
+
+        // An overdrawn client can overflow rather than saturate.
+        let overdrawn = ClientData {
+            available: Decimal::MIN,
+            held: dec!(1),
+            ..ClientData::default()
+        };
+
+        assert!(HeldAsNegative::Available
+            .apply(&overdrawn)
+            .is_err());
+    }
+
+    /// A writer that counts flushes.
+    #[derive(Default)]
+    struct Flushes {
+        bytes:   Vec<u8>,
+        flushes: usize
+    }
+
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.bytes.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn flushing_each_row_writes_identical_bytes() {
+        let clients = (0..5000)
+            .map(|id| {
+                (
+                    id,
+                    ClientData {
+                        available: Decimal::from(id),
+                        total: Decimal::from(id),
+                        ..ClientData::default()
+                    }
+                )
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut buffered = Flushes::default();
+        write_clients(&mut buffered, &clients, &OutputOptions::default()).unwrap();
+
+        let mut flushed = Flushes::default();
+        let options = OutputOptions {
+            flush_each_row: true,
+            ..OutputOptions::default()
+        };
+        write_clients(&mut flushed, &clients, &options).unwrap();
+
+        assert_eq!(buffered.bytes, flushed.bytes);
+        assert_eq!(buffered.flushes, 1);
+        assert_eq!(flushed.flushes, 5001);
+
+        let text = String::from_utf8(buffered.bytes).unwrap();
+        assert_eq!(text.lines().count(), 5001);
+        assert_eq!(
+            text.lines().last(),
+            Some("4999,4999.0000,0.0000,4999.0000,false")
+        );
+    }
+
+    #[test]
+    fn table_columns_are_aligned() {
+        let clients = HashMap::from([
+            (
+                1,
+                ClientData {
+                    available: dec!(1.5),
+                    total: dec!(1.5),
+                    ..ClientData::default()
+                }
+            ),
+            (
+                12345,
+                ClientData {
+                    available: dec!(1000.0),
+                    held:      dec!(20.0),
+                    total:     dec!(1020.0),
+                    locked:    true
+                }
+            )
+        ]);
+        let options = OutputOptions {
+            pretty: true,
+            ..OutputOptions::default()
+        };
+        let mut out = Vec::new();
+
+        write_output(&mut out, &clients, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                "client  available     held      total  locked\n",
+                "1          1.5000   0.0000     1.5000  false\n",
+                "12345   1000.0000  20.0000  1020.0000  true\n"
+            )
+        );
+    }
+
+    #[test]
+    fn table_highlights_locked_clients_in_color() {
+        let clients = HashMap::from([(
+            1,
+            ClientData {
+                locked: true,
+                ..ClientData::default()
+            }
+        )]);
+        let options = OutputOptions {
+            pretty: true,
+            color: true,
+            ..OutputOptions::default()
+        };
+        let mut out = Vec::new();
+
+        write_output(&mut out, &clients, &options).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let row = text.lines().nth(1).unwrap();
+
+        assert!(row.starts_with("\x1b["));
+        assert!(row.ends_with("true\x1b[0m"));
+    }
+
+    #[test]
+    fn column_precision_overrides_global_precision() {
+        let clients = HashMap::from([(
+            1,
+            ClientData {
+                available: dec!(1.5),
+                held:      dec!(2.5),
+                total:     dec!(4.0),
+                locked:    false
+            }
+        )]);
+        let options = OutputOptions {
+            column_precision: vec!["held=2".parse().unwrap()],
+            ..OutputOptions::default()
+        };
+        let mut out = Vec::new();
+
+        write_clients(&mut out, &clients, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n1,1.5000,2.50,4.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn column_precision_parses() {
+        assert_eq!(
+            "Total = 0"
+                .parse::<ColumnPrecision>()
+                .unwrap(),
+            ColumnPrecision {
+                column: Column::Total,
+                places: 0
+            }
+        );
+        assert!("held"
+            .parse::<ColumnPrecision>()
+            .is_err());
+        assert!("client=2"
+            .parse::<ColumnPrecision>()
+            .is_err());
+        assert!("held=x"
+            .parse::<ColumnPrecision>()
+            .is_err());
+    }
+
+    #[test]
+    fn crlf_terminates_rows_with_crlf() {
+        let clients = HashMap::from([(1, ClientData::default())]);
+        let options = OutputOptions {
+            terminator: Terminator::Crlf,
+            ..OutputOptions::default()
+        };
+        let mut out = Vec::new();
+
+        write_clients(&mut out, &clients, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\r\n1,0.0000,0.0000,0.0000,false\r\n"
+        );
+    }
+
+    #[test]
+    fn quoting_always_quotes_every_field() {
+        let clients = HashMap::from([(1, ClientData::default())]);
+        let options = OutputOptions {
+            quoting: Quoting::Always,
+            ..OutputOptions::default()
+        };
+        let mut out = Vec::new();
+
+        write_clients(&mut out, &clients, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\"client\",\"available\",\"held\",\"total\",\"locked\"\n\
+             \"1\",\"0.0000\",\"0.0000\",\"0.0000\",\"false\"\n"
+        );
+    }
+
+    #[test]
+    fn client_ids_are_zero_padded() {
+        let clients = HashMap::from([(1, ClientData::default()), (12345, ClientData::default())]);
+        let options = OutputOptions {
+            client_width: Some(5),
+            ..OutputOptions::default()
+        };
+        let mut out = Vec::new();
+
+        write_clients(&mut out, &clients, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n\
+             00001,0.0000,0.0000,0.0000,false\n\
+             12345,0.0000,0.0000,0.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn tx_counts_are_output_when_set() {
+        let clients = HashMap::from([(1, ClientData::default()), (2, ClientData::default())]);
+        let options = OutputOptions {
+            tx_counts: Some(HashMap::from([(1, 3)])),
+            totals_row: true,
+            ..OutputOptions::default()
+        };
+        let mut csv = Vec::new();
+        let mut json = Vec::new();
+
+        write_clients(&mut csv, &clients, &options).unwrap();
+        write_json(&mut json, &clients, &options).unwrap();
+
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "client,available,held,total,locked,tx_count\n\
+             1,0.0000,0.0000,0.0000,false,3\n\
+             2,0.0000,0.0000,0.0000,false,0\n\
+             TOTAL,0.0000,0.0000,0.0000,0,3\n"
+        );
+        assert!(String::from_utf8(json)
+            .unwrap()
+            .contains(r#""locked":false,"tx_count":3}"#));
+    }
+
+    #[test]
+    fn totals_row_sums_clients() {
+        let clients = HashMap::from([
+            (
+                1,
+                ClientData {
+                    available: dec!(1.5),
+                    held:      dec!(2.0),
+                    total:     dec!(3.5),
+                    locked:    false
+                }
+            ),
+            (
+                2,
+                ClientData {
+                    available: dec!(10.25),
+                    total: dec!(10.25),
+                    locked: true,
+                    ..ClientData::default()
+                }
+            ),
+            (
+                3,
+                ClientData {
+                    locked: true,
+                    ..ClientData::default()
+                }
+            )
+        ]);
+        let options = OutputOptions {
+            totals_row: true,
+            ..OutputOptions::default()
+        };
+        let mut out = Vec::new();
+
+        write_clients(&mut out, &clients, &options).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+
+        assert_eq!(text.lines().last(), Some("TOTAL,11.7500,2.0000,13.7500,2"));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{input, process, ClientData, InputOptions, Options};
+use anyhow::{anyhow, Result};
+use rust_decimal::Decimal;
+use std::{collections::HashMap, io::Read, thread};
+
+/// Processes each input on its own thread, then merges their client data
+/// with `merge`. The inputs are processed independently, so a dispute in
+/// one input can't refer to a deposit in another, and such disputes are
+/// skipped. This is meant for inputs with disjoint clients, like
+/// independent daily files.
+pub fn process_parallel<R: Read + Send>(
+    inputs: Vec<R>,
+    options: &Options,
+    input_options: &InputOptions
+) -> Result<HashMap<u16, ClientData>> {
+    let results = thread::scope(|scope| {
+        let handles = inputs
+            .into_iter()
+            .map(|input| {
+                scope.spawn(move || {
+                    let mut reader = input::reader(input, input_options);
+
+                    process(input::transactions(&mut reader, input_options)?, options)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| anyhow!("a processing thread panicked"))?
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    let mut merged = HashMap::new();
+
+    for clients in results {
+        merge(&mut merged, clients)?;
+    }
+
+    Ok(merged)
+}
+
+/// Merges client data into `into`, summing the balances of clients in
+/// both and locking them if either is locked. Fails if a summed balance
+/// overflows.
+pub fn merge(into: &mut HashMap<u16, ClientData>, clients: HashMap<u16, ClientData>) -> Result<()> {
+    for (id, client) in clients {
+        let merged = into.entry(id).or_default();
+        let sum = |a: Decimal, b: Decimal| {
+            a.checked_add(b)
+                .ok_or_else(|| anyhow!("merging client {id} overflows its balance"))
+        };
+
+        merged.available = sum(merged.available, client.available)?;
+        merged.held = sum(merged.held, client.held)?;
+        merged.total = sum(merged.total, client.total)?;
+        merged.locked |= client.locked;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn files_are_merged() {
+        let first = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,2,5.0\n\
+                     dispute,2,2,\n\
+                     chargeback,2,2,\n";
+        let second = "type,client,tx,amount\n\
+                      deposit,2,3,7.5\n\
+                      deposit,3,4,1.0\n\
+                      dispute,3,4,\n";
+        let clients = process_parallel(
+            vec![first.as_bytes(), second.as_bytes()],
+            &Options::default(),
+            &InputOptions::default()
+        )
+        .unwrap();
+
+        assert_eq!(clients.len(), 3);
+        assert_eq!(clients[&1].total, dec!(10.0));
+
+        // Client 2 is in both files, and locked by the first.
+        assert_eq!(clients[&2].available, dec!(7.5));
+        assert_eq!(clients[&2].total, dec!(7.5));
+        assert!(clients[&2].locked);
+
+        assert_eq!(clients[&3].held, dec!(1.0));
+        assert_eq!(clients[&3].total, dec!(1.0));
+    }
+
+    #[test]
+    fn errors_in_any_file_fail() {
+        let good = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        let bad = "type,client,tx,amount\ndeposit,1,2,abc\n";
+        let result = process_parallel(
+            vec![good.as_bytes(), bad.as_bytes()],
+            &Options::default(),
+            &InputOptions::default()
+        );
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    output::{self, Column, OutputOptions},
+    ClientData
+};
+use anyhow::Result;
+use arrow_array::{ArrayRef, BooleanArray, Decimal128Array, RecordBatch, UInt16Array};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use rust_decimal::Decimal;
+use std::{collections::HashMap, io::Write, sync::Arc};
+
+/// The number of significant digits in the monetary columns.
+const DECIMAL_PRECISION: u8 = 38;
+
+/// Writes the client data as a parquet file, with the monetary columns
+/// as decimals at their output precision so no amount goes through
+/// floating point.
+pub fn write_parquet<W: Write + Send>(
+    out: W,
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<()> {
+    let rows = output::reported(clients, options)
+        .map(|(id, client)| Ok((id, output::columns(client, options)?, client.locked)))
+        .collect::<Result<Vec<_>>>()?;
+    let decimal = |name, column, value: fn(&(Decimal, Decimal, Decimal)) -> Decimal| {
+        let scale = options.precision(column);
+        let values = rows
+            .iter()
+            .map(|(_, amounts, _)| {
+                let mut value = value(amounts).trunc_with_scale(scale);
+
+                value.rescale(scale);
+                value.mantissa()
+            })
+            .collect::<Decimal128Array>()
+            .with_precision_and_scale(DECIMAL_PRECISION, scale as i8)?;
+
+        Ok::<_, anyhow::Error>((
+            Field::new(
+                name,
+                DataType::Decimal128(DECIMAL_PRECISION, scale as i8),
+                false
+            ),
+            Arc::new(values) as ArrayRef
+        ))
+    };
+
+    let (fields, columns): (Vec<_>, Vec<_>) = [
+        (
+            Field::new("client", DataType::UInt16, false),
+            Arc::new(UInt16Array::from_iter_values(
+                rows.iter().map(|(id, ..)| *id)
+            )) as ArrayRef
+        ),
+        decimal("available", Column::Available, |a| a.0)?,
+        decimal("held", Column::Held, |a| a.1)?,
+        decimal("total", Column::Total, |a| a.2)?,
+        (
+            Field::new("locked", DataType::Boolean, false),
+            Arc::new(BooleanArray::from_iter(
+                rows.iter()
+                    .map(|(.., locked)| Some(*locked))
+            )) as ArrayRef
+        )
+    ]
+    .into_iter()
+    .unzip();
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let mut writer = ArrowWriter::try_new(out, schema, None)?;
+
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use rust_decimal_macros::dec;
+    use std::{
+        env,
+        fs::{self, File}
+    };
+
+    #[test]
+    fn parquet_round_trips() {
+        let clients = HashMap::from([
+            (
+                2,
+                ClientData {
+                    available: dec!(1.5),
+                    held:      dec!(2.25),
+                    total:     dec!(3.75),
+                    locked:    true
+                }
+            ),
+            (
+                1,
+                ClientData {
+                    available: dec!(10.12345),
+                    total: dec!(10.12345),
+                    ..ClientData::default()
+                }
+            )
+        ]);
+        let path = env::temp_dir().join(format!("transactions-{}.parquet", std::process::id()));
+
+        write_parquet(
+            File::create(&path).unwrap(),
+            &clients,
+            &OutputOptions::default()
+        )
+        .unwrap();
+
+        let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let column = |name| {
+            batch
+                .column_by_name(name)
+                .unwrap()
+                .clone()
+        };
+        let client = column("client");
+        let client = client
+            .as_any()
+            .downcast_ref::<UInt16Array>()
+            .unwrap();
+        let available = column("available");
+        let available = available
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+        let held = column("held");
+        let held = held
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+        let locked = column("locked");
+        let locked = locked
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .unwrap();
+
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(client.values(), &[1, 2]);
+        assert_eq!(available.scale(), 4);
+        assert_eq!(available.value(0), 101234);
+        assert_eq!(available.value(1), 15000);
+        assert_eq!(held.value(1), 22500);
+        assert!(!locked.value(0));
+        assert!(locked.value(1));
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::io::Write;
+
+/// A column of the input or output.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    /// The column header.
+    pub name: &'static str,
+
+    /// The type of the column's values.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+
+    /// Whether the column may be empty or left off.
+    pub optional: bool,
+
+    /// What the column holds.
+    pub description: &'static str
+}
+
+/// The columns of the input and output.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schema {
+    pub input:  &'static [Field],
+    pub output: &'static [Field]
+}
+
+/// The columns transactions are read from, in order.
+pub const INPUT: &[Field] = &[
+    Field {
+        name:        "type",
+        kind:        "string",
+        optional:    false,
+        description: "deposit, withdrawal, dispute, resolve, chargeback or transfer"
+    },
+    Field {
+        name:        "client",
+        kind:        "u16",
+        optional:    false,
+        description: "the client id"
+    },
+    Field {
+        name:        "tx",
+        kind:        "u32",
+        optional:    false,
+        description: "the transaction id, or the one a dispute refers to"
+    },
+    Field {
+        name:        "amount",
+        kind:        "decimal",
+        optional:    true,
+        description: "the amount, required for deposits, withdrawals and transfers"
+    },
+    Field {
+        name:        "counterparty",
+        kind:        "u16",
+        optional:    true,
+        description: "the client receiving a transfer"
+    },
+    Field {
+        name:        "seq",
+        kind:        "u64",
+        optional:    true,
+        description: "the intended order, for sorting with --sort-by-seq"
+    },
+    Field {
+        name:        "timestamp",
+        kind:        "datetime",
+        optional:    true,
+        description: "when it happened, in ISO-8601, for filtering with --window"
+    }
+];
+
+/// The columns client data is written with, in order.
+pub const OUTPUT: &[Field] = &[
+    Field {
+        name:        "client",
+        kind:        "u16",
+        optional:    false,
+        description: "the client id"
+    },
+    Field {
+        name:        "available",
+        kind:        "decimal",
+        optional:    false,
+        description: "the funds available to withdraw"
+    },
+    Field {
+        name:        "held",
+        kind:        "decimal",
+        optional:    false,
+        description: "the funds held by open disputes"
+    },
+    Field {
+        name:        "total",
+        kind:        "decimal",
+        optional:    false,
+        description: "available plus held"
+    },
+    Field {
+        name:        "locked",
+        kind:        "bool",
+        optional:    false,
+        description: "whether a chargeback locked the client"
+    }
+];
+
+/// The columns of the input and output.
+pub const SCHEMA: Schema = Schema {
+    input:  INPUT,
+    output: OUTPUT
+};
+
+/// Writes the schema, as json or as aligned text.
+pub fn write_schema<W: Write>(out: &mut W, json: bool) -> Result<()> {
+    if json {
+        serde_json::to_writer_pretty(&mut *out, &SCHEMA)?;
+        writeln!(out)?;
+
+        return Ok(());
+    }
+
+    for (title, fields) in [("input", INPUT), ("output", OUTPUT)] {
+        let header = fields
+            .iter()
+            .map(|f| f.name)
+            .collect::<Vec<_>>();
+
+        writeln!(out, "{title}: {}", header.join(","))?;
+
+        for field in fields {
+            let required = if field.optional {
+                "optional"
+            } else {
+                "required"
+            };
+
+            writeln!(
+                out,
+                "  {:<14}{:<9}{:<10}{}",
+                field.name, field.kind, required, field.description
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_schema_lists_input_columns() {
+        let mut out = Vec::new();
+
+        write_schema(&mut out, false).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let amount = text
+            .lines()
+            .find(|line| {
+                line.trim_start()
+                    .starts_with("amount")
+            })
+            .unwrap();
+
+        assert!(text.starts_with("input: type,client,tx,amount,counterparty,seq,timestamp\n"));
+        assert!(text.contains("output: client,available,held,total,locked\n"));
+        assert!(amount.contains("optional"));
+    }
+
+    #[test]
+    fn json_schema_marks_amount_optional() {
+        let mut out = Vec::new();
+
+        write_schema(&mut out, true).unwrap();
+
+        let schema: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let input = schema["input"].as_array().unwrap();
+        let names = input
+            .iter()
+            .map(|f| f["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names[..4], ["type", "client", "tx", "amount"]);
+        assert_eq!(input[3]["type"], "decimal");
+        assert_eq!(input[3]["optional"], true);
+        assert_eq!(input[0]["optional"], false);
+    }
+}
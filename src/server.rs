@@ -0,0 +1,50 @@
+use crate::{input, output, Diagnostics, InputOptions, Options, OutputOptions};
+use anyhow::Result;
+use std::{
+    io::{BufRead, BufReader, BufWriter, Write},
+    net::{Shutdown, TcpListener}
+};
+
+/// Accepts a single connection, processes the transactions it streams and
+/// writes the client data back once the peer closes its side of the
+/// connection.
+///
+/// Input starting with `{` is read as newline-delimited json, one
+/// transaction object per line, and answered the same way, one client
+/// object per line. Anything else is read and answered as csv.
+pub fn serve<W: Write>(
+    listener: &TcpListener,
+    options: &Options,
+    input_options: &InputOptions,
+    output_options: &OutputOptions,
+    diagnostics: &mut Diagnostics<W>
+) -> Result<()> {
+    let (stream, _) = listener.accept()?;
+    let mut input = BufReader::new(input::Limited::new(
+        stream.try_clone()?,
+        input_options.max_size
+    ));
+    let ndjson = input
+        .fill_buf()?
+        .trim_ascii_start()
+        .starts_with(b"{");
+
+    // Process the transactions until EOF, then write the client data back
+    // to the peer.
+    let mut out = BufWriter::new(&stream);
+
+    if ndjson {
+        let clients = input::process_ndjson(input, options, diagnostics)?;
+
+        output::write_ndjson(&mut out, &clients, output_options)?;
+    } else {
+        let clients = input::process_csv(input, options, input_options, diagnostics)?;
+
+        output::write_clients(&mut out, &clients, output_options)?;
+    }
+
+    drop(out);
+    stream.shutdown(Shutdown::Write)?;
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use crate::{ClientData, Engine, Options, Outcome, Transaction};
+use anyhow::{anyhow, Result};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// An engine shared between threads, say by the connections of a server.
+/// Clones are cheap and all refer to the same engine.
+///
+/// The whole engine sits behind a single lock, held for exactly one call:
+/// one transaction for `apply`, one client for `balance`. Transactions
+/// from different threads therefore interleave a transaction at a time,
+/// in whatever order they take the lock, and a balance read always sees
+/// every transaction applied before it and none of one still applying.
+/// Anything needing several calls to be atomic, like a batch, should go
+/// through `with`.
+#[derive(Default, Debug, Clone)]
+pub struct SharedEngine {
+    engine: Arc<Mutex<Engine>>
+}
+
+impl SharedEngine {
+    /// Creates a shared engine with no clients.
+    pub fn new(options: Options) -> Self {
+        Self::from(Engine::new(options))
+    }
+
+    /// Applies a transaction.
+    pub fn apply(&self, tx: Transaction) -> Result<Outcome> {
+        self.lock()?.apply(tx)
+    }
+
+    /// A copy of a client's current data, if the client exists.
+    pub fn balance(&self, client: u16) -> Result<Option<ClientData>> {
+        Ok(self.lock()?.balance(client))
+    }
+
+    /// Runs `f` on the engine while holding the lock, so nothing else
+    /// applies in between.
+    pub fn with<T>(&self, f: impl FnOnce(&mut Engine) -> T) -> Result<T> {
+        Ok(f(&mut *self.lock()?))
+    }
+
+    /// Locks the engine. A thread panicking mid-apply could have left it
+    /// half updated, so a poisoned lock is an error rather than recovered.
+    fn lock(&self) -> Result<MutexGuard<'_, Engine>> {
+        self.engine
+            .lock()
+            .map_err(|_| anyhow!("the shared engine is poisoned by a thread that panicked"))
+    }
+}
+
+impl From<Engine> for SharedEngine {
+    fn from(engine: Engine) -> Self {
+        Self {
+            engine: Arc::new(Mutex::new(engine))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionType;
+    use rust_decimal_macros::dec;
+    use std::thread;
+
+    #[test]
+    fn threads_share_one_engine() {
+        let engine = SharedEngine::new(Options::default());
+        let threads = (0..2)
+            .map(|thread| {
+                let engine = engine.clone();
+
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        engine
+                            .apply(Transaction {
+                                kind:         TransactionType::Deposit,
+                                client:       1,
+                                tx:           thread * 500 + i,
+                                amount:       Some(dec!(0.5)),
+                                counterparty: None,
+                                seq:          None,
+                                timestamp:    None
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let balance = engine.balance(1).unwrap().unwrap();
+
+        assert_eq!(balance.available, dec!(500));
+        assert_eq!(balance.total, dec!(500));
+        assert_eq!(engine.balance(2).unwrap(), None);
+        assert_eq!(
+            engine
+                .with(|engine| engine.metrics().deposits.count)
+                .unwrap(),
+            1000
+        );
+    }
+}
@@ -0,0 +1,733 @@
+use crate::{checks, clients_sorted, ClientData, Diagnostics, Engine, Options, Transaction};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Read, Write},
+    num::NonZeroUsize,
+    path::PathBuf,
+    rc::Rc
+};
+
+/// What a snapshot holds.
+#[derive(ValueEnum, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// Only the client data.
+    #[default]
+    Basic,
+
+    /// The client data along with the deposits, withdrawals and disputes,
+    /// so disputes can be resolved after loading it.
+    Extended
+}
+
+/// A client's data as it appears in a snapshot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Row {
+    client: u16,
+
+    #[serde(flatten)]
+    data: ClientData
+}
+
+/// The engine state kept by an extended snapshot, taken with
+/// `Engine::snapshot` and restored with `Engine::from_snapshot`.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct EngineSnapshot {
+    /// The client data.
+    clients: Vec<Row>,
+
+    /// The deposits that may be disputed, from least to most recently
+    /// used.
+    pub(crate) deposits: Vec<Transaction>,
+
+    /// The withdrawals, from least to most recently used.
+    pub(crate) withdrawals: Vec<Transaction>,
+
+    /// The transactions being disputed.
+    pub(crate) disputed: Vec<u32>,
+
+    /// How much of each partially held dispute couldn't be held.
+    pub(crate) shortfalls: Vec<(u32, Decimal)>,
+
+    /// How many times each transaction has been disputed, only kept if
+    /// the number of disputes is capped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) dispute_counts: Vec<(u32, u32)>,
+
+    /// The client and amount of every applied deposit, only kept for the
+    /// reject and idempotent duplicate policies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) applied: Vec<(u32, u16, Decimal)>
+}
+
+impl EngineSnapshot {
+    /// Creates the state from the client data.
+    pub(crate) fn new(clients: &HashMap<u16, ClientData>) -> Self {
+        Self {
+            clients: rows(clients),
+            ..Self::default()
+        }
+    }
+
+    /// The client data.
+    pub fn clients(&self) -> HashMap<u16, ClientData> {
+        self.clients
+            .iter()
+            .map(|row| (row.client, row.data.clone()))
+            .collect()
+    }
+}
+
+/// A snapshot written by checkpoints, along with how many input records
+/// had been read, after the header, so a resumed run can skip them.
+#[derive(Serialize, Deserialize)]
+struct Checkpoint<T> {
+    records: usize,
+
+    snapshot: T
+}
+
+/// A snapshot in either format, told apart by its shape.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Snapshot {
+    Basic(Vec<Row>),
+    Extended(EngineSnapshot),
+    Checkpoint(Checkpoint<Box<Snapshot>>)
+}
+
+/// The client data as snapshot rows, sorted by client id.
+fn rows(clients: &HashMap<u16, ClientData>) -> Vec<Row> {
+    clients_sorted(clients)
+        .into_iter()
+        .map(|(client, data)| Row {
+            client,
+            data: data.clone()
+        })
+        .collect()
+}
+
+/// Writes the client data as a json snapshot, sorted by client id.
+pub fn write_snapshot<W: Write>(out: W, clients: &HashMap<u16, ClientData>) -> Result<()> {
+    serde_json::to_writer(out, &rows(clients))?;
+
+    Ok(())
+}
+
+/// Writes the engine's state as a json snapshot in the given format.
+pub fn write_engine_snapshot<W: Write>(
+    out: W,
+    engine: &Engine,
+    format: SnapshotFormat
+) -> Result<()> {
+    match format {
+        SnapshotFormat::Basic => write_snapshot(out, &engine.normalized_clients()),
+        SnapshotFormat::Extended => {
+            serde_json::to_writer(out, &engine.snapshot())?;
+
+            Ok(())
+        }
+    }
+}
+
+/// Reads client data from a json snapshot in either format.
+pub fn read_snapshot<R: Read>(input: R) -> Result<HashMap<u16, ClientData>> {
+    Ok(clients(serde_json::from_reader(input)?))
+}
+
+/// The client data of a snapshot.
+fn clients(snapshot: Snapshot) -> HashMap<u16, ClientData> {
+    match snapshot {
+        Snapshot::Basic(rows) => rows
+            .into_iter()
+            .map(|row| (row.client, row.data))
+            .collect(),
+        Snapshot::Extended(state) => state.clients(),
+        Snapshot::Checkpoint(checkpoint) => clients(*checkpoint.snapshot)
+    }
+}
+
+/// Creates an engine from a json snapshot in either format. Only an
+/// extended snapshot restores the deposits and disputes, so with a basic
+/// one earlier deposits can't be disputed.
+pub fn read_engine_snapshot<R: Read>(input: R, options: Options) -> Result<Engine> {
+    let (engine, _) = resume(serde_json::from_reader(input)?, options)?;
+
+    Ok(engine)
+}
+
+/// Creates an engine from a snapshot, along with how many input records
+/// had been read when it was taken, which is zero unless a checkpoint
+/// wrote it.
+fn resume(snapshot: Snapshot, options: Options) -> Result<(Engine, usize)> {
+    Ok(match snapshot {
+        Snapshot::Basic(rows) => (
+            Engine::with_clients(
+                options,
+                rows.into_iter()
+                    .map(|row| (row.client, row.data))
+                    .collect()
+            ),
+            0
+        ),
+        Snapshot::Extended(state) => (Engine::from_snapshot(options, state)?, 0),
+        Snapshot::Checkpoint(checkpoint) => {
+            let (engine, _) = resume(*checkpoint.snapshot, options)?;
+
+            (engine, checkpoint.records)
+        }
+    })
+}
+
+/// Creates an engine from a json snapshot like `read_engine_snapshot`,
+/// along with how many input records had been read when a checkpoint
+/// wrote it, so the input can resume after them. Warns about any client
+/// whose `available` and `held` don't add up to `total`, since
+/// processing on top of them only compounds the drift.
+pub fn load_engine_snapshot<R: Read, W: Write>(
+    input: R,
+    options: Options,
+    diagnostics: &mut Diagnostics<W>
+) -> Result<(Engine, usize)> {
+    let (engine, records) = resume(serde_json::from_reader(input)?, options)?;
+    let unbalanced = checks::unbalanced(engine.clients());
+
+    if !unbalanced.is_empty() {
+        let ids = unbalanced
+            .iter()
+            .map(u16::to_string)
+            .collect::<Vec<_>>();
+
+        diagnostics.print(format_args!(
+            "snapshot clients {} have available + held != total",
+            ids.join(", ")
+        ));
+    }
+
+    Ok((engine, records))
+}
+
+/// Writes the engine's state as a json checkpoint in the given format,
+/// recording how many input records had been read.
+pub fn write_checkpoint<W: Write>(
+    out: W,
+    engine: &Engine,
+    format: SnapshotFormat,
+    records: usize
+) -> Result<()> {
+    match format {
+        SnapshotFormat::Basic => serde_json::to_writer(
+            out,
+            &Checkpoint {
+                records,
+                snapshot: rows(&engine.normalized_clients())
+            }
+        )?,
+        SnapshotFormat::Extended => serde_json::to_writer(
+            out,
+            &Checkpoint {
+                records,
+                snapshot: engine.snapshot()
+            }
+        )?
+    }
+
+    Ok(())
+}
+
+/// Writes a checkpoint to a file, replacing it atomically so a crash
+/// never leaves a partially written snapshot behind.
+pub fn write_checkpoint_file(
+    path: &PathBuf,
+    engine: &Engine,
+    format: SnapshotFormat,
+    records: usize
+) -> Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&temp)?);
+
+    write_checkpoint(&mut out, engine, format, records)?;
+    out.flush()?;
+    fs::rename(&temp, path).with_context(|| format!("failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Writes a snapshot of the engine, given how many input records had
+/// been read.
+type WriteSnapshot = dyn FnMut(&Engine, usize) -> Result<()>;
+
+/// Writes a snapshot every so many transactions, so a crash loses at most
+/// that many transactions of progress.
+pub struct Checkpoints {
+    /// How many transactions to process between snapshots.
+    every: NonZeroUsize,
+
+    /// How many transactions have been processed.
+    seen: usize,
+
+    /// How many input records have been read, after the header, including
+    /// any skipped, filtered out or failed ones.
+    records: Rc<Cell<usize>>,
+
+    /// Writes a snapshot.
+    write: Box<WriteSnapshot>
+}
+
+impl Checkpoints {
+    /// Creates checkpoints that call `write` every `every` transactions.
+    pub fn new<F>(every: NonZeroUsize, write: F) -> Self
+    where
+        F: FnMut(&Engine, usize) -> Result<()> + 'static
+    {
+        Self {
+            every,
+            seen: 0,
+            records: Rc::default(),
+            write: Box::new(write)
+        }
+    }
+
+    /// Creates checkpoints that write a snapshot file in the given format
+    /// every `every` transactions.
+    pub fn to_file(every: NonZeroUsize, path: PathBuf, format: SnapshotFormat) -> Self {
+        Self::new(every, move |engine, records| {
+            write_checkpoint_file(&path, engine, format, records)
+        })
+    }
+
+    /// Starts counting input records from `records`, those skipped before
+    /// the first counted one.
+    pub fn starting_at(self, records: usize) -> Self {
+        self.records.set(records);
+        self
+    }
+
+    /// Counts the input records read through `records`, so snapshots
+    /// record where to resume from. Wrap the input before it's filtered.
+    pub fn counting<I: Iterator>(&self, records: I) -> impl Iterator<Item = I::Item> + use<I> {
+        let count = self.records.clone();
+
+        records.inspect(move |_| count.set(count.get() + 1))
+    }
+
+    /// Records a processed transaction, writing a snapshot if one is due.
+    pub fn record(&mut self, engine: &Engine) -> Result<()> {
+        self.seen += 1;
+
+        if self
+            .seen
+            .is_multiple_of(self.every.get())
+        {
+            (self.write)(engine, self.records.get())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        engine, Diagnostics, DuplicatePolicy, Engine, Options, Outcome, Skip, Transaction,
+        TransactionType
+    };
+    use rust_decimal_macros::dec;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn snapshots_round_trip() {
+        let clients = HashMap::from([
+            (
+                1,
+                ClientData {
+                    available: dec!(1.5),
+                    held:      dec!(2.25),
+                    total:     dec!(3.75),
+                    locked:    false
+                }
+            ),
+            (
+                2,
+                ClientData {
+                    locked: true,
+                    ..ClientData::default()
+                }
+            )
+        ]);
+        let mut out = Vec::new();
+
+        write_snapshot(&mut out, &clients).unwrap();
+
+        assert_eq!(read_snapshot(out.as_slice()).unwrap(), clients);
+    }
+
+    #[test]
+    fn checkpoints_are_written_at_intervals() {
+        let txs = (1..=10)
+            .map(|tx| Transaction {
+                kind: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(dec!(1.0)),
+                counterparty: None,
+                seq: None,
+                timestamp: None
+            })
+            .map(Ok);
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let mut checkpoints = Checkpoints::new(NonZeroUsize::new(3).unwrap(), {
+            let written = written.clone();
+
+            move |engine: &Engine, records| {
+                written
+                    .borrow_mut()
+                    .push((engine.clients()[&1].total, records));
+                Ok(())
+            }
+        })
+        .starting_at(2);
+        let txs = checkpoints.counting(txs);
+        let mut engine = Engine::new(Options::default());
+
+        engine::run(
+            &mut engine,
+            txs,
+            &mut Diagnostics::silent(),
+            Some(&mut checkpoints)
+        )
+        .unwrap();
+
+        assert_eq!(
+            *written.borrow(),
+            vec![(dec!(3), 5), (dec!(6), 8), (dec!(9), 11)]
+        );
+    }
+
+    #[test]
+    fn loaded_snapshots_resume_processing() {
+        let prior = HashMap::from([(
+            1,
+            ClientData {
+                available: dec!(5.0),
+                total: dec!(5.0),
+                ..ClientData::default()
+            }
+        )]);
+        let mut engine = Engine::with_clients(Options::default(), prior);
+        let txs = [Ok(Transaction {
+            kind:         TransactionType::Withdrawal,
+            client:       1,
+            tx:           7,
+            amount:       Some(dec!(2.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        })];
+
+        engine::run(&mut engine, txs, &mut Diagnostics::silent(), None).unwrap();
+
+        assert_eq!(engine.clients()[&1].available, dec!(3.0));
+        assert_eq!(engine.clients()[&1].total, dec!(3.0));
+    }
+
+    #[test]
+    fn engine_snapshots_restore_mid_stream() {
+        let options = Options {
+            duplicate_policy: DuplicatePolicy::Idempotent,
+            max_dispute_cycles: Some(2),
+            ..Options::default()
+        };
+        let tx = |kind, client, tx, amount| Transaction {
+            kind,
+            client,
+            tx,
+            amount,
+            counterparty: None,
+            seq: None,
+            timestamp: None
+        };
+        let txs = [
+            tx(TransactionType::Deposit, 1, 1, Some(dec!(10.0))),
+            tx(TransactionType::Deposit, 2, 2, Some(dec!(4.0))),
+            tx(TransactionType::Withdrawal, 1, 3, Some(dec!(2.5))),
+            tx(TransactionType::Dispute, 1, 1, None),
+            tx(TransactionType::Resolve, 1, 1, None),
+            tx(TransactionType::Dispute, 2, 2, None),
+            // The rest run after the snapshot.
+            tx(TransactionType::Deposit, 1, 1, Some(dec!(10.0))),
+            tx(TransactionType::Dispute, 1, 1, None),
+            tx(TransactionType::Dispute, 1, 1, None),
+            tx(TransactionType::Chargeback, 2, 2, None),
+            tx(TransactionType::Deposit, 3, 4, Some(dec!(1.0)))
+        ];
+        let (before, after) = txs.split_at(6);
+        let mut engine = Engine::new(options.clone());
+
+        for tx in before {
+            engine.apply(tx.clone()).unwrap();
+        }
+
+        let snapshot = engine.snapshot();
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let mut restored =
+            Engine::from_snapshot(options, serde_json::from_str(&json).unwrap()).unwrap();
+
+        assert_eq!(restored.snapshot(), snapshot);
+
+        for tx in after {
+            assert_eq!(
+                restored.apply(tx.clone()).unwrap(),
+                engine.apply(tx.clone()).unwrap(),
+                "outcome of tx {}",
+                tx.tx
+            );
+        }
+
+        assert_eq!(restored.clients(), engine.clients());
+        assert_eq!(restored.snapshot(), engine.snapshot());
+        assert!(restored.clients()[&2].locked);
+    }
+
+    #[test]
+    fn extended_snapshots_keep_disputes_open() {
+        let before = [
+            Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            },
+            Transaction {
+                kind:         TransactionType::Dispute,
+                client:       1,
+                tx:           1,
+                amount:       None,
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            }
+        ];
+        let resolve = Transaction {
+            kind:         TransactionType::Resolve,
+            client:       1,
+            tx:           1,
+            amount:       None,
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        };
+        let mut engine = Engine::new(Options::default());
+
+        engine::run(
+            &mut engine,
+            before.map(Ok),
+            &mut Diagnostics::silent(),
+            None
+        )
+        .unwrap();
+
+        let mut basic = Vec::new();
+        let mut extended = Vec::new();
+
+        write_engine_snapshot(&mut basic, &engine, SnapshotFormat::Basic).unwrap();
+        write_engine_snapshot(&mut extended, &engine, SnapshotFormat::Extended).unwrap();
+
+        // Both formats hold the same client data.
+        assert_eq!(read_snapshot(basic.as_slice()).unwrap(), *engine.clients());
+        assert_eq!(
+            read_snapshot(extended.as_slice()).unwrap(),
+            *engine.clients()
+        );
+
+        // Only the extended format remembers the dispute.
+        let mut loaded = read_engine_snapshot(extended.as_slice(), Options::default()).unwrap();
+
+        assert_eq!(
+            loaded
+                .apply(resolve.clone())
+                .unwrap(),
+            Outcome::Applied
+        );
+        assert_eq!(loaded.clients()[&1].available, dec!(10.0));
+        assert_eq!(loaded.clients()[&1].held, dec!(0.0));
+
+        let mut loaded = read_engine_snapshot(basic.as_slice(), Options::default()).unwrap();
+
+        assert_eq!(
+            loaded.apply(resolve).unwrap(),
+            Outcome::Skipped(Skip::UnknownTransaction)
+        );
+        assert_eq!(loaded.clients()[&1].held, dec!(10.0));
+    }
+
+    #[test]
+    fn unbalanced_snapshots_are_warned_about() {
+        let snapshot = r#"[
+            {"client":1,"available":"5.0","held":"1.0","total":"6.0","locked":false},
+            {"client":2,"available":"5.0","held":"1.0","total":"4.0","locked":false},
+            {"client":3,"available":"0.0","held":"2.0","total":"0.0","locked":true}
+        ]"#;
+        let mut diagnostics = Diagnostics::new(Vec::new(), false, false);
+        let (mut engine, _) =
+            load_engine_snapshot(snapshot.as_bytes(), Options::default(), &mut diagnostics)
+                .unwrap();
+
+        // Resolving a dispute from before the snapshot is called out too.
+        let resolve = Transaction {
+            kind:         TransactionType::Resolve,
+            client:       1,
+            tx:           7,
+            amount:       None,
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        };
+
+        engine::run(&mut engine, [Ok(resolve)], &mut diagnostics, None).unwrap();
+
+        assert_eq!(
+            String::from_utf8(diagnostics.into_inner()).unwrap(),
+            "snapshot clients 2, 3 have available + held != total\n\
+             resolve of transaction 7 for client 1 refers to a transaction unknown since the \
+             snapshot was loaded\n"
+        );
+
+        let mut diagnostics = Diagnostics::new(Vec::new(), false, false);
+
+        load_engine_snapshot(
+            r#"[{"client":1,"available":"5.0","held":"1.0","total":"6.0","locked":false}]"#
+                .as_bytes(),
+            Options::default(),
+            &mut diagnostics
+        )
+        .unwrap();
+
+        assert!(diagnostics.into_inner().is_empty());
+    }
+
+    #[test]
+    fn checkpoints_record_the_records_read() {
+        let mut engine = Engine::new(Options::default());
+
+        engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+
+        for format in [SnapshotFormat::Basic, SnapshotFormat::Extended] {
+            let mut out = Vec::new();
+
+            write_checkpoint(&mut out, &engine, format, 7).unwrap();
+
+            let (loaded, records) = load_engine_snapshot(
+                out.as_slice(),
+                Options::default(),
+                &mut Diagnostics::silent()
+            )
+            .unwrap();
+
+            assert_eq!(records, 7);
+            assert_eq!(loaded.clients(), engine.clients());
+            assert_eq!(read_snapshot(out.as_slice()).unwrap(), *engine.clients());
+        }
+
+        // Snapshots written otherwise resume from the start.
+        let mut out = Vec::new();
+
+        write_engine_snapshot(&mut out, &engine, SnapshotFormat::Extended).unwrap();
+
+        let (_, records) = load_engine_snapshot(
+            out.as_slice(),
+            Options::default(),
+            &mut Diagnostics::silent()
+        )
+        .unwrap();
+
+        assert_eq!(records, 0);
+    }
+
+    #[test]
+    fn deposits_without_amounts_are_rejected_on_load() {
+        let mut engine = Engine::new(Options::default());
+
+        engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.0)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+
+        let mut out = Vec::new();
+
+        write_engine_snapshot(&mut out, &engine, SnapshotFormat::Extended).unwrap();
+
+        let mut snapshot: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        snapshot["deposits"][0]["amount"] = serde_json::Value::Null;
+
+        let malformed = serde_json::to_vec(&snapshot).unwrap();
+        let result = read_engine_snapshot(malformed.as_slice(), Options::default());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn snapshots_are_normalized() {
+        let mut engine = Engine::new(Options {
+            normalize_scale: Some(4),
+            ..Options::default()
+        });
+
+        engine
+            .apply(Transaction {
+                kind:         TransactionType::Deposit,
+                client:       1,
+                tx:           1,
+                amount:       Some(dec!(10.00000000)),
+                counterparty: None,
+                seq:          None,
+                timestamp:    None
+            })
+            .unwrap();
+
+        let mut out = Vec::new();
+
+        write_engine_snapshot(&mut out, &engine, SnapshotFormat::Basic).unwrap();
+
+        let snapshot = String::from_utf8(out).unwrap();
+
+        assert!(snapshot.contains("\"total\":\"10.0000\""));
+        assert!(!snapshot.contains("10.00000000"));
+
+        // Extended snapshots keep the exact balances to restore from.
+        let mut out = Vec::new();
+
+        write_engine_snapshot(&mut out, &engine, SnapshotFormat::Extended).unwrap();
+
+        let restored = read_engine_snapshot(out.as_slice(), Options::default()).unwrap();
+
+        assert_eq!(restored.clients()[&1].total.scale(), 8);
+    }
+}
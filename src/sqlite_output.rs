@@ -0,0 +1,156 @@
+use crate::{
+    output::{self, Column, OutputOptions},
+    ClientData
+};
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::{collections::HashMap, path::Path};
+
+/// Writes the client data to a `clients` table in a sqlite database,
+/// creating the table if it doesn't exist. Amounts are stored as text at
+/// their output precision so no precision is lost. Clients already in the
+/// table are updated, so re-running over the same database refreshes it,
+/// though clients absent from this run are left as they were.
+pub fn write_sqlite(
+    path: &Path,
+    clients: &HashMap<u16, ClientData>,
+    options: &OutputOptions
+) -> Result<()> {
+    let mut connection = Connection::open(path)?;
+    let transaction = connection.transaction()?;
+
+    transaction.execute(
+        "CREATE TABLE IF NOT EXISTS clients (
+            client    INTEGER PRIMARY KEY,
+            available TEXT NOT NULL,
+            held      TEXT NOT NULL,
+            total     TEXT NOT NULL,
+            locked    INTEGER NOT NULL
+        )",
+        []
+    )?;
+
+    // Write every row in one transaction, which is far faster.
+    {
+        let mut upsert = transaction.prepare(
+            "INSERT INTO clients (client, available, held, total, locked)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (client) DO UPDATE SET
+                available = excluded.available,
+                held      = excluded.held,
+                total     = excluded.total,
+                locked    = excluded.locked"
+        )?;
+
+        for (id, client) in output::reported(clients, options) {
+            let (available, held, total) = output::columns(client, options)?;
+
+            upsert.execute(params![
+                id,
+                options.format(available, Column::Available),
+                options.format(held, Column::Held),
+                options.format(total, Column::Total),
+                client.locked
+            ])?;
+        }
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::{env, fs};
+
+    /// Reads back every row of the `clients` table.
+    fn rows(path: &Path) -> Vec<(u16, String, String, String, bool)> {
+        let connection = Connection::open(path).unwrap();
+        let mut select = connection
+            .prepare("SELECT client, available, held, total, locked FROM clients ORDER BY client")
+            .unwrap();
+
+        select
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn sqlite_rows_are_upserted() {
+        let path = env::temp_dir().join(format!("transactions-{}.db", std::process::id()));
+        let mut clients = HashMap::from([
+            (
+                2,
+                ClientData {
+                    available: dec!(1.5),
+                    held:      dec!(2.25),
+                    total:     dec!(3.75),
+                    locked:    true
+                }
+            ),
+            (
+                1,
+                ClientData {
+                    available: dec!(10.12345),
+                    total: dec!(10.12345),
+                    ..ClientData::default()
+                }
+            )
+        ]);
+
+        let _ = fs::remove_file(&path);
+
+        write_sqlite(&path, &clients, &OutputOptions::default()).unwrap();
+
+        assert_eq!(
+            rows(&path),
+            vec![
+                (
+                    1,
+                    "10.1234".to_string(),
+                    "0.0000".to_string(),
+                    "10.1234".to_string(),
+                    false
+                ),
+                (
+                    2,
+                    "1.5000".to_string(),
+                    "2.2500".to_string(),
+                    "3.7500".to_string(),
+                    true
+                ),
+            ]
+        );
+
+        // Re-running updates the rows in place.
+        clients.insert(
+            1,
+            ClientData {
+                available: dec!(4.0),
+                total: dec!(4.0),
+                ..ClientData::default()
+            }
+        );
+        write_sqlite(&path, &clients, &OutputOptions::default()).unwrap();
+
+        let rows = rows(&path);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].1, "4.0000");
+        assert_eq!(rows[1].1, "1.5000");
+    }
+}
@@ -0,0 +1,120 @@
+//! Helpers for testing code built on the engine. Enable the `testing`
+//! feature to use them outside of this crate.
+
+use crate::{output::format_amount, process_slice, Options, Transaction, PRECISION};
+use rust_decimal::Decimal;
+
+/// Asserts that processing `txs` with the default options gives the same
+/// client data every time.
+pub fn assert_deterministic(txs: &[Transaction]) {
+    assert_deterministic_with(txs, &Options::default());
+}
+
+/// Asserts that processing `txs` with `options` gives the same client
+/// data every time. Client data is compared by id, so the order clients
+/// happen to be stored in doesn't matter.
+pub fn assert_deterministic_with(txs: &[Transaction], options: &Options) {
+    let run = || process_slice(txs, options).expect("processing should succeed");
+
+    assert_eq!(run(), run(), "processing is not deterministic");
+}
+
+/// Asserts two amounts are numerically equal regardless of scale, so
+/// `10.0` equals `10.0000`. Use this for balances, where only the value
+/// matters.
+#[track_caller]
+pub fn assert_amount_eq(actual: Decimal, expected: Decimal) {
+    assert!(actual == expected, "amounts differ: {actual} != {expected}");
+}
+
+/// Asserts an amount formats as `expected` at `PRECISION` places, the way
+/// it's output. Use this when checking output, where `10.0` and `10.0000`
+/// must both come out as `10.0000`.
+#[track_caller]
+pub fn assert_amount_formatted(actual: Decimal, expected: &str) {
+    assert_eq!(
+        format_amount(actual, PRECISION),
+        expected,
+        "amount {actual} formats differently"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionType;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn processing_is_deterministic() {
+        let txs = (1..=100)
+            .flat_map(|i| {
+                [
+                    Transaction {
+                        kind:         TransactionType::Deposit,
+                        client:       (i % 7) as u16,
+                        tx:           i,
+                        amount:       Some(dec!(1.5)),
+                        counterparty: None,
+                        seq:          None,
+                        timestamp:    None
+                    },
+                    Transaction {
+                        kind:         TransactionType::Dispute,
+                        client:       (i % 7) as u16,
+                        tx:           i / 2,
+                        amount:       None,
+                        counterparty: None,
+                        seq:          None,
+                        timestamp:    None
+                    },
+                    Transaction {
+                        kind:         TransactionType::Chargeback,
+                        client:       (i % 7) as u16,
+                        tx:           i / 3,
+                        amount:       None,
+                        counterparty: None,
+                        seq:          None,
+                        timestamp:    None
+                    }
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        assert_deterministic(&txs);
+        assert_deterministic_with(
+            &txs,
+            &Options {
+                allow_overdraft: true,
+                ..Options::default()
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "processing should succeed")]
+    fn processing_errors_fail_the_assertion() {
+        assert_deterministic(&[Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       None,
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        }]);
+    }
+
+    #[test]
+    fn amounts_compare_by_value() {
+        assert_amount_eq(dec!(10.0), dec!(10.0000));
+        assert_amount_formatted(dec!(10.0), "10.0000");
+        assert_amount_formatted(dec!(10.0000), "10.0000");
+    }
+
+    #[test]
+    #[should_panic(expected = "amounts differ")]
+    fn different_amounts_are_not_equal() {
+        assert_amount_eq(dec!(10.0), dec!(10.0001));
+    }
+}
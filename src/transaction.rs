@@ -0,0 +1,284 @@
+use crate::error::ErrorKind;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The number of decimal places amounts are expected to have.
+pub const PRECISION: u32 = 4;
+
+/// How over-precise amounts are brought down to `PRECISION` places.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoundMode {
+    /// Drop the extra digits.
+    Truncate,
+
+    /// Round to nearest, with midpoints away from zero.
+    RoundHalfUp,
+
+    /// Round to nearest, with midpoints towards the even neighbor.
+    RoundHalfEven
+}
+
+impl From<RoundMode> for RoundingStrategy {
+    fn from(mode: RoundMode) -> Self {
+        match mode {
+            RoundMode::Truncate => RoundingStrategy::ToZero,
+            RoundMode::RoundHalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundMode::RoundHalfEven => RoundingStrategy::MidpointNearestEven
+        }
+    }
+}
+
+/// The transaction type.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    Transfer
+}
+
+impl fmt::Display for TransactionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Deposit => "deposit",
+            Self::Withdrawal => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Transfer => "transfer"
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// A transaction.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Transaction {
+    /// The transaction type.
+    #[serde(rename = "type")]
+    pub kind: TransactionType,
+
+    /// The client id.
+    pub client: u16,
+
+    /// The transaction id.
+    pub tx: u32,
+
+    /// The amount.
+    pub amount: Option<Decimal>,
+
+    /// The client receiving a transfer.
+    #[serde(default)]
+    pub counterparty: Option<u16>,
+
+    /// Where the transaction belongs in the intended order, for sources
+    /// that emit transactions out of order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+
+    /// When the transaction happened, as an ISO-8601 timestamp, for
+    /// processing a window of time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<DateTime<Utc>>
+}
+
+impl Transaction {
+    /// Makes sure transactions are well-formed.
+    pub fn verify(&self) -> Result<()> {
+        if matches!(
+            self.kind,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+        ) {
+            self.required_amount()?;
+        }
+
+        if self.kind == TransactionType::Transfer && self.required_counterparty()? == self.client {
+            bail!(ErrorKind::InvalidTransfer.error(format!(
+                "transfer {} has the same client and counterparty",
+                self.tx
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Makes sure a deposit, withdrawal or transfer has a strictly positive
+    /// amount, since its type gives the direction funds move in, so a
+    /// negative or zero amount is likely a sign convention mistake. Other
+    /// transactions always pass.
+    pub fn verify_sign(&self) -> Result<()> {
+        if !matches!(
+            self.kind,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Transfer
+        ) {
+            return Ok(());
+        }
+
+        let amount = self.required_amount()?;
+
+        if amount <= Decimal::ZERO {
+            bail!(ErrorKind::NonPositiveAmount.error(format!(
+                "{} {} has amount {amount}, but it must be positive",
+                self.kind, self.tx
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// The amount, failing if there isn't one.
+    pub fn required_amount(&self) -> Result<Decimal> {
+        self.amount.ok_or_else(|| {
+            ErrorKind::MissingAmount
+                .error(format!("transaction {} has no amount", self.tx))
+                .into()
+        })
+    }
+
+    /// The counterparty, failing if there isn't one.
+    pub fn required_counterparty(&self) -> Result<u16> {
+        self.counterparty.ok_or_else(|| {
+            ErrorKind::InvalidTransfer
+                .error(format!("transfer {} has no counterparty", self.tx))
+                .into()
+        })
+    }
+
+    /// Rounds the amount to `PRECISION` places if it's more precise.
+    pub fn round(&mut self, mode: RoundMode) {
+        if let Some(amount) = &mut self.amount
+            && amount.scale() > PRECISION
+        {
+            *amount = amount.round_dp_with_strategy(PRECISION, mode.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn rounded(amount: Decimal, mode: RoundMode) -> Decimal {
+        let mut tx = Transaction {
+            kind:         TransactionType::Deposit,
+            client:       1,
+            tx:           1,
+            amount:       Some(amount),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        };
+
+        tx.round(mode);
+        tx.amount.unwrap()
+    }
+
+    #[test]
+    fn round_truncates() {
+        assert_eq!(rounded(dec!(1.23455), RoundMode::Truncate), dec!(1.2345));
+        assert_eq!(rounded(dec!(1.23459), RoundMode::Truncate), dec!(1.2345));
+    }
+
+    #[test]
+    fn round_half_up() {
+        assert_eq!(rounded(dec!(1.23455), RoundMode::RoundHalfUp), dec!(1.2346));
+        assert_eq!(rounded(dec!(1.23445), RoundMode::RoundHalfUp), dec!(1.2345));
+    }
+
+    #[test]
+    fn round_half_even() {
+        assert_eq!(
+            rounded(dec!(1.23455), RoundMode::RoundHalfEven),
+            dec!(1.2346)
+        );
+        assert_eq!(
+            rounded(dec!(1.23445), RoundMode::RoundHalfEven),
+            dec!(1.2344)
+        );
+    }
+
+    #[test]
+    fn round_leaves_precise_amounts_alone() {
+        assert_eq!(rounded(dec!(1.2345), RoundMode::Truncate).scale(), 4);
+        assert_eq!(rounded(dec!(1.5), RoundMode::Truncate).scale(), 1);
+    }
+
+    #[test]
+    fn transfer_requires_a_distinct_counterparty() {
+        let mut tx = Transaction {
+            kind:         TransactionType::Transfer,
+            client:       1,
+            tx:           1,
+            amount:       Some(dec!(1.0)),
+            counterparty: None,
+            seq:          None,
+            timestamp:    None
+        };
+        assert!(tx.verify().is_err());
+
+        tx.counterparty = Some(1);
+        assert!(tx.verify().is_err());
+
+        tx.counterparty = Some(2);
+        assert!(tx.verify().is_ok());
+    }
+
+    #[test]
+    fn signs_must_be_positive() {
+        let tx = |kind, amount| Transaction {
+            kind,
+            client: 1,
+            tx: 7,
+            amount: Some(amount),
+            counterparty: Some(2),
+            seq: None,
+            timestamp: None
+        };
+        let error = |kind, amount| {
+            tx(kind, amount)
+                .verify_sign()
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error(TransactionType::Deposit, dec!(-5.0)),
+            "deposit 7 has amount -5.0, but it must be positive"
+        );
+        assert_eq!(
+            error(TransactionType::Deposit, dec!(0)),
+            "deposit 7 has amount 0, but it must be positive"
+        );
+        assert_eq!(
+            error(TransactionType::Withdrawal, dec!(-1.5)),
+            "withdrawal 7 has amount -1.5, but it must be positive"
+        );
+        assert_eq!(
+            error(TransactionType::Withdrawal, dec!(0.0)),
+            "withdrawal 7 has amount 0.0, but it must be positive"
+        );
+        assert_eq!(
+            error(TransactionType::Transfer, dec!(-2)),
+            "transfer 7 has amount -2, but it must be positive"
+        );
+
+        assert!(tx(TransactionType::Deposit, dec!(0.0001))
+            .verify_sign()
+            .is_ok());
+        assert!(tx(TransactionType::Withdrawal, dec!(3))
+            .verify_sign()
+            .is_ok());
+        assert!(tx(TransactionType::Dispute, dec!(-3))
+            .verify_sign()
+            .is_ok());
+    }
+}
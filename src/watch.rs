@@ -0,0 +1,135 @@
+use crate::{
+    engine,
+    input::{self, StreamParser},
+    output, Diagnostics, Engine, InputOptions, Options, OutputOptions
+};
+use anyhow::{bail, Result};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    thread,
+    time::{Duration, Instant}
+};
+
+/// Buffers bytes read from a growing file, handing out only complete
+/// lines so a line still being written is never parsed.
+#[derive(Default, Debug)]
+pub struct LineBuffer {
+    /// The bytes read that don't end in a newline yet.
+    pending: Vec<u8>
+}
+
+impl LineBuffer {
+    /// Adds newly read bytes.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.pending
+            .extend_from_slice(bytes);
+    }
+
+    /// Takes every complete line, including their newlines, leaving any
+    /// partial line buffered.
+    pub fn take_lines(&mut self) -> Vec<u8> {
+        match self
+            .pending
+            .iter()
+            .rposition(|&b| b == b'\n')
+        {
+            Some(end) => {
+                let rest = self.pending.split_off(end + 1);
+
+                std::mem::replace(&mut self.pending, rest)
+            },
+            None => Vec::new()
+        }
+    }
+}
+
+/// Tails a growing csv file, applying transactions as they're appended
+/// and writing the full client data to `out` every `interval`. Never
+/// returns unless something fails.
+pub fn watch<W: Write, D: Write>(
+    path: &Path,
+    interval: Duration,
+    options: &Options,
+    input_options: &InputOptions,
+    output_options: &OutputOptions,
+    out: &mut W,
+    diagnostics: &mut Diagnostics<D>
+) -> Result<()> {
+    let mut file = File::open(path)?;
+    let mut engine = Engine::new(options.clone());
+    let mut buffer = LineBuffer::default();
+    let mut parser = StreamParser::new(input_options);
+    let mut offset = 0;
+    let mut printed = Instant::now();
+    let poll = interval.min(Duration::from_millis(250));
+
+    loop {
+        // Read whatever was appended since we last looked, unless the
+        // file has grown too large.
+        input::check_size(&file, input_options)?;
+
+        let len = file.metadata()?.len();
+
+        if len < offset {
+            bail!("{} was truncated while being watched", path.display());
+        }
+
+        if len > offset {
+            let mut bytes = Vec::new();
+
+            file.seek(SeekFrom::Start(offset))?;
+            offset += Read::by_ref(&mut file)
+                .take(len - offset)
+                .read_to_end(&mut bytes)? as u64;
+            buffer.push(&bytes);
+        }
+
+        // Apply the complete lines as the next part of one csv stream.
+        let lines = buffer.take_lines();
+
+        if !lines.is_empty() {
+            engine::run(
+                &mut engine,
+                parser
+                    .parse(&lines)?
+                    .into_iter()
+                    .map(|tx| tx.map(|(_, tx)| tx)),
+                diagnostics,
+                None
+            )?;
+        }
+
+        // Reprint the client data when it's due.
+        if printed.elapsed() >= interval {
+            output::write_output(out, &engine.normalized_clients(), output_options)?;
+            printed = Instant::now();
+        }
+
+        thread::sleep(poll);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_lines_wait_for_their_newline() {
+        let mut buffer = LineBuffer::default();
+
+        buffer.push(b"type,client,tx,amount\ndeposit,1,1,");
+        assert_eq!(buffer.take_lines(), b"type,client,tx,amount\n");
+
+        buffer.push(b"1.0");
+        assert!(buffer.take_lines().is_empty());
+
+        buffer.push(b"\ndeposit,1,2,2.0\nwith");
+        assert_eq!(buffer.take_lines(), b"deposit,1,1,1.0\ndeposit,1,2,2.0\n");
+
+        buffer.push(b"drawal,1,3,1.0\n");
+        assert_eq!(buffer.take_lines(), b"withdrawal,1,3,1.0\n");
+        assert!(buffer.take_lines().is_empty());
+    }
+}
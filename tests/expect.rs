@@ -0,0 +1,55 @@
+use std::{env, fs, path::PathBuf, process::Command};
+
+/// Runs the binary, returning whether it succeeded and what it wrote to
+/// stdout.
+fn run(args: &[&str]) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_transactions"))
+        .args(args)
+        .output()
+        .unwrap();
+
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap()
+    )
+}
+
+/// Writes a file to the temporary directory, returning its path.
+fn temp(name: &str, contents: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("transactions-expect-{}-{name}", std::process::id()));
+
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn output_is_expected_back() {
+    let input = temp(
+        "input.csv",
+        "type,client,tx,amount\n\
+         deposit,1,1,1.23456\n\
+         deposit,2,2,5.0\n\
+         deposit,2,3,2.0\n\
+         dispute,2,3,\n\
+         chargeback,2,3,\n\
+         deposit,3,4,3.0\n\
+         dispute,3,4,\n"
+    );
+    let input = input.to_str().unwrap();
+
+    for flags in [
+        &[][..],
+        &["--locked-only"],
+        &["--output-held-as-negative"],
+        &["--output-held-as-negative", "held", "--trim-zeros"]
+    ] {
+        let (ok, output) = run(&[&[input][..], flags].concat());
+
+        assert!(ok, "{flags:?}");
+
+        let expected = temp("expected.csv", &output);
+        let (ok, _) = run(&[&[input, "--expect", expected.to_str().unwrap()][..], flags].concat());
+
+        assert!(ok, "{flags:?} rejected its own output:\n{output}");
+    }
+}
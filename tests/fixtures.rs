@@ -0,0 +1,169 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::{collections::HashMap, fs::File, path::Path};
+use transactions::{
+    input,
+    output::{self, ColumnPrecision, Quoting, Terminator},
+    ClientData, Diagnostics, InputOptions, Options, OutputOptions
+};
+
+/// Processes a fixture from `tests/fixtures`.
+fn process_fixture(name: &str) -> anyhow::Result<HashMap<u16, ClientData>> {
+    process_fixture_with(name, &InputOptions::default())
+}
+
+/// Processes a fixture from `tests/fixtures` with the given input
+/// options.
+fn process_fixture_with(
+    name: &str,
+    input_options: &InputOptions
+) -> anyhow::Result<HashMap<u16, ClientData>> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+
+    input::process_csv(
+        File::open(path)?,
+        &Options::default(),
+        input_options,
+        &mut Diagnostics::silent()
+    )
+}
+
+/// Asserts a client's balances.
+fn assert_client(
+    clients: &HashMap<u16, ClientData>,
+    id: u16,
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool
+) {
+    let client = clients.get(&id).unwrap();
+
+    assert_eq!(client.available, available, "client {id} available");
+    assert_eq!(client.held, held, "client {id} held");
+    assert_eq!(client.total, total, "client {id} total");
+    assert_eq!(client.locked, locked, "client {id} locked");
+}
+
+#[test]
+fn basic_fixture() {
+    let clients = process_fixture("basic.csv").unwrap();
+
+    assert_eq!(clients.len(), 2);
+    assert_client(&clients, 1, dec!(11.0), dec!(0.0), dec!(11.0), false);
+    assert_client(&clients, 2, dec!(15.0), dec!(0.0), dec!(15.0), false);
+}
+
+#[test]
+fn whitespace_is_trimmed() {
+    let clients = process_fixture("whitespace.csv").unwrap();
+
+    assert_client(&clients, 1, dec!(1.0), dec!(0.0), dec!(1.0), false);
+    assert_client(&clients, 2, dec!(2.25), dec!(0.0), dec!(2.25), false);
+}
+
+#[test]
+fn missing_amount_columns_are_allowed_for_disputes() {
+    let clients = process_fixture("missing_columns.csv").unwrap();
+
+    assert_client(&clients, 1, dec!(3.0), dec!(2.0), dec!(5.0), false);
+}
+
+#[test]
+fn missing_amount_on_withdrawal_is_an_error() {
+    let err = process_fixture("missing_amount.csv").unwrap_err();
+
+    assert!(err
+        .to_string()
+        .contains("transaction 2 has no amount"));
+}
+
+#[test]
+fn mixed_fixture() {
+    let clients = process_fixture("mixed.csv").unwrap();
+
+    assert_eq!(clients.len(), 3);
+    assert_client(&clients, 1, dec!(70.0), dec!(0.0), dec!(70.0), false);
+    assert_client(&clients, 2, dec!(0.0), dec!(0.0), dec!(0.0), true);
+    assert_client(&clients, 3, dec!(0.0), dec!(0.0), dec!(0.0), false);
+}
+
+#[test]
+fn transfer_fixture() {
+    let clients = process_fixture("transfer.csv").unwrap();
+
+    assert_client(&clients, 1, dec!(7.0), dec!(0.0), dec!(7.0), false);
+    assert_client(&clients, 2, dec!(3.0), dec!(0.0), dec!(3.0), false);
+}
+
+#[test]
+fn latin1_input_is_transcoded() {
+    // The ignored `note` column has bytes that aren't valid UTF-8.
+    assert!(process_fixture("latin1.csv").is_err());
+
+    let options = InputOptions {
+        encoding: Some(input::encoding("latin1").unwrap()),
+        ..InputOptions::default()
+    };
+    let clients = process_fixture_with("latin1.csv", &options).unwrap();
+
+    assert_eq!(clients.len(), 2);
+    assert_client(&clients, 1, dec!(6.0), dec!(0.0), dec!(6.0), false);
+    assert_client(&clients, 2, dec!(2.5), dec!(0.0), dec!(2.5), false);
+}
+
+#[test]
+fn oversized_inputs_are_rejected() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/basic.csv");
+    let limit = |max_size| InputOptions {
+        max_size: Some(max_size),
+        ..InputOptions::default()
+    };
+
+    // Files are checked up front.
+    let file = File::open(&path).unwrap();
+
+    assert!(input::check_size(&file, &limit(183)).is_ok());
+    assert!(input::check_size(&file, &limit(182)).is_err());
+
+    // Streams are checked as they're read.
+    assert!(process_fixture_with("basic.csv", &limit(183)).is_ok());
+
+    let err = process_fixture_with("basic.csv", &limit(100)).unwrap_err();
+
+    assert!(err
+        .to_string()
+        .contains("larger than the maximum of 100 bytes"));
+}
+
+#[test]
+fn canonical_output_matches_golden_file() {
+    let clients = process_fixture("mixed.csv").unwrap();
+    let golden = std::fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mixed.canonical.csv")
+    )
+    .unwrap();
+
+    // Formatting options are ignored once canonicalized.
+    let options = OutputOptions {
+        pretty: true,
+        column_precision: vec!["available=1"
+            .parse::<ColumnPrecision>()
+            .unwrap()],
+        terminator: Terminator::Crlf,
+        quoting: Quoting::Always,
+        ..OutputOptions::default()
+    }
+    .canonical();
+    let render = || {
+        let mut out = Vec::new();
+
+        output::write_output(&mut out, &clients, &options).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    assert_eq!(render(), golden);
+    assert_eq!(render(), render());
+}
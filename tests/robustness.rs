@@ -0,0 +1,253 @@
+//! Feeds generated and mangled input through processing and output,
+//! making sure nothing panics. Errors are fine. `fuzz/` holds a
+//! coverage-guided target for longer runs.
+
+use std::collections::HashMap;
+use transactions::{
+    input,
+    output::{write_json, write_output, HeldAsNegative},
+    parallel::merge,
+    transaction::RoundMode,
+    ClientData, Diagnostics, Disputable, DisputeMode, DuplicatePolicy, InputOptions, LockedBlocks,
+    Options, OutputOptions
+};
+
+/// A small xorshift generator, so runs are reproducible without a
+/// dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+const TYPES: &[&str] = &[
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "transfer"
+];
+
+const IDS: &[&str] = &["1", "2", "3", "0", "65535"];
+
+const AMOUNTS: &[&str] = &[
+    "1.0",
+    "0.0001",
+    "0.00005",
+    "0",
+    "-5.0",
+    "100",
+    "7922816251426433759354395033.5",
+    "-7922816251426433759354395033.5"
+];
+
+const MALFORMED: &[&str] = &[
+    "DEPOSIT",
+    "bogus",
+    "",
+    "65536",
+    "-1",
+    "4294967296",
+    "x",
+    "1e5",
+    "$3.00",
+    "79228162514264337593543950335"
+];
+
+/// Picks a field, occasionally a malformed one.
+fn field<'a>(rng: &mut Rng, valid: &[&'a str]) -> &'a str {
+    if rng.below(200) == 0 {
+        rng.pick(MALFORMED)
+    } else {
+        rng.pick(valid)
+    }
+}
+
+/// Generates a csv of plausible and occasionally malformed transactions.
+fn structured(rng: &mut Rng) -> Vec<u8> {
+    let mut csv = String::from("type,client,tx,amount,counterparty\n");
+
+    for _ in 0..rng.below(40) {
+        let kind = field(rng, TYPES);
+        let client = field(rng, IDS);
+        let tx = field(rng, IDS);
+        let row = match kind {
+            "deposit" | "withdrawal" => format!("{kind},{client},{tx},{}", field(rng, AMOUNTS)),
+            "transfer" => {
+                let counterparty = field(rng, IDS);
+
+                if counterparty == client {
+                    continue;
+                }
+
+                format!(
+                    "{kind},{client},{tx},{},{counterparty}",
+                    field(rng, AMOUNTS)
+                )
+            },
+            _ => format!("{kind},{client},{tx}")
+        };
+
+        csv.push_str(&row);
+        csv.push('\n');
+    }
+
+    csv.into_bytes()
+}
+
+/// Mutates bytes at random.
+fn mutated(rng: &mut Rng, mut bytes: Vec<u8>) -> Vec<u8> {
+    for _ in 0..rng.below(8) {
+        let index = rng.below(bytes.len() + 1);
+        let byte = rng.next() as u8;
+
+        match rng.below(3) {
+            0 => bytes.insert(index, byte),
+            1 if index < bytes.len() => bytes[index] = byte,
+            _ if index < bytes.len() => {
+                bytes.remove(index);
+            },
+            _ => {}
+        }
+    }
+
+    bytes
+}
+
+/// Option combinations that take different paths through the engine.
+fn option_sets() -> Vec<Options> {
+    vec![
+        Options::default(),
+        Options {
+            allow_overdraft: true,
+            disputable: Disputable::Both,
+            dispute_mode: DisputeMode::Proportional,
+            duplicate_policy: DuplicatePolicy::First,
+            ..Options::default()
+        },
+        Options {
+            round_mode: Some(RoundMode::RoundHalfEven),
+            skip_zero_amounts: true,
+            locked_blocks: LockedBlocks::WithdrawalsOnly,
+            max_deposits: Some(2),
+            coerce_missing_amount: true,
+            check_conservation: true,
+            ..Options::default()
+        },
+        Options {
+            strict: true,
+            strict_references: true,
+            duplicate_policy: DuplicatePolicy::Idempotent,
+            max_dispute_cycles: Some(1),
+            max_open_disputes: Some(2),
+            saturating: true,
+            ..Options::default()
+        },
+    ]
+}
+
+/// Output option combinations, including those that sum or negate.
+fn output_sets() -> Vec<OutputOptions> {
+    vec![
+        OutputOptions::default(),
+        OutputOptions {
+            totals_row: true,
+            held_as_negative: Some(HeldAsNegative::Available),
+            ..OutputOptions::default()
+        },
+        OutputOptions {
+            pretty: true,
+            held_as_negative: Some(HeldAsNegative::Held),
+            ..OutputOptions::default()
+        },
+    ]
+}
+
+/// Writes the clients every way they can be output, and merges them
+/// into themselves.
+fn render(clients: &HashMap<u16, ClientData>) {
+    for options in output_sets() {
+        let _ = write_output(&mut Vec::new(), clients, &options);
+        let _ = write_json(&mut Vec::new(), clients, &options);
+    }
+
+    let _ = merge(&mut clients.clone(), clients.clone());
+}
+
+#[test]
+fn arbitrary_input_never_panics() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let options = option_sets();
+    let input_options = [
+        InputOptions::default(),
+        InputOptions {
+            normalize_case: true,
+            strict_schema: true,
+            strip_currency_symbol: true,
+            ..InputOptions::default()
+        }
+    ];
+
+    for _ in 0..2000 {
+        let csv = structured(&mut rng);
+        let csv = if rng.below(4) == 0 {
+            mutated(&mut rng, csv)
+        } else {
+            csv
+        };
+
+        for options in &options {
+            for input_options in &input_options {
+                // Errors are fine, only panics fail.
+                let clients = input::process_csv(
+                    csv.as_slice(),
+                    options,
+                    input_options,
+                    &mut Diagnostics::silent()
+                );
+
+                if let Ok(clients) = clients {
+                    render(&clients);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn huge_amounts_never_panic() {
+    let mut csv = String::from("type,client,tx,amount\n");
+
+    for client in 1..=12 {
+        csv.push_str(&format!(
+            "deposit,{client},{client},7922816251426433759354395033.5\n"
+        ));
+    }
+
+    for options in option_sets() {
+        let clients = input::process_csv(
+            csv.as_bytes(),
+            &options,
+            &InputOptions::default(),
+            &mut Diagnostics::silent()
+        );
+
+        if let Ok(clients) = clients {
+            render(&clients);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    thread
+};
+use transactions::{server, Diagnostics, InputOptions, Options, OutputOptions};
+
+/// Streams a request to a server accepting a single connection and
+/// returns its response.
+fn exchange(request: &[u8]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        server::serve(
+            &listener,
+            &Options::default(),
+            &InputOptions::default(),
+            &OutputOptions::default(),
+            &mut Diagnostics::silent()
+        )
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+
+    stream.write_all(request).unwrap();
+    stream
+        .shutdown(Shutdown::Write)
+        .unwrap();
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .unwrap();
+
+    server.join().unwrap().unwrap();
+
+    response
+}
+
+#[test]
+fn serve_writes_balances_back_at_eof() {
+    assert_eq!(
+        exchange(b"type,client,tx,amount\ndeposit,1,1,10.0\nwithdrawal,1,2,4.0\n"),
+        "client,available,held,total,locked\n1,6.0000,0.0000,6.0000,false\n"
+    );
+}
+
+#[test]
+fn serve_answers_ndjson_with_ndjson() {
+    let request = br#"{"type":"deposit","client":2,"tx":1,"amount":"2.5"}
+{"type":"deposit","client":1,"tx":2,"amount":10.0}
+
+{"type":"withdrawal","client":1,"tx":3,"amount":"4.0"}
+"#;
+
+    assert_eq!(
+        exchange(request),
+        "{\"client\":1,\"available\":\"6.0000\",\"held\":\"0.0000\",\"total\":\"6.0000\",\"locked\":false}\n\
+         {\"client\":2,\"available\":\"2.5000\",\"held\":\"0.0000\",\"total\":\"2.5000\",\"locked\":false}\n"
+    );
+}